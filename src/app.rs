@@ -1,11 +1,19 @@
-use bdk_wallet::bitcoin::Network;
+use bdk_wallet::bitcoin::{bip32::Fingerprint, Network};
 use flume::{Receiver, Sender};
 use sidepanel::sidepanel;
 
+use crate::chain;
 use crate::messages::{self, CreatedWallet};
+use crate::registry;
 use crate::wallet::WalletBackground;
 
+mod addresses;
+mod backup;
+mod export;
 mod home;
+mod labels;
+mod policy;
+mod qr;
 mod receive;
 pub mod send;
 pub mod settings;
@@ -29,8 +37,26 @@ pub struct WalletApp {
     pub send: send::SendState,
     /// State for Receive page
     pub receive: receive::ReceiveState,
+    /// State for Addresses page
+    pub addresses: addresses::AddressesState,
     /// State data for settings page
     pub settings: settings::Settings,
+    /// Scan settings for the loaded wallet
+    pub wallet_settings: settings::WalletSettings,
+    /// State for Export page
+    pub export: export::ExportState,
+    /// State for Policy page
+    pub policy: policy::PolicyState,
+    /// Labels for the loaded wallet
+    pub labels: labels::LabelState,
+    /// Backup dialog for the loaded wallet
+    pub backup: backup::BackupState,
+    /// Electrum server list editing and test results
+    pub servers: settings::ServerListState,
+    /// Chain source state shown in the top bar
+    pub connection: messages::ConnectionStatus,
+    /// Pinned server certificates that changed, waiting for the user to decide
+    pub cert_changes: Vec<chain::CertChange>,
     /// Channel for requests to the wallet thread
     pub wallet_req: Sender<messages::WalletRequest>,
     /// Channel for updates from the wallet thread
@@ -44,12 +70,18 @@ pub struct WalletApp {
 #[derive(Debug)]
 pub struct WalletInfo {
     pub name: String,
+    /// Master fingerprint of the seed the wallet was derived from
+    pub seed: Option<Fingerprint>,
+    /// Whether the seed backup was checked, from the wallet registry
+    pub backup_verified: Option<bool>,
 }
 
 impl WalletInfo {
     fn from_wallet() -> Self {
         Self {
             name: "test".into(),
+            seed: None,
+            backup_verified: None,
         }
     }
 }
@@ -60,17 +92,34 @@ pub enum Page {
     Home,
     Send,
     Receive,
+    Addresses,
     Transactions,
+    Export,
+    Policy,
     Settings,
 }
 
 impl WalletApp {
-    pub fn new_bg(&self, wallet: CreatedWallet) {
+    pub fn new_bg(&mut self, wallet: CreatedWallet) {
+        self.network = wallet.wallet.network();
+        self.wallet_info.name = wallet.name.clone();
+        let entry = registry::load(&self.settings.wallet_db)
+            .ok()
+            .and_then(|wallets| wallets.into_iter().find(|w| w.name == wallet.name));
+        self.wallet_info.seed = entry.as_ref().and_then(|e| e.seed);
+        self.wallet_info.backup_verified = entry.and_then(|e| e.backup_verified);
         let recv = self.for_bg_req.clone();
         let send = self.for_bg_upd.clone();
         let settings = self.settings.clone();
         std::thread::spawn(move || {
-            let mut bg = WalletBackground::new(wallet.wallet, wallet.name, recv, send, settings);
+            let mut bg = WalletBackground::new(
+                wallet.wallet,
+                wallet.name,
+                wallet.db_file,
+                recv,
+                send,
+                settings,
+            );
             bg.monitor_wallet();
         });
     }
@@ -90,7 +139,7 @@ impl WalletApp {
         let settings = settings::Settings::new();
 
         WalletApp {
-            network: Network::Testnet,
+            network: settings.network,
             page: Page::SplashScreen,
             debug: Vec::new(),
             wallet_info: WalletInfo::from_wallet(),
@@ -98,6 +147,15 @@ impl WalletApp {
             home: home::HomeState::new(),
             send: send::SendState::new(),
             receive: receive::ReceiveState::new(),
+            addresses: addresses::AddressesState::new(),
+            wallet_settings: settings::WalletSettings::default(),
+            export: export::ExportState::new(),
+            policy: policy::PolicyState::default(),
+            labels: labels::LabelState::new(),
+            backup: backup::BackupState::default(),
+            servers: settings::ServerListState::new(),
+            connection: messages::ConnectionStatus::default(),
+            cert_changes: Vec::new(),
             wallet_req: req.0,
             wallet_updates: resp.1,
             for_bg_req: req.1,
@@ -129,9 +187,39 @@ impl eframe::App for WalletApp {
                 }
                 messages::WalletResponse::Sync(b) => self.home.balance = Some(b),
                 messages::WalletResponse::UtxoList(utxos) => self.send.selected_utxos = utxos,
+                messages::WalletResponse::TxHistory(txs) => self.home.transactions = txs,
+                messages::WalletResponse::PaymentRequests(reqs) => self.receive.requests = reqs,
+                messages::WalletResponse::Labels(labels) => self.labels.set_all(labels),
+                messages::WalletResponse::AddressPage(rows, more) => {
+                    self.addresses.rows = rows;
+                    self.addresses.more = more;
+                }
+                messages::WalletResponse::AddressSearch(res) => {
+                    self.addresses.search_result = Some(res)
+                }
                 messages::WalletResponse::RecvAddresses(addrs) => self.receive.next_addr = addrs,
+                messages::WalletResponse::MarkedAddresses(addrs) => self.receive.marked = addrs,
                 messages::WalletResponse::WalletReady => self.page = Page::Home,
+                messages::WalletResponse::WalletConfig(c) => self.wallet_settings = c,
                 messages::WalletResponse::NewPsbt(psbt) => self.send.psbt = Some(psbt),
+                messages::WalletResponse::Drafts(drafts) => self.send.drafts = drafts,
+                messages::WalletResponse::FeeEstimate(rate) => {
+                    self.send.fee_rate = rate.to_sat_per_kwu() as f32 / 250.
+                }
+                messages::WalletResponse::Connection(status) => self.connection = status,
+                messages::WalletResponse::ServerTest(url, res) => {
+                    self.servers.tests.insert(url, res);
+                }
+                messages::WalletResponse::Export(data) => self.export.data = Some(data),
+                messages::WalletResponse::Policies(trees) => {
+                    self.send.set_policy(&trees);
+                    self.policy.trees = trees;
+                }
+                messages::WalletResponse::CertificateChanged(change) => {
+                    if !self.cert_changes.contains(&change) {
+                        self.cert_changes.push(change);
+                    }
+                }
             }
         }
 
@@ -151,6 +239,10 @@ impl eframe::App for WalletApp {
                             self.page = Page::SplashScreen;
                             self.splash = splash::SplashState::new(&self.settings.wallet_db);
                         }
+                        if self.page != Page::SplashScreen && ui.button("Backup Wallet").clicked() {
+                            self.backup.start(&self.wallet_info.name);
+                            ui.close_menu();
+                        }
                         if ui.button("Settings").clicked() {
                             self.page = Page::Settings;
                         }
@@ -159,9 +251,32 @@ impl eframe::App for WalletApp {
                 }
 
                 egui::widgets::global_dark_light_mode_buttons(ui);
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let (color, text) = match &self.connection {
+                        messages::ConnectionStatus::Unknown => {
+                            (ui.visuals().weak_text_color(), "Not connected".to_string())
+                        }
+                        messages::ConnectionStatus::Connected(server) => {
+                            (egui::Color32::GREEN, format!("Connected: {server}"))
+                        }
+                        messages::ConnectionStatus::Offline(e) => {
+                            (ui.visuals().error_fg_color, format!("Offline: {e}"))
+                        }
+                    };
+                    ui.colored_label(color, text);
+                });
             });
         });
 
+        if self.backup.open {
+            backup::window(self, ctx);
+        }
+
+        if let Some(change) = self.cert_changes.first().cloned() {
+            cert_changed_window(self, ctx, &change);
+        }
+
         if self.page != Page::SplashScreen && self.page != Page::Settings {
             egui::SidePanel::left("side").show(ctx, |ui| sidepanel(self, ui));
         }
@@ -172,7 +287,10 @@ impl eframe::App for WalletApp {
                 Page::Home => home::page(self, ui),
                 Page::Send => send::page(self, ui),
                 Page::Receive => receive::page(self, ui),
+                Page::Addresses => addresses::page(self, ui),
                 Page::Transactions => transactions::page(self, ui),
+                Page::Export => export::page(self, ui),
+                Page::Policy => policy::page(self, ui),
                 Page::Settings => settings::page(self, ui),
             };
 
@@ -185,3 +303,35 @@ impl eframe::App for WalletApp {
         });
     }
 }
+
+/// Warn that a pinned certificate changed and let the user trust the new one
+fn cert_changed_window(app: &mut WalletApp, ctx: &egui::Context, change: &chain::CertChange) {
+    egui::Window::new("Server certificate changed")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(ctx, |ui| {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!(
+                    "{} presented a different certificate than the one pinned for it. \
+                    This is expected if the server renewed its certificate, \
+                    otherwise someone may be intercepting the connection.",
+                    change.url
+                ),
+            );
+            ui.label(format!("Pinned: {}", change.pinned));
+            ui.label(format!("Presented: {}", change.presented));
+            ui.horizontal(|ui| {
+                if ui.button("Trust new certificate").clicked() {
+                    if let Err(e) = chain::trust_pin(&change.url, &change.presented) {
+                        app.debug.push(e);
+                    }
+                    app.cert_changes.remove(0);
+                }
+                if ui.button("Keep old certificate").clicked() {
+                    app.cert_changes.remove(0);
+                }
+            });
+        });
+}
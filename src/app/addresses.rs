@@ -0,0 +1,148 @@
+use bdk_wallet::KeychainKind;
+
+use crate::{
+    bip329::LabelType,
+    messages::{AddressDetails, WalletRequest},
+    WalletApp,
+};
+
+pub struct AddressesState {
+    keychain: KeychainKind,
+    page: u32,
+    /// Ask the wallet for the current page on the next frame
+    pub refresh: bool,
+    pub rows: Vec<AddressDetails>,
+    /// The wallet has a page after this one
+    pub more: bool,
+    search: String,
+    pub search_result: Option<Result<Option<AddressDetails>, String>>,
+}
+
+impl AddressesState {
+    pub fn new() -> Self {
+        AddressesState {
+            keychain: KeychainKind::External,
+            page: 0,
+            refresh: true,
+            rows: Vec::new(),
+            more: false,
+            search: "".into(),
+            search_result: None,
+        }
+    }
+}
+
+fn request_page(app_state: &mut WalletApp) {
+    app_state
+        .wallet_req
+        .send(WalletRequest::AddressPage(
+            app_state.addresses.keychain,
+            app_state.addresses.page,
+        ))
+        .unwrap();
+}
+
+pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    ui.heading("Addresses");
+
+    ui.horizontal(|ui| {
+        ui.label("Search: ");
+        ui.text_edit_singleline(&mut app_state.addresses.search);
+        if ui.button("Find").clicked() {
+            app_state
+                .wallet_req
+                .send(WalletRequest::SearchAddress(
+                    app_state.addresses.search.clone(),
+                ))
+                .unwrap();
+        }
+    });
+    match &app_state.addresses.search_result {
+        Some(Ok(Some(details))) => {
+            ui.label(format!(
+                "Belongs to this wallet: {:?} keychain, index {}, path {}",
+                details.keychain, details.index, details.path
+            ));
+        }
+        Some(Ok(None)) => {
+            ui.label("Address does not belong to this wallet");
+        }
+        Some(Err(e)) => {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+        None => {}
+    }
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        for (kc, name) in [
+            (KeychainKind::External, "Receive"),
+            (KeychainKind::Internal, "Change"),
+        ] {
+            if ui
+                .selectable_label(app_state.addresses.keychain == kc, name)
+                .clicked()
+            {
+                app_state.addresses.keychain = kc;
+                app_state.addresses.page = 0;
+                app_state.addresses.refresh = true;
+            }
+        }
+        ui.add_space(16.);
+        if ui
+            .add_enabled(app_state.addresses.page > 0, egui::Button::new("Previous"))
+            .clicked()
+        {
+            app_state.addresses.page -= 1;
+            app_state.addresses.refresh = true;
+        }
+        ui.label(format!("Page {}", app_state.addresses.page + 1));
+        if ui
+            .add_enabled(app_state.addresses.more, egui::Button::new("Next"))
+            .clicked()
+        {
+            app_state.addresses.page += 1;
+            app_state.addresses.refresh = true;
+        }
+        if ui.button("Refresh").clicked() {
+            app_state.addresses.refresh = true;
+        }
+    });
+    if app_state.addresses.refresh {
+        app_state.addresses.refresh = false;
+        request_page(app_state);
+    }
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        egui::Grid::new("addresses").striped(true).show(ui, |ui| {
+            for header in [
+                "Index", "Path", "Address", "Status", "Received", "Balance", "Label",
+            ] {
+                ui.strong(header);
+            }
+            ui.end_row();
+
+            for row in app_state.addresses.rows.iter() {
+                ui.label(row.index.to_string());
+                ui.label(&row.path);
+                ui.label(row.address.to_string());
+                ui.label(match (row.used, row.revealed) {
+                    (true, _) => "used",
+                    (false, true) => "unused",
+                    (false, false) => "not revealed",
+                });
+                ui.label(format!("{}x", row.times_received));
+                ui.label(format!("{} sats", row.balance.to_sat()));
+                ui.horizontal(|ui| {
+                    app_state.labels.edit(
+                        ui,
+                        &app_state.wallet_req,
+                        LabelType::Addr,
+                        row.address.to_string(),
+                    );
+                });
+                ui.end_row();
+            }
+        });
+    });
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use crate::messages::WalletRequest;
+use crate::WalletApp;
+
+/// Backup dialog opened from the File menu
+#[derive(Debug, Default)]
+pub struct BackupState {
+    pub open: bool,
+    pub file: String,
+    pub passphrase: String,
+    pub confirm: String,
+}
+
+impl BackupState {
+    /// Open the dialog with a backup file named after `wallet` in the home folder
+    pub fn start(&mut self, wallet: &str) {
+        let mut file = dirs::home_dir().unwrap_or_default();
+        file.push(format!("{wallet}.seashell-backup"));
+        *self = BackupState {
+            open: true,
+            file: file.display().to_string(),
+            ..Default::default()
+        };
+    }
+}
+
+pub fn window(app: &mut WalletApp, ctx: &egui::Context) {
+    let mut open = app.backup.open;
+    let mut done = false;
+    egui::Window::new("Back up wallet")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label(
+                "The backup holds the wallet database, private keys if saved, labels and \
+                settings, encrypted with the passphrase below.",
+            );
+            let state = &mut app.backup;
+            egui::Grid::new("backup").show(ui, |ui| {
+                ui.label("File: ");
+                ui.text_edit_singleline(&mut state.file);
+                ui.end_row();
+                ui.label("Passphrase: ");
+                ui.add(egui::TextEdit::singleline(&mut state.passphrase).password(true));
+                ui.end_row();
+                ui.label("Confirm passphrase: ");
+                ui.add(egui::TextEdit::singleline(&mut state.confirm).password(true));
+                ui.end_row();
+            });
+            let ready = !state.file.is_empty()
+                && !state.passphrase.is_empty()
+                && state.passphrase == state.confirm;
+            if !state.confirm.is_empty() && state.passphrase != state.confirm {
+                ui.colored_label(ui.visuals().error_fg_color, "Passphrases don't match");
+            }
+            if ui
+                .add_enabled(ready, egui::Button::new("Create backup"))
+                .clicked()
+            {
+                let req = WalletRequest::Backup(
+                    PathBuf::from(&state.file),
+                    std::mem::take(&mut state.passphrase),
+                );
+                app.wallet_req.send(req).unwrap();
+                done = true;
+            }
+        });
+    if done {
+        app.backup = BackupState::default();
+    } else {
+        app.backup.open = open;
+    }
+}
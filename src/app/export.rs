@@ -0,0 +1,131 @@
+use crate::{bip329::LabelType, export::WalletExport, messages::WalletRequest, WalletApp};
+
+use super::qr;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum View {
+    External,
+    Internal,
+    Xpub,
+    Core,
+    Specter,
+    Bsms,
+}
+
+pub struct ExportState {
+    pub data: Option<WalletExport>,
+    view: View,
+    /// Where "Save to file" writes the current view
+    file: String,
+    /// Outcome of the last save
+    saved: Option<Result<String, String>>,
+}
+
+impl ExportState {
+    pub fn new() -> Self {
+        ExportState {
+            data: None,
+            view: View::External,
+            file: String::new(),
+            saved: None,
+        }
+    }
+}
+
+/// Ask the wallet for fresh export data
+pub fn request(app_state: &mut WalletApp) {
+    app_state.export.data = None;
+    app_state.export.saved = None;
+    app_state.wallet_req.send(WalletRequest::Export).unwrap();
+}
+
+pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    ui.heading("Export public descriptors");
+    let state = &mut app_state.export;
+    let Some(data) = &state.data else {
+        ui.label("Loading...");
+        return;
+    };
+
+    egui::Grid::new("export_keys").show(ui, |ui| {
+        for (fp, key) in &data.keys {
+            ui.label("Master fingerprint: ");
+            ui.monospace(fp.to_string());
+            ui.end_row();
+            ui.label("Account key: ");
+            ui.monospace(key);
+            ui.end_row();
+            // BIP329 refers to the bare xpub, without the key origin
+            let xpub = key.rsplit(']').next().unwrap_or(key);
+            ui.label("Label: ");
+            ui.horizontal(|ui| {
+                app_state
+                    .labels
+                    .edit(ui, &app_state.wallet_req, LabelType::Xpub, xpub.to_string())
+            });
+            ui.end_row();
+        }
+    });
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut state.view, View::External, "External descriptor");
+        ui.selectable_value(&mut state.view, View::Internal, "Change descriptor");
+        ui.selectable_value(&mut state.view, View::Xpub, "Account xpub");
+        ui.selectable_value(&mut state.view, View::Core, "Bitcoin Core");
+        ui.selectable_value(&mut state.view, View::Specter, "Sparrow / Specter");
+        if data.bsms().is_some() {
+            ui.selectable_value(&mut state.view, View::Bsms, "BSMS");
+        }
+    });
+    let (text, ext) = match state.view {
+        View::External => (data.external.clone(), "txt"),
+        View::Internal => (data.internal.clone(), "txt"),
+        View::Xpub => (
+            data.keys
+                .iter()
+                .map(|(_, key)| key.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "txt",
+        ),
+        View::Core => (data.core_import(), "json"),
+        View::Specter => (data.specter_file(), "json"),
+        View::Bsms => (data.bsms().unwrap_or_default(), "bsms"),
+    };
+
+    ui.add(
+        egui::TextEdit::multiline(&mut text.as_str())
+            .code_editor()
+            .desired_width(f32::INFINITY),
+    );
+    ui.horizontal(|ui| {
+        if ui.button("Copy").clicked() {
+            ui.output_mut(|o| o.copied_text = text.clone());
+        }
+        ui.label("File: ");
+        if state.file.is_empty() {
+            state.file = format!("{}.{ext}", data.name);
+        }
+        ui.text_edit_singleline(&mut state.file);
+        if ui.button("Save to file").clicked() {
+            state.saved = Some(
+                std::fs::write(&state.file, &text)
+                    .map(|_| format!("Saved to {}", state.file))
+                    .map_err(|e| e.to_string()),
+            );
+        }
+    });
+    match &state.saved {
+        Some(Ok(msg)) => {
+            ui.label(msg);
+        }
+        Some(Err(e)) => {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+        None => {}
+    }
+    if matches!(state.view, View::External | View::Internal | View::Xpub) {
+        qr::show(ui, &text);
+    }
+}
@@ -1,11 +1,20 @@
-use crate::{messages::WalletRequest, WalletApp};
-use bdk_wallet::bitcoin::Transaction;
-use bdk_wallet::Balance;
+use crate::{
+    bdk_utils,
+    bip329::LabelType,
+    messages::{TxDetails, WalletRequest},
+    registry, WalletApp,
+};
+use bdk_wallet::{bip39::Mnemonic, Balance};
 
 #[derive(Debug, Clone)]
 pub struct HomeState {
     pub balance: Option<Balance>,
-    transactions: Vec<Transaction>,
+    pub transactions: Vec<TxDetails>,
+    /// Stop gap for a manual full rescan
+    rescan_gap: usize,
+    /// Seed phrase typed to verify the backup, while the check is open
+    verify: Option<String>,
+    verify_error: Option<String>,
 }
 
 impl HomeState {
@@ -13,12 +22,18 @@ impl HomeState {
         HomeState {
             balance: None,
             transactions: Vec::new(),
+            rescan_gap: 200,
+            verify: None,
+            verify_error: None,
         }
     }
 }
 
 pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Home");
+    if app_state.wallet_info.backup_verified == Some(false) {
+        backup_nag(app_state, ui);
+    }
 
     if ui.button("Sync").clicked() {
         app_state
@@ -27,10 +42,74 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
             .expect("bg failed");
     }
 
+    ui.horizontal(|ui| {
+        ui.label("Stop gap:");
+        ui.add(egui::DragValue::new(&mut app_state.home.rescan_gap).clamp_range(1..=100_000));
+        if ui.button("Rescan with larger gap").clicked() {
+            app_state
+                .wallet_req
+                .send(WalletRequest::Rescan(app_state.home.rescan_gap))
+                .expect("bg failed");
+        }
+    });
+
     ui.label(format!("{:?}", app_state.home.balance));
 
     ui.heading("Transaction History");
-    app_state.home.transactions.iter().for_each(|t| {
-        ui.label(format!("{:?}", t));
+    for t in app_state.home.transactions.iter() {
+        let status = match t.height {
+            Some(h) => format!("confirmed at {h}"),
+            None => "pending".into(),
+        };
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{}: +{} / -{} sats ({status})",
+                t.txid,
+                t.received.to_sat(),
+                t.sent.to_sat()
+            ));
+            app_state
+                .labels
+                .edit(ui, &app_state.wallet_req, LabelType::Tx, t.txid.to_string());
+        });
+    }
+}
+
+/// Warn about a seed backup that was never checked and let the user check it by typing it
+fn backup_nag(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            "The seed backup for this wallet hasn't been verified. \
+            A mistake in the written copy would make the funds unrecoverable from it.",
+        );
+        let Some(words) = &mut app_state.home.verify else {
+            if ui.button("Verify backup").clicked() {
+                app_state.home.verify = Some(String::new());
+            }
+            return;
+        };
+        ui.label("Type the seed phrase from your backup:");
+        ui.text_edit_multiline(words);
+        if let Some(e) = &app_state.home.verify_error {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+        if !ui.button("Check").clicked() {
+            return;
+        }
+        let matches = Mnemonic::parse(words.trim())
+            .map_err(|e| e.to_string())
+            .map(|mne| Some(bdk_utils::seed_fingerprint(&mne)) == app_state.wallet_info.seed);
+        app_state.home.verify_error = match matches {
+            Ok(true) => {
+                app_state.home.verify = None;
+                app_state.wallet_info.backup_verified = Some(true);
+                app_state.wallet_info.seed.and_then(|seed| {
+                    registry::set_backup_verified(&app_state.settings.wallet_db, seed).err()
+                })
+            }
+            Ok(false) => Some("This phrase is not the seed of this wallet".into()),
+            Err(e) => Some(format!("Invalid seed: {e}")),
+        };
     });
 }
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use flume::Sender;
+
+use crate::{
+    bip329::{Label, LabelType},
+    messages::WalletRequest,
+};
+
+/// Labels for the loaded wallet plus the one currently being edited
+pub struct LabelState {
+    labels: HashMap<(LabelType, String), Label>,
+    editing: Option<(LabelType, String)>,
+    entry: String,
+    /// Path used for BIP329 import and export
+    pub file: String,
+}
+
+impl LabelState {
+    pub fn new() -> Self {
+        let file = dirs::download_dir()
+            .map(|mut dl| {
+                dl.push("labels.jsonl");
+                dl.to_string_lossy().to_string()
+            })
+            .unwrap_or_default();
+        LabelState {
+            labels: HashMap::new(),
+            editing: None,
+            entry: "".into(),
+            file,
+        }
+    }
+
+    pub fn set_all(&mut self, labels: Vec<Label>) {
+        self.labels = labels
+            .into_iter()
+            .map(|l| ((l.kind, l.reference.clone()), l))
+            .collect();
+    }
+
+    pub fn get(&self, kind: LabelType, reference: &str) -> Option<&str> {
+        self.labels
+            .get(&(kind, reference.to_string()))
+            .map(|l| l.label.as_str())
+            .filter(|l| !l.is_empty())
+    }
+
+    /// Show the label for `reference` with a button to edit it in place
+    pub fn edit(
+        &mut self,
+        ui: &mut egui::Ui,
+        wallet_req: &Sender<WalletRequest>,
+        kind: LabelType,
+        reference: String,
+    ) {
+        let key = (kind, reference);
+        if self.editing.as_ref() == Some(&key) {
+            ui.text_edit_singleline(&mut self.entry);
+            if ui.button("Save").clicked() {
+                let mut label = self
+                    .labels
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| Label::new(key.0, key.1.clone(), "".into()));
+                label.label = self.entry.trim().to_string();
+                wallet_req.send(WalletRequest::SetLabel(label)).unwrap();
+                self.editing = None;
+            }
+            if ui.button("Cancel").clicked() {
+                self.editing = None;
+            }
+        } else {
+            if let Some(label) = self.get(key.0, &key.1) {
+                ui.label(format!("[{label}]"));
+            }
+            if ui.small_button("Label").clicked() {
+                self.entry = self.get(key.0, &key.1).unwrap_or_default().to_string();
+                self.editing = Some(key);
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use bdk_wallet::KeychainKind;
+
+use crate::{
+    policy::{Contribution, PolicyNode},
+    WalletApp,
+};
+
+#[derive(Debug, Default)]
+pub struct PolicyState {
+    /// Spending policy of each keychain, sent by the wallet when it loads
+    pub trees: Vec<(KeychainKind, PolicyNode)>,
+}
+
+pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    ui.heading("Spending policy");
+    if app_state.policy.trees.is_empty() {
+        ui.label("This wallet has no spending policy to show.");
+        return;
+    }
+    ui.label("Branches marked \"can sign\" are satisfied by the keys this wallet holds.");
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for (kc, tree) in &app_state.policy.trees {
+            let title = match kc {
+                KeychainKind::External => "Receive addresses",
+                KeychainKind::Internal => "Change addresses",
+            };
+            egui::CollapsingHeader::new(title)
+                .default_open(*kc == KeychainKind::External)
+                .show(ui, |ui| node(ui, tree));
+        }
+    });
+}
+
+fn node(ui: &mut egui::Ui, tree: &PolicyNode) {
+    ui.horizontal(|ui| {
+        ui.strong(&tree.description);
+        match (tree.contribution, &tree.timelock) {
+            // bdk counts timelocks as satisfied, they only wait on the chain
+            (_, Some(timelock)) => {
+                ui.colored_label(ui.visuals().warn_fg_color, timelock);
+            }
+            (Contribution::Complete, None) => {
+                ui.colored_label(egui::Color32::DARK_GREEN, "can sign");
+            }
+            (Contribution::Partial { have, need }, None) => {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!("we cover {have} of {need}"),
+                );
+            }
+            (Contribution::None, None) => {
+                ui.weak("needs other signers");
+            }
+        }
+    });
+    ui.indent(ui.next_auto_id(), |ui| {
+        for (key, ours) in &tree.keys {
+            ui.horizontal(|ui| {
+                ui.label("Key: ");
+                ui.monospace(key);
+                if *ours {
+                    ui.label("(ours)");
+                }
+            });
+        }
+        for child in &tree.children {
+            node(ui, child);
+        }
+    });
+}
@@ -0,0 +1,33 @@
+use qrcode::{Color, QrCode};
+
+/// Size of a single QR module in points
+const MODULE: f32 = 4.;
+/// Light border around the code, in modules
+const QUIET_ZONE: usize = 4;
+
+/// Draw `data` as a QR code
+pub fn show(ui: &mut egui::Ui, data: &str) {
+    let code = match QrCode::new(data.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            ui.label(format!("Unable to create QR code: {e}"));
+            return;
+        }
+    };
+    let width = code.width();
+    let side = (width + 2 * QUIET_ZONE) as f32 * MODULE;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(side, side), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0., egui::Color32::WHITE);
+
+    let colors = code.to_colors();
+    for (i, color) in colors.iter().enumerate() {
+        if *color == Color::Dark {
+            let x = (i % width + QUIET_ZONE) as f32 * MODULE;
+            let y = (i / width + QUIET_ZONE) as f32 * MODULE;
+            let module =
+                egui::Rect::from_min_size(rect.min + egui::vec2(x, y), egui::vec2(MODULE, MODULE));
+            painter.rect_filled(module, 0., egui::Color32::BLACK);
+        }
+    }
+}
@@ -1,11 +1,27 @@
-use crate::WalletApp;
-use bdk_wallet::AddressInfo;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    bip21::Bip21Uri,
+    bip329::LabelType,
+    messages::{MarkedAddress, PaymentRequest, PaymentStatus, WalletRequest},
+    WalletApp,
+};
+use bdk_wallet::{bitcoin::Amount, AddressInfo};
+
+use super::qr;
 
 pub struct ReceiveState {
     pub pay_to_addr: String,
     pub label: String,
-    pub derivation: String,
     pub next_addr: Vec<AddressInfo>,
+    pub marked: Vec<MarkedAddress>,
+    /// Counterparty name for reserving an address
+    reserve_for: String,
+    /// Address the payment request is being built for
+    request_addr: Option<AddressInfo>,
+    sats_entry: String,
+    message: String,
+    pub requests: Vec<(PaymentRequest, PaymentStatus)>,
 }
 
 impl ReceiveState {
@@ -13,26 +29,221 @@ impl ReceiveState {
         ReceiveState {
             pay_to_addr: "".into(),
             label: "".into(),
-            derivation: "".into(),
             next_addr: Vec::new(),
+            marked: Vec::new(),
+            reserve_for: "".into(),
+            request_addr: None,
+            sats_entry: "".into(),
+            message: "".into(),
+            requests: Vec::new(),
         }
     }
+
+    fn payment_request(&self) -> Option<PaymentRequest> {
+        let addr = self.request_addr.as_ref()?;
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Some(PaymentRequest {
+            index: addr.index,
+            address: addr.address.clone(),
+            amount: self.sats_entry.parse().ok().map(Amount::from_sat),
+            label: self.label.clone(),
+            message: self.message.clone(),
+            created_at,
+        })
+    }
+
+    fn edit_request(&mut self, req: &PaymentRequest) {
+        self.request_addr = Some(AddressInfo {
+            index: req.index,
+            address: req.address.clone(),
+            keychain: bdk_wallet::KeychainKind::External,
+        });
+        self.pay_to_addr = req.address.to_string();
+        self.sats_entry = req
+            .amount
+            .map(|a| a.to_sat().to_string())
+            .unwrap_or_default();
+        self.label = req.label.clone();
+        self.message = req.message.clone();
+    }
+}
+
+fn to_uri(req: &PaymentRequest) -> Bip21Uri {
+    let mut uri = Bip21Uri::new(req.address.clone());
+    uri.amount = req.amount;
+    uri.label = Some(req.label.clone()).filter(|l| !l.is_empty());
+    uri.message = Some(req.message.clone()).filter(|m| !m.is_empty());
+    uri
 }
 
 pub fn page(state: &mut WalletApp, ui: &mut egui::Ui) {
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        addresses(state, ui);
+        marked_addresses(state, ui);
+        request_builder(state, ui);
+        saved_requests(state, ui);
+    });
+}
+
+fn addresses(state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Receive");
+    ui.horizontal(|ui| {
+        ui.label("Reserve for: ");
+        ui.text_edit_singleline(&mut state.receive.reserve_for);
+    });
+    let can_reserve = !state.receive.reserve_for.trim().is_empty();
     for i in 0..state.receive.next_addr.len() {
         ui.horizontal(|ui| {
             ui.label(format!("Unused Address {:02}:", i + 1));
             ui.label(format!("{}", state.receive.next_addr[i]));
+            state.labels.edit(
+                ui,
+                &state.wallet_req,
+                LabelType::Addr,
+                state.receive.next_addr[i].address.to_string(),
+            );
+            if ui.button("Request payment").clicked() {
+                let addr = state.receive.next_addr[i].clone();
+                state.receive.pay_to_addr = addr.address.to_string();
+                state.receive.label = state
+                    .labels
+                    .get(LabelType::Addr, &state.receive.pay_to_addr)
+                    .unwrap_or_default()
+                    .to_string();
+                state.receive.request_addr = Some(addr);
+            }
             if ui.button("Mark used").clicked() {
-                state.debug.push("mark used unimplemented".into());
-                // state
-                //     .wallet_req
-                //     .send(crate::messages::WalletRequest::MarkUsed(
-                //         state.receive.next_addr[i],
-                //     ));
+                state
+                    .wallet_req
+                    .send(WalletRequest::MarkUsed(state.receive.next_addr[i].clone()))
+                    .unwrap();
+            }
+            if ui
+                .add_enabled(can_reserve, egui::Button::new("Reserve"))
+                .clicked()
+            {
+                state
+                    .wallet_req
+                    .send(WalletRequest::Reserve(
+                        state.receive.next_addr[i].clone(),
+                        state.receive.reserve_for.trim().to_string(),
+                    ))
+                    .unwrap();
+                state.receive.reserve_for.clear();
             }
         });
     }
 }
+
+fn marked_addresses(state: &mut WalletApp, ui: &mut egui::Ui) {
+    if state.receive.marked.is_empty() {
+        return;
+    }
+    ui.separator();
+    ui.heading("Used and reserved addresses");
+    for marked in state.receive.marked.iter() {
+        ui.horizontal(|ui| {
+            ui.label(format!("{}", marked.address));
+            state.labels.edit(
+                ui,
+                &state.wallet_req,
+                LabelType::Addr,
+                marked.address.address.to_string(),
+            );
+            match &marked.reserved_for {
+                Some(name) => ui.label(format!("reserved for {name}")),
+                None => ui.label("marked used"),
+            };
+            if ui.button("Unmark").clicked() {
+                state
+                    .wallet_req
+                    .send(WalletRequest::UnmarkUsed(marked.address.clone()))
+                    .unwrap();
+            }
+        });
+    }
+}
+
+fn request_builder(state: &mut WalletApp, ui: &mut egui::Ui) {
+    if state.receive.request_addr.is_none() {
+        return;
+    }
+    ui.separator();
+    ui.heading("Payment request");
+    ui.label(format!("Address: {}", state.receive.pay_to_addr));
+    ui.horizontal(|ui| {
+        ui.label("Amount (sats): ");
+        if ui
+            .text_edit_singleline(&mut state.receive.sats_entry)
+            .changed()
+        {
+            state.receive.sats_entry.retain(|c| c.is_ascii_digit());
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Label: ");
+        ui.text_edit_singleline(&mut state.receive.label);
+    });
+    ui.horizontal(|ui| {
+        ui.label("Message: ");
+        ui.text_edit_singleline(&mut state.receive.message);
+    });
+
+    // built after the inputs so the URI and QR match what was just typed
+    let Some(req) = state.receive.payment_request() else {
+        return;
+    };
+    let uri = to_uri(&req).to_string();
+    ui.horizontal(|ui| {
+        ui.label(&uri);
+        if ui.button("Copy").clicked() {
+            ui.output_mut(|o| o.copied_text = uri.clone());
+        }
+    });
+    qr::show(ui, &uri);
+
+    ui.horizontal(|ui| {
+        if ui.button("Save request").clicked() {
+            state
+                .wallet_req
+                .send(WalletRequest::SavePaymentRequest(req))
+                .unwrap();
+            state.receive.request_addr = None;
+        }
+        if ui.button("Cancel").clicked() {
+            state.receive.request_addr = None;
+        }
+    });
+}
+
+fn saved_requests(state: &mut WalletApp, ui: &mut egui::Ui) {
+    if state.receive.requests.is_empty() {
+        return;
+    }
+    ui.separator();
+    ui.heading("Saved requests");
+    let mut edit = None;
+    for (req, status) in state.receive.requests.iter() {
+        ui.horizontal(|ui| {
+            let requested = match req.amount {
+                Some(a) => format!("{} sats", a.to_sat()),
+                None => "any amount".into(),
+            };
+            let status = match status {
+                PaymentStatus::Unpaid => "unpaid".to_string(),
+                PaymentStatus::PartiallyPaid(a) => format!("partially paid ({} sats)", a.to_sat()),
+                PaymentStatus::Paid(a) => format!("paid ({} sats)", a.to_sat()),
+            };
+            ui.label(format!("{} - {requested} - {status}", req.label));
+            if ui.button("Show").clicked() {
+                edit = Some(req.clone());
+            }
+        });
+    }
+    if let Some(req) = edit {
+        state.receive.edit_request(&req);
+    }
+}
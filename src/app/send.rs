@@ -1,22 +1,38 @@
-use std::{io::Write, str::FromStr};
+use std::io::Write;
 
-use crate::WalletApp;
+use crate::{
+    bip21::Bip21Uri,
+    bip329::LabelType,
+    messages::Draft,
+    policy::{PathChoice, PolicyNode},
+    registry, WalletApp,
+};
 use bdk_wallet::{
     bitcoin::{Address, Network, Psbt},
-    LocalOutput,
+    KeychainKind, LocalOutput,
 };
 
 #[derive(Clone, Debug)]
 pub struct SendState {
     pub pay_to_addr: Option<Address>,
     addr_entry: String,
+    addr_error: Option<String>,
     pub label: String,
+    pub message: String,
     pub sats_amount: u64,
     pub sats_entry: String,
     pub selected_utxos: Vec<LocalOutput>,
+    /// sat/vB
     pub fee_rate: f32,
+    /// Confirmation target in blocks used for fee estimation
+    fee_target: usize,
     pub fees: u64,
     pub psbt: Option<Psbt>,
+    /// Transactions created earlier and not broadcast yet
+    pub drafts: Vec<Draft>,
+    /// Spending policy of wallets that can spend in more than one way
+    pub policy: Option<PolicyNode>,
+    pub policy_choice: PathChoice,
 }
 
 impl SendState {
@@ -24,16 +40,36 @@ impl SendState {
         SendState {
             pay_to_addr: None,
             addr_entry: "".into(),
+            addr_error: None,
             label: "".into(),
+            message: "".into(),
             sats_amount: 0,
             sats_entry: "".into(),
             selected_utxos: Vec::new(),
-            fee_rate: 1.,
+            fee_rate: 5.,
+            fee_target: 6,
             fees: 0,
             psbt: None,
+            drafts: Vec::new(),
+            policy: None,
+            policy_choice: PathChoice::new(),
         }
     }
 
+    /// Keep the receive policy when it offers more than one way to spend
+    pub fn set_policy(&mut self, trees: &[(KeychainKind, PolicyNode)]) {
+        let policy = trees
+            .iter()
+            .find(|(kc, _)| *kc == KeychainKind::External)
+            .map(|(_, tree)| tree.clone())
+            .filter(PolicyNode::has_choice);
+        self.policy_choice = policy
+            .as_ref()
+            .map(PolicyNode::default_choice)
+            .unwrap_or_default();
+        self.policy = policy;
+    }
+
     // fn is_signed(self) -> bool {
     //     if let Some(psbt) = self.psbt {
     //         // psbt.finalize()
@@ -44,23 +80,31 @@ impl SendState {
     // }
 
     fn get_psbt(&self) -> Option<Vec<u8>> {
-        match self.psbt.clone() {
-            Some(psbt) => Some(psbt.serialize()),
-            None => None,
-        }
+        self.psbt.clone().map(|psbt| psbt.serialize())
     }
 
+    /// Accepts a bare address or a BIP21 URI, filling in any fields the URI provides
     fn verify_address(&mut self, network: Network) {
-        let addr = Address::from_str(&self.addr_entry);
-        if let Ok(a) = addr {
-            let res = a.require_network(network);
-            if let Ok(res) = res {
-                self.pay_to_addr = Some(res);
-            } else {
-                self.addr_entry = "Invalid address".into();
+        match Bip21Uri::parse(&self.addr_entry, network) {
+            Ok(uri) => {
+                if let Some(amount) = uri.amount {
+                    self.sats_amount = amount.to_sat();
+                    self.sats_entry = self.sats_amount.to_string();
+                }
+                if let Some(label) = uri.label {
+                    self.label = label;
+                }
+                if let Some(message) = uri.message {
+                    self.message = message;
+                }
+                self.addr_entry = uri.address.to_string();
+                self.pay_to_addr = Some(uri.address);
+                self.addr_error = None;
+            }
+            Err(e) => {
+                self.pay_to_addr = None;
+                self.addr_error = Some(e.to_string());
             }
-        } else {
-            self.addr_entry = addr.err().unwrap().to_string();
         }
     }
 }
@@ -68,12 +112,13 @@ impl SendState {
 pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Transaction Builder");
     ui.horizontal(|ui| {
-        ui.label("Label: ");
-        ui.label(format!("{}", app_state.send.label));
-    });
-    ui.horizontal(|ui| {
-        ui.label("Destination Address:");
-        ui.text_edit_singleline(&mut app_state.send.addr_entry);
+        ui.label("Destination Address or URI:");
+        if ui
+            .text_edit_singleline(&mut app_state.send.addr_entry)
+            .changed()
+        {
+            app_state.send.pay_to_addr = None;
+        }
 
         if ui.button("Verify address").clicked() {
             app_state.send.verify_address(app_state.network);
@@ -83,6 +128,17 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
             ui.label("Address verified");
         }
     });
+    if let Some(e) = &app_state.send.addr_error {
+        ui.colored_label(ui.visuals().error_fg_color, e);
+    }
+    ui.horizontal(|ui| {
+        ui.label("Label: ");
+        ui.text_edit_singleline(&mut app_state.send.label);
+    });
+    ui.horizontal(|ui| {
+        ui.label("Message: ");
+        ui.text_edit_singleline(&mut app_state.send.message);
+    });
 
     ui.horizontal(|ui| {
         ui.label("Amount (sats): ");
@@ -98,7 +154,7 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
                         .send
                         .sats_entry
                         .chars()
-                        .filter(|c| c.is_digit(10))
+                        .filter(|c| c.is_ascii_digit())
                         .collect();
                 }
             };
@@ -106,13 +162,60 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     });
 
     ui.horizontal(|ui| {
-        ui.label("UTXOS:");
-        app_state.send.selected_utxos.iter().for_each(|utxo| {
-            ui.label(format!("{} sats", utxo.txout.value));
-        });
+        ui.label("Fee rate (sat/vB): ");
+        ui.add(
+            egui::DragValue::new(&mut app_state.send.fee_rate)
+                .speed(0.1)
+                .clamp_range(1.0..=10_000.0),
+        );
+        ui.label("Target blocks: ");
+        ui.add(egui::DragValue::new(&mut app_state.send.fee_target).clamp_range(1..=1008));
+        if ui.button("Estimate").clicked() {
+            app_state
+                .wallet_req
+                .send(crate::messages::WalletRequest::EstimateFee(
+                    app_state.send.fee_target,
+                ))
+                .unwrap();
+        }
     });
 
-    if ui.button("Create TX").clicked() {
+    ui.label("UTXOS:");
+    for utxo in app_state.send.selected_utxos.iter() {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{}: {} sats",
+                utxo.outpoint,
+                utxo.txout.value.to_sat()
+            ));
+            app_state.labels.edit(
+                ui,
+                &app_state.wallet_req,
+                LabelType::Output,
+                utxo.outpoint.to_string(),
+            );
+        });
+    }
+
+    if let Some(policy) = &app_state.send.policy {
+        ui.separator();
+        ui.label("Spending path:");
+        policy_tree(
+            ui,
+            policy,
+            &mut Vec::new(),
+            &mut app_state.send.policy_choice,
+        );
+        ui.separator();
+    }
+
+    if ui
+        .add_enabled(
+            app_state.send.pay_to_addr.is_some(),
+            egui::Button::new("Create TX"),
+        )
+        .clicked()
+    {
         app_state
             .wallet_req
             .send(crate::messages::WalletRequest::CreateTransaction(
@@ -121,15 +224,13 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
             .unwrap();
     }
 
-    if app_state.send.psbt.is_some() {
-        let psbt = app_state.send.psbt.as_mut().unwrap().clone();
-
+    if let Some(psbt) = app_state.send.psbt.clone() {
         if ui.button("Download PSBT").clicked() {
             let mut dl = dirs::download_dir().unwrap();
             dl.push("psbt.txt");
             if let Ok(mut f) = std::fs::File::create(dl) {
                 if let Some(vec) = app_state.send.get_psbt() {
-                    f.write(&vec).unwrap();
+                    f.write_all(&vec).unwrap();
                 }
             }
         }
@@ -137,8 +238,73 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
         if ui.button("Broadcast Transaction").clicked() {
             app_state
                 .wallet_req
-                .send(crate::messages::WalletRequest::SendTransaction(psbt))
+                .send(crate::messages::WalletRequest::SendTransaction(
+                    psbt,
+                    app_state.send.label.clone(),
+                ))
                 .unwrap()
         }
     }
+
+    if !app_state.send.drafts.is_empty() {
+        ui.separator();
+        ui.label("Drafts:");
+    }
+    let mut discard = None;
+    for draft in &app_state.send.drafts {
+        let tx = &draft.psbt.unsigned_tx;
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} · {} sats out · {}",
+                registry::format_date(draft.created_at),
+                tx.output.iter().map(|o| o.value.to_sat()).sum::<u64>(),
+                tx.compute_txid()
+            ));
+            if ui.button("Open").clicked() {
+                app_state.send.psbt = Some(draft.psbt.clone());
+            }
+            if ui.button("Discard").clicked() {
+                discard = Some(tx.compute_txid());
+            }
+        });
+    }
+    if let Some(txid) = discard {
+        app_state
+            .wallet_req
+            .send(crate::messages::WalletRequest::DiscardDraft(txid))
+            .unwrap();
+    }
+}
+
+/// Show the policy with a checkbox for every child of a node where the user picks which
+/// branches to satisfy, explaining any timelock a branch is waiting on
+fn policy_tree(
+    ui: &mut egui::Ui,
+    node: &PolicyNode,
+    position: &mut Vec<usize>,
+    choice: &mut PathChoice,
+) {
+    ui.label(&node.description);
+    if let Some(timelock) = &node.timelock {
+        ui.label(format!("Timelock: {timelock}"));
+    }
+    ui.indent(position.clone(), |ui| {
+        for (i, child) in node.children.iter().enumerate() {
+            if node.is_choice() {
+                let picked = choice.entry(position.clone()).or_default();
+                let mut checked = picked.contains(&i);
+                // picking a branch swaps out another, so exactly `threshold` stay picked
+                if ui.checkbox(&mut checked, "Use this path").changed() && checked {
+                    if picked.len() >= node.threshold {
+                        picked.remove(0);
+                    }
+                    picked.push(i);
+                    picked.sort();
+                }
+            }
+            position.push(i);
+            policy_tree(ui, child, position, choice);
+            position.pop();
+        }
+    });
 }
@@ -1,14 +1,147 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-use crate::WalletApp;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use bdk_wallet::bitcoin::Network;
+
+use crate::{
+    bdk_utils,
+    chain::{self, Backend, CbfSettings, ProxySettings, RpcSettings, ServerInfo},
+    messages::WalletResponse,
+    WalletApp,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
-    pub electrum_url: String,
+    /// Electrum servers in the order they are tried
+    #[serde(alias = "electrum_url", deserialize_with = "one_or_many")]
+    pub electrum_servers: Vec<ElectrumServer>,
     pub wallet_db: String,
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(default = "default_esplora_url")]
+    pub esplora_url: String,
+    #[serde(default)]
+    pub rpc: RpcSettings,
+    #[serde(default)]
+    pub cbf: CbfSettings,
+    #[serde(default)]
+    pub proxy: ProxySettings,
+    /// Socket timeout for Electrum and Esplora connections
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u8,
+    /// How many times a failed Electrum or Esplora request is retried
+    #[serde(default = "default_retries")]
+    pub retries: u8,
+    /// Network for newly created wallets
+    #[serde(default = "default_network")]
+    pub network: Network,
+}
+
+/// An Electrum server and how its certificate is checked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElectrumServer {
+    pub url: String,
+    /// Check `ssl://` certificates against the system roots and the server name.
+    /// When off, and always for onion servers, the certificate seen on first connection is
+    /// pinned instead.
+    #[serde(default = "default_validate_domain")]
+    pub validate_domain: bool,
+}
+
+impl ElectrumServer {
+    pub fn new(url: String) -> Self {
+        ElectrumServer {
+            url,
+            validate_domain: true,
+        }
+    }
+}
+
+fn default_validate_domain() -> bool {
+    true
+}
+
+/// Older settings files hold a single `electrum_url` string or a list of urls
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ElectrumServer>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Server {
+        Url(String),
+        Full(ElectrumServer),
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<Server>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![ElectrumServer::new(url)],
+        OneOrMany::Many(servers) => servers
+            .into_iter()
+            .map(|s| match s {
+                Server::Url(url) => ElectrumServer::new(url),
+                Server::Full(server) => server,
+            })
+            .collect(),
+    })
+}
+
+fn default_timeout() -> u8 {
+    30
+}
+
+fn default_retries() -> u8 {
+    1
+}
+
+fn default_network() -> Network {
+    Network::Testnet
+}
+
+fn default_esplora_url() -> String {
+    "https://blockstream.info/testnet/api".into()
 }
 
-const FOLDER: &str = "bdkw";
+/// Scan settings stored alongside each wallet in its database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletSettings {
+    pub stop_gap: usize,
+    pub batch_size: usize,
+    /// Applied when the wallet is loaded
+    pub lookahead: u32,
+}
+
+impl Default for WalletSettings {
+    fn default() -> Self {
+        WalletSettings {
+            stop_gap: bdk_utils::STOP_GAP,
+            batch_size: bdk_utils::BATCH_SIZE,
+            lookahead: bdk_wallet::chain::keychain_txout::DEFAULT_LOOKAHEAD,
+        }
+    }
+}
+
+/// Editing state for the Electrum server list
+pub struct ServerListState {
+    new_server: String,
+    /// Latest connection test per server
+    pub tests: HashMap<String, Result<ServerInfo, String>>,
+}
+
+impl ServerListState {
+    pub fn new() -> Self {
+        ServerListState {
+            new_server: "".into(),
+            tests: HashMap::new(),
+        }
+    }
+}
+
+pub const FOLDER: &str = "bdkw";
 const SETTINGS: &str = "settings.json";
 
 impl Settings {
@@ -18,13 +151,23 @@ impl Settings {
         if !dir.exists() {
             std::fs::create_dir(dir.clone()).expect("unable to create config directory");
         }
+        let wallets = dir.join("wallets");
         dir.push(SETTINGS);
 
         if !dir.exists() {
-            dir.push("wallets");
             Self {
-                electrum_url: "ssl://electrum.blockstream.info:60002".into(),
-                wallet_db: dir.to_str().unwrap().to_string(),
+                electrum_servers: vec![ElectrumServer::new(
+                    "ssl://electrum.blockstream.info:60002".into(),
+                )],
+                wallet_db: wallets.to_str().unwrap().to_string(),
+                backend: Backend::default(),
+                esplora_url: default_esplora_url(),
+                rpc: RpcSettings::for_network(default_network()),
+                cbf: CbfSettings::default(),
+                proxy: ProxySettings::default(),
+                timeout_secs: default_timeout(),
+                retries: default_retries(),
+                network: default_network(),
             }
         } else {
             let str = std::fs::read_to_string(dir).expect("already checked if exists");
@@ -45,10 +188,93 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Settings");
 
     ui.horizontal(|ui| {
-        ui.label("Electrum URL: ");
-        ui.text_edit_singleline(&mut app_state.settings.electrum_url);
+        ui.label("Backend: ");
+        ui.selectable_value(
+            &mut app_state.settings.backend,
+            Backend::Electrum,
+            "Electrum",
+        );
+        ui.selectable_value(&mut app_state.settings.backend, Backend::Esplora, "Esplora");
+        ui.selectable_value(
+            &mut app_state.settings.backend,
+            Backend::Rpc,
+            "Bitcoin Core RPC",
+        );
+        ui.selectable_value(
+            &mut app_state.settings.backend,
+            Backend::Cbf,
+            "Compact block filters",
+        );
     });
 
+    ui.horizontal(|ui| {
+        ui.label("Network for new wallets: ");
+        let before = app_state.settings.network;
+        for network in [Network::Testnet, Network::Signet, Network::Regtest] {
+            ui.selectable_value(
+                &mut app_state.settings.network,
+                network,
+                network.to_string(),
+            );
+        }
+        // follow the node defaults of the new network unless they were edited
+        let settings = &mut app_state.settings;
+        if settings.network != before && settings.rpc == RpcSettings::for_network(before) {
+            settings.rpc = RpcSettings::for_network(settings.network);
+        }
+    });
+
+    electrum_servers(app_state, ui);
+
+    ui.horizontal(|ui| {
+        ui.label("Esplora URL: ");
+        ui.text_edit_singleline(&mut app_state.settings.esplora_url);
+    });
+
+    if app_state.settings.backend == Backend::Rpc {
+        let rpc = &mut app_state.settings.rpc;
+        egui::Grid::new("rpc_settings").show(ui, |ui| {
+            ui.label("RPC URL: ");
+            ui.text_edit_singleline(&mut rpc.url);
+            ui.end_row();
+            ui.label("Auth: ");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut rpc.use_cookie, true, "Cookie file");
+                ui.selectable_value(&mut rpc.use_cookie, false, "User/password");
+            });
+            ui.end_row();
+            if rpc.use_cookie {
+                ui.label("Cookie file: ");
+                ui.text_edit_singleline(&mut rpc.cookie_file);
+                ui.end_row();
+            } else {
+                ui.label("User: ");
+                ui.text_edit_singleline(&mut rpc.user);
+                ui.end_row();
+                ui.label("Password: ");
+                ui.add(egui::TextEdit::singleline(&mut rpc.password).password(true));
+                ui.end_row();
+            }
+            ui.label("Scan from height: ");
+            ui.add(egui::DragValue::new(&mut rpc.start_height));
+            ui.end_row();
+        });
+    }
+
+    if app_state.settings.backend == Backend::Cbf {
+        let cbf = &mut app_state.settings.cbf;
+        egui::Grid::new("cbf_settings").show(ui, |ui| {
+            ui.label("Peer: ");
+            ui.text_edit_singleline(&mut cbf.peer);
+            ui.end_row();
+            ui.label("Scan from height: ");
+            ui.add(egui::DragValue::new(&mut cbf.start_height));
+            ui.end_row();
+        });
+    }
+
+    connection_settings(app_state, ui);
+
     ui.horizontal(|ui| {
         ui.label("DB URL: ");
         ui.text_edit_singleline(&mut app_state.settings.wallet_db);
@@ -57,9 +283,201 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
             app_state
                 .wallet_req
                 .send(crate::messages::WalletRequest::AppConfig(
-                    app_state.settings.clone().into(),
+                    app_state.settings.clone(),
                 ))
                 .unwrap();
         }
     });
+
+    ui.separator();
+    ui.heading("Wallet scan settings");
+    let ws = &mut app_state.wallet_settings;
+    egui::Grid::new("wallet_settings").show(ui, |ui| {
+        ui.label("Stop gap: ");
+        ui.add(egui::DragValue::new(&mut ws.stop_gap).clamp_range(1..=100_000));
+        ui.end_row();
+        ui.label("Batch size: ");
+        ui.add(egui::DragValue::new(&mut ws.batch_size).clamp_range(1..=1_000));
+        ui.end_row();
+        ui.label("Lookahead: ");
+        ui.add(egui::DragValue::new(&mut ws.lookahead).clamp_range(1..=100_000));
+        ui.label("(applied next time the wallet is loaded)");
+        ui.end_row();
+    });
+    if ui.button("Save scan settings").clicked() {
+        app_state
+            .wallet_req
+            .send(crate::messages::WalletRequest::WalletConfig(
+                app_state.wallet_settings.clone(),
+            ))
+            .unwrap();
+    }
+
+    ui.separator();
+    ui.heading("Labels (BIP329)");
+    ui.horizontal(|ui| {
+        ui.label("Labels file: ");
+        ui.text_edit_singleline(&mut app_state.labels.file);
+    });
+    ui.horizontal(|ui| {
+        let path = std::path::PathBuf::from(&app_state.labels.file);
+        if ui.button("Import labels").clicked() {
+            app_state
+                .wallet_req
+                .send(crate::messages::WalletRequest::ImportLabels(path.clone()))
+                .unwrap();
+        }
+        if ui.button("Export labels").clicked() {
+            app_state
+                .wallet_req
+                .send(crate::messages::WalletRequest::ExportLabels(path))
+                .unwrap();
+        }
+    });
+}
+
+fn connection_settings(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    let settings = &mut app_state.settings;
+    ui.checkbox(&mut settings.proxy.enabled, "Connect through a proxy (Tor)");
+    egui::Grid::new("connection_settings").show(ui, |ui| {
+        if settings.proxy.enabled {
+            let proxy = &mut settings.proxy;
+            ui.label("SOCKS5 proxy (Electrum, RPC, CBF): ");
+            ui.text_edit_singleline(&mut proxy.socks5);
+            ui.end_row();
+            ui.label("Proxy user: ");
+            ui.text_edit_singleline(&mut proxy.user);
+            ui.end_row();
+            ui.label("Proxy password: ");
+            ui.add(egui::TextEdit::singleline(&mut proxy.password).password(true));
+            ui.end_row();
+            ui.label("HTTP tunnel (Esplora): ");
+            ui.text_edit_singleline(&mut proxy.http_tunnel);
+            ui.end_row();
+        }
+        ui.label("Timeout (s): ");
+        ui.add(egui::DragValue::new(&mut settings.timeout_secs).clamp_range(1..=255));
+        ui.end_row();
+        ui.label("Retries: ");
+        ui.add(egui::DragValue::new(&mut settings.retries).clamp_range(0..=10));
+        ui.end_row();
+    });
+}
+
+fn electrum_servers(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    ui.label("Electrum servers (tried in order): ");
+    // proxy and timeouts for connection tests
+    let settings = app_state.settings.clone();
+    let servers = &mut app_state.settings.electrum_servers;
+    let mut swap = None;
+    let mut remove = None;
+    egui::Grid::new("electrum_servers").show(ui, |ui| {
+        let count = servers.len();
+        for (i, server) in servers.iter_mut().enumerate() {
+            ui.label(&server.url);
+            ui.horizontal(|ui| {
+                if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                    swap = Some((i - 1, i));
+                }
+                if ui
+                    .add_enabled(i + 1 < count, egui::Button::new("Down"))
+                    .clicked()
+                {
+                    swap = Some((i, i + 1));
+                }
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+                if ui.button("Test").clicked() {
+                    // runs off the UI thread so it works before a wallet is loaded
+                    let server = server.clone();
+                    let settings = settings.clone();
+                    let updates = app_state.for_bg_upd.clone();
+                    std::thread::spawn(move || {
+                        let res = chain::test_server(&server, &settings);
+                        for change in chain::take_cert_changes() {
+                            let _ = updates.send(WalletResponse::CertificateChanged(change));
+                        }
+                        let _ = updates.send(WalletResponse::ServerTest(server.url, res));
+                    });
+                }
+            });
+            ui.horizontal(|ui| {
+                if server.url.starts_with("ssl://") {
+                    if chain::is_onion(&server.url) {
+                        ui.label("Certificate pinned, onion addresses have no domain to validate");
+                    } else {
+                        ui.checkbox(&mut server.validate_domain, "Validate domain");
+                    }
+                    if chain::is_pinned(server)
+                        && ui
+                            .small_button("Forget pinned certificate")
+                            .on_hover_text("The next certificate seen is trusted")
+                            .clicked()
+                    {
+                        if let Err(e) = chain::forget_pin(&server.url) {
+                            app_state.debug.push(e);
+                        }
+                    }
+                }
+            });
+            match app_state.servers.tests.get(&server.url) {
+                Some(Ok(info)) => {
+                    ui.label(format!(
+                        "{} ms, {}, tip {}",
+                        info.latency.as_millis(),
+                        info.version,
+                        info.tip_height
+                    ));
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, e);
+                }
+                None => {
+                    ui.label("");
+                }
+            }
+            ui.end_row();
+        }
+    });
+    if let Some((a, b)) = swap {
+        servers.swap(a, b);
+    }
+    if let Some(i) = remove {
+        servers.remove(i);
+    }
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut app_state.servers.new_server);
+        let new_server = app_state.servers.new_server.trim().to_string();
+        if ui
+            .add_enabled(!new_server.is_empty(), egui::Button::new("Add server"))
+            .clicked()
+        {
+            if !servers.iter().any(|s| s.url == new_server) {
+                servers.push(ElectrumServer::new(new_server));
+            }
+            app_state.servers.new_server.clear();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_electrum_url_migrates() {
+        let expected = vec![ElectrumServer::new("ssl://a:50002".into())];
+        let old = r#"{"electrum_url": "ssl://a:50002", "wallet_db": "/tmp/w"}"#;
+        let settings: Settings = serde_json::from_str(old).unwrap();
+        assert_eq!(settings.electrum_servers, expected);
+
+        let list = r#"{"electrum_servers": ["ssl://a:50002"], "wallet_db": "/tmp/w"}"#;
+        let settings: Settings = serde_json::from_str(list).unwrap();
+        assert_eq!(settings.electrum_servers, expected);
+
+        let new = serde_json::to_string(&settings).unwrap();
+        let settings: Settings = serde_json::from_str(&new).unwrap();
+        assert_eq!(settings.electrum_servers, expected);
+    }
 }
@@ -15,7 +15,21 @@ pub fn sidepanel(app_state: &mut WalletApp, ui: &mut egui::Ui) {
         app_state.page = Page::Receive;
     }
 
+    if ui.button("Addresses").clicked() {
+        app_state.page = Page::Addresses;
+        app_state.addresses.refresh = true;
+    }
+
     if ui.button("Transactions").clicked() {
         app_state.page = Page::Transactions;
     }
+
+    if ui.button("Export").clicked() {
+        app_state.page = Page::Export;
+        super::export::request(app_state);
+    }
+
+    if ui.button("Policy").clicked() {
+        app_state.page = Page::Policy;
+    }
 }
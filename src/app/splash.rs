@@ -1,18 +1,57 @@
-use bdk_wallet::bip39::Mnemonic;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
-use crate::bdk_utils;
+use bdk_wallet::{
+    bip39::Mnemonic,
+    bitcoin::{bip32::Fingerprint, Amount, Network, NetworkKind},
+    rusqlite::Connection,
+    PersistedWallet,
+};
+
+use crate::entropy::{self, SeedEntropy, Source};
+use crate::export::WalletExport;
+use crate::import::{self, ImportedWallet};
 use crate::messages::CreatedWallet;
+use crate::multisig::{self, MultisigScript, OwnKey};
+use crate::policy;
+use crate::registry::{self, WalletEntry};
 use crate::WalletApp;
+use crate::{
+    bdk_utils::{self, AccountScript},
+    bundle,
+};
 
 #[derive(Debug)]
 pub struct SplashState {
     selected_wallet: String,
-    wallets: Vec<String>,
+    wallets: Vec<WalletEntry>,
+    /// Why the wallet registry could not be read
+    error: Option<String>,
+    /// Rename, duplicate or delete in progress on one of the wallets
+    manage: Option<ManageState>,
+    /// Outcome of the last manage or restore action
+    message: Option<String>,
+    restore: RestoreState,
     save_seed: bool,
     new_name: String,
     new_1: String,
     new_2: String,
     new_option: NewWallet,
+    /// Descriptors read from the import file
+    import: Option<Result<ImportedWallet, String>>,
+    /// Network the imported wallet is created on
+    import_network: Network,
+    multisig: MultisigState,
+    policy: PolicyState,
+    account: AccountState,
+    /// The seed entered is an existing backup, so its check is left for the home page
+    restoring: bool,
+    quiz: Option<Quiz>,
+    seed_gen: SeedGen,
+    /// Balance across the accounts of each seed, as of each account's last sync
+    totals: BTreeMap<Fingerprint, Result<Amount, String>>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -20,18 +59,273 @@ pub enum NewWallet {
     Seed,
     Xpub,
     Descriptor,
+    Import,
+    Multisig,
+    Policy,
+    Account,
+}
+
+/// Options for generating a new seed, shared by every form that needs one
+#[derive(Debug)]
+struct SeedGen {
+    words: usize,
+    source: Source,
+    /// Dice rolls or coin flips typed by the user
+    input: String,
+    /// Mix system randomness in with the user's entropy
+    rng: bool,
+    /// Last generated seed, kept to show how it was derived
+    result: Option<Result<SeedEntropy, String>>,
+    /// Fingerprints of every seed generated this session, whose backups must be checked
+    made: Vec<Fingerprint>,
+}
+
+impl SeedGen {
+    fn new() -> Self {
+        SeedGen {
+            words: 24,
+            source: Source::None,
+            input: String::new(),
+            rng: true,
+            result: None,
+            made: Vec::new(),
+        }
+    }
+
+    /// Whether `words` were generated here this session, so no backup of them can predate it
+    fn made(&self, words: &Mnemonic) -> bool {
+        self.made.contains(&bdk_utils::seed_fingerprint(words))
+    }
+
+    /// Seed options and the generate button, returning the seed when one is made
+    fn show(&mut self, ui: &mut egui::Ui) -> Option<Mnemonic> {
+        let mut generated = None;
+        egui::CollapsingHeader::new("New seed options").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Words: ");
+                for words in entropy::WORD_COUNTS {
+                    ui.selectable_value(&mut self.words, words, words.to_string());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Your own entropy: ");
+                ui.selectable_value(&mut self.source, Source::None, "None");
+                ui.selectable_value(&mut self.source, Source::Dice, "Dice rolls");
+                ui.selectable_value(&mut self.source, Source::Coins, "Coin flips");
+            });
+            if self.source != Source::None {
+                let hint = match self.source {
+                    Source::Dice => "1 to 6 for each roll",
+                    _ => "H or T for each flip",
+                };
+                ui.add(egui::TextEdit::multiline(&mut self.input).hint_text(hint));
+                ui.checkbox(&mut self.rng, "Mix in system randomness");
+            }
+            if let Some(Ok(seed)) = &self.result {
+                for line in seed.explain() {
+                    ui.monospace(line);
+                }
+            }
+        });
+        if ui.button("Give me a new seed please").clicked() {
+            let rng = self.rng || self.source == Source::None;
+            let result = entropy::generate(self.words, rng, self.source, &self.input);
+            generated = result.as_ref().ok().map(|seed| seed.mnemonic.clone());
+            self.made
+                .extend(generated.iter().map(bdk_utils::seed_fingerprint));
+            self.result = Some(result);
+        }
+        if let Some(Err(e)) = &self.result {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+        }
+        generated
+    }
+}
+
+/// Words asked back from a new seed before the wallet is created
+#[derive(Debug)]
+struct Quiz {
+    positions: Vec<usize>,
+    answers: Vec<String>,
+}
+
+impl Quiz {
+    const WORDS: usize = 3;
+
+    fn new(words: &Mnemonic) -> Self {
+        Quiz {
+            positions: bdk_utils::quiz_positions(words.word_count(), Self::WORDS),
+            answers: vec![String::new(); Self::WORDS],
+        }
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Check your backup");
+        ui.label("Enter these words from the seed you wrote down:");
+        egui::Grid::new("quiz").show(ui, |ui| {
+            for (i, answer) in self.positions.iter().zip(&mut self.answers) {
+                ui.label(format!("Word #{}: ", i + 1));
+                ui.text_edit_singleline(answer);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn passed(&self, words: &Mnemonic) -> bool {
+        let words: Vec<&str> = words.word_iter().collect();
+        self.positions
+            .iter()
+            .zip(&self.answers)
+            .all(|(i, answer)| answer.trim().eq_ignore_ascii_case(words[*i]))
+    }
+}
+
+/// Script type and account index for wallets derived from a seed
+#[derive(Debug)]
+struct AccountState {
+    script: AccountScript,
+    index: u32,
+    /// Signing wallet whose seed a new account is derived from
+    parent: String,
+    error: Option<String>,
+}
+
+impl AccountState {
+    fn new() -> Self {
+        AccountState {
+            script: AccountScript::Bip84,
+            index: 0,
+            parent: String::new(),
+            error: None,
+        }
+    }
+
+    fn pick(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Script type: ");
+            for script in AccountScript::ALL {
+                ui.selectable_value(&mut self.script, script, script.name());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Account index: ");
+            ui.add(egui::DragValue::new(&mut self.index).clamp_range(0..=0x7fff_ffff_u32));
+        });
+    }
+}
+
+/// Inputs for the multisig wizard
+#[derive(Debug)]
+struct MultisigState {
+    script: MultisigScript,
+    threshold: usize,
+    /// Seed for our own cosigner key
+    seed: String,
+    quiz: Option<Quiz>,
+    cosigners: Vec<(Fingerprint, String)>,
+    new_cosigner: String,
+    /// Where the BSMS record for the other signers is written
+    bsms_file: String,
+    error: Option<String>,
+}
+
+impl MultisigState {
+    fn new() -> Self {
+        MultisigState {
+            script: MultisigScript::P2wsh,
+            threshold: 2,
+            seed: String::new(),
+            quiz: None,
+            cosigners: Vec::new(),
+            new_cosigner: String::new(),
+            bsms_file: String::new(),
+            error: None,
+        }
+    }
+}
+
+/// Inputs for the miniscript policy editor
+#[derive(Debug)]
+struct PolicyState {
+    policy: String,
+    /// Alias the policy uses for the key derived from `seed`
+    own_alias: String,
+    seed: String,
+    quiz: Option<Quiz>,
+    /// Other keys as (alias, `[fingerprint/path]xpub`)
+    keys: Vec<(String, String)>,
+    new_alias: String,
+    new_key: String,
+    error: Option<String>,
+}
+
+impl PolicyState {
+    fn new() -> Self {
+        PolicyState {
+            policy: policy::RECOVERY_TEMPLATE.into(),
+            own_alias: "primary".into(),
+            seed: String::new(),
+            quiz: None,
+            keys: Vec::new(),
+            new_alias: "recovery".into(),
+            new_key: String::new(),
+            error: None,
+        }
+    }
+}
+
+/// Inputs for restoring a wallet from an encrypted backup
+#[derive(Debug, Default)]
+struct RestoreState {
+    file: String,
+    passphrase: String,
+    name: String,
+    /// Also replace the app settings with the ones in the backup
+    settings: bool,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Manage {
+    Rename,
+    Duplicate,
+    Delete,
+}
+
+#[derive(Debug)]
+struct ManageState {
+    wallet: String,
+    action: Manage,
+    /// New name, or the wallet name typed again to confirm a delete
+    input: String,
 }
 
 impl SplashState {
     pub fn new(db: &str) -> Self {
+        let (wallets, error) = match registry::load(db) {
+            Ok(wallets) => (wallets, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
         SplashState {
             selected_wallet: String::new(),
             save_seed: false,
-            wallets: bdk_utils::list_wallets(db),
+            wallets,
+            error,
+            manage: None,
+            message: None,
+            restore: RestoreState::default(),
             new_name: String::new(),
             new_1: String::new(),
             new_2: String::new(),
             new_option: NewWallet::Seed,
+            import: None,
+            import_network: Network::Testnet,
+            multisig: MultisigState::new(),
+            policy: PolicyState::new(),
+            account: AccountState::new(),
+            restoring: false,
+            quiz: None,
+            seed_gen: SeedGen::new(),
+            totals: BTreeMap::new(),
         }
     }
 }
@@ -40,39 +334,94 @@ const NEW_NAME: &str = "New";
 
 pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Select a wallet to begin:");
-    egui::ComboBox::from_label("Select wallet")
-        .selected_text(format!("{}", app_state.splash.selected_wallet))
-        .show_ui(ui, |ui| {
-            ui.selectable_value(
-                &mut app_state.splash.selected_wallet,
-                NEW_NAME.into(),
-                NEW_NAME,
-            );
-            app_state.splash.wallets.iter().for_each(|w| {
-                ui.selectable_value(&mut app_state.splash.selected_wallet, w.to_string(), w);
-            })
+    if let Some(e) = &app_state.splash.error {
+        ui.colored_label(
+            egui::Color32::RED,
+            format!("Unable to read wallet list: {e}"),
+        );
+    } else if app_state.splash.wallets.is_empty() {
+        ui.label("No wallets yet, create one below.");
+    }
+    let mut load = None;
+    let mut confirmed = false;
+    let mut add_account = None;
+    let splash = &mut app_state.splash;
+    let mut card =
+        |ui: &mut egui::Ui, entry: &WalletEntry| match wallet_card(ui, entry, &mut splash.manage) {
+            Some(CardEvent::Load) => load = Some(entry.name.clone()),
+            Some(CardEvent::Confirm) => confirmed = true,
+            Some(CardEvent::AddAccount) => add_account = Some(entry.name.clone()),
+            None => {}
+        };
+    for (seed, group) in seed_groups(&splash.wallets) {
+        let Some(seed) = seed else {
+            card(ui, group[0]);
+            continue;
+        };
+        egui::Frame::group(ui.style()).show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong(format!("Seed {seed} · {} accounts", group.len()));
+                if ui.button("Total balance").clicked() {
+                    let total = group
+                        .iter()
+                        .filter(|w| w.exists())
+                        .map(|w| registry::balance(w))
+                        .sum::<Result<Amount, String>>();
+                    splash.totals.insert(seed, total);
+                }
+                match splash.totals.get(&seed) {
+                    Some(Ok(total)) => {
+                        ui.label(format!("{} sats as of the last sync", total.to_sat()));
+                    }
+                    Some(Err(e)) => {
+                        ui.colored_label(ui.visuals().error_fg_color, e);
+                    }
+                    None => {}
+                }
+            });
+            for entry in group {
+                card(ui, entry);
+            }
         });
-    ui.add_space(20.);
-    if &app_state.splash.selected_wallet != "" && &app_state.splash.selected_wallet != NEW_NAME {
-        if ui.button("Load wallet").clicked() {
-            // load wallet and send to backend on click
-            let wallet = bdk_utils::from_changeset(
-                &app_state.settings.wallet_db,
-                &app_state.splash.selected_wallet,
-            );
-            if let Ok(wallet) = wallet {
+    }
+    if let Some(parent) = add_account {
+        start_account(&mut app_state.splash, parent);
+    }
+    if confirmed {
+        manage_wallet(app_state);
+    }
+    if let Some(message) = &app_state.splash.message {
+        ui.label(message);
+    }
+    if let Some(name) = load {
+        // load wallet from the files the registry lists and send to backend on click
+        let entry = app_state.splash.wallets.iter().find(|w| w.name == name);
+        let loaded = entry
+            .ok_or_else(|| format!("No wallet named \"{name}\""))
+            .and_then(|entry| {
+                let wallet = bdk_utils::load_wallet(&entry.db_file, entry.keys_file.as_deref())?;
+                Ok((wallet, entry.db_file.clone(), entry.backend))
+            });
+        match loaded {
+            Ok((wallet, db_file, backend)) => {
+                app_state.settings.backend = backend;
                 let wallet = CreatedWallet {
                     wallet,
-                    name: app_state.splash.selected_wallet.clone(),
+                    name,
+                    db_file,
                 };
                 app_state.new_bg(wallet);
-            } else {
-                println!("Load failed");
             }
+            Err(e) => app_state.splash.message = Some(format!("Unable to load {name}: {e}")),
         }
     }
+    ui.add_space(20.);
+    if app_state.splash.selected_wallet != NEW_NAME && ui.button("Create new wallet").clicked() {
+        app_state.splash.selected_wallet = NEW_NAME.into();
+    }
+    egui::CollapsingHeader::new("Restore from backup").show(ui, |ui| restore(app_state, ui));
 
-    if &app_state.splash.selected_wallet == NEW_NAME {
+    if app_state.splash.selected_wallet == NEW_NAME {
         ui.heading("New wallet options:");
         ui.horizontal(|ui| {
             ui.label("Enter name:");
@@ -88,6 +437,26 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
                     NewWallet::Descriptor,
                     "Descriptor",
                 );
+                ui.selectable_value(
+                    &mut app_state.splash.new_option,
+                    NewWallet::Import,
+                    "Import file",
+                );
+                ui.selectable_value(
+                    &mut app_state.splash.new_option,
+                    NewWallet::Multisig,
+                    "Multisig",
+                );
+                ui.selectable_value(
+                    &mut app_state.splash.new_option,
+                    NewWallet::Policy,
+                    "Policy",
+                );
+                ui.selectable_value(
+                    &mut app_state.splash.new_option,
+                    NewWallet::Account,
+                    "Account of a saved seed",
+                );
             });
 
         ui.checkbox(&mut app_state.splash.save_seed, "Save private key?");
@@ -96,58 +465,189 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
             NewWallet::Seed => seed_opt(app_state, ui),
             NewWallet::Xpub => xpub_opt(app_state, ui),
             NewWallet::Descriptor => descriptor_opt(app_state, ui),
+            NewWallet::Import => import_opt(app_state, ui),
+            NewWallet::Multisig => multisig_opt(app_state, ui),
+            NewWallet::Policy => policy_opt(app_state, ui),
+            NewWallet::Account => account_opt(app_state, ui),
         }
     }
 }
 
 fn seed_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    let splash = &mut app_state.splash;
+    let mne = Mnemonic::parse(splash.new_1.trim());
+    if let (Some(quiz), Ok(mne)) = (&mut splash.quiz, &mne) {
+        quiz.show(ui);
+        let passed = quiz.passed(mne);
+        let mut back = false;
+        let mut proceed = false;
+        ui.horizontal(|ui| {
+            back = ui.button("Show seed again").clicked();
+            proceed = ui
+                .add_enabled(
+                    passed && !splash.new_name.is_empty(),
+                    egui::Button::new("Proceed to load wallet"),
+                )
+                .clicked();
+        });
+        if back {
+            splash.quiz = None;
+        } else if proceed {
+            let mne = mne.clone();
+            finalize_wallet(app_state, mne, true);
+        }
+        return;
+    }
+
     ui.heading("Enter seed below:");
-    if ui.button("Give me a new seed please").clicked() {
-        // fill in seed
-        let new_seed = bdk_utils::new_seed();
-        app_state.splash.new_1 = new_seed.to_string();
-    }
-    ui.text_edit_multiline(&mut app_state.splash.new_1);
-    ui.heading("Confirm seed");
-    ui.text_edit_multiline(&mut app_state.splash.new_2);
-    if &app_state.splash.new_name != "" {
-        if &app_state.splash.new_1 != "" && app_state.splash.new_1 == app_state.splash.new_2 {
-            if ui.button("Proceed to load wallet").clicked() {
-                //
-                let mne = Mnemonic::parse(&app_state.splash.new_1);
-                match mne {
-                    Ok(seed) => finalize_wallet(app_state, seed),
-                    Err(e) => {
-                        app_state.splash.new_1 = "Invalid seed, try again...".into();
-                        app_state.splash.new_2 = e.to_string();
-                    }
-                }
-            }
+    if let Some(mne) = splash.seed_gen.show(ui) {
+        splash.new_1 = mne.to_string();
+        splash.restoring = false;
+    }
+    ui.text_edit_multiline(&mut splash.new_1);
+    if let (Err(e), false) = (&mne, splash.new_1.trim().is_empty()) {
+        ui.colored_label(ui.visuals().error_fg_color, format!("Invalid seed: {e}"));
+    }
+    // a seed generated here can't have a backup yet, so its check can't be skipped
+    let made = mne.as_ref().is_ok_and(|mne| splash.seed_gen.made(mne));
+    if !made {
+        ui.checkbox(
+            &mut splash.restoring,
+            "Restoring a seed I already have (check the backup later)",
+        );
+    }
+    splash.account.pick(ui);
+    let Ok(mne) = mne else {
+        return;
+    };
+    if splash.new_name.is_empty() {
+        return;
+    }
+    if splash.restoring && !made {
+        if ui.button("Proceed to load wallet").clicked() {
+            finalize_wallet(app_state, mne, false);
         }
+    } else if ui.button("I have written it down").clicked() {
+        splash.quiz = Some(Quiz::new(&mne));
     }
 }
 
-fn finalize_wallet(state: &mut WalletApp, mne: Mnemonic) {
+fn finalize_wallet(state: &mut WalletApp, mne: Mnemonic, backup_verified: bool) {
+    state.splash.quiz = None;
+    let backup = Some((bdk_utils::seed_fingerprint(&mne), backup_verified));
+    if !check_new_name(state) {
+        return;
+    }
     let wallet = bdk_utils::from_words(
         &state.settings.wallet_db,
         &state.splash.new_name,
         mne,
+        state.splash.account.script,
+        state.splash.account.index,
         state.splash.save_seed,
+        state.settings.network,
     );
+    match wallet {
+        Ok(wallet) => load_created(state, wallet, !state.splash.save_seed, backup),
+        Err(e) => state.splash.message = Some(format!("Unable to create wallet: {e}")),
+    }
+}
+
+/// Whether the name in the form can be used for a new wallet, showing why not when it can't
+fn check_new_name(state: &mut WalletApp) -> bool {
+    let dir = &state.settings.wallet_db;
+    let checked = registry::load(dir)
+        .and_then(|wallets| registry::check_name(dir, &wallets, &state.splash.new_name));
+    if let Err(e) = &checked {
+        state.splash.message = Some(format!("Unable to create wallet: {e}"));
+    }
+    checked.is_ok()
+}
+
+/// Create button for a wizard. A seed generated this session is hidden and its words asked
+/// back first, the backup of a seed typed in is left to check from the home page. Returns
+/// whether the backup was checked once the wallet should be created.
+fn wizard_create(
+    ui: &mut egui::Ui,
+    quiz: &mut Option<Quiz>,
+    mne: &Mnemonic,
+    made: bool,
+    label: &str,
+) -> Option<bool> {
+    let Some(q) = quiz else {
+        if !ui.button(label).clicked() {
+            return None;
+        }
+        if !made {
+            return Some(false);
+        }
+        *quiz = Some(Quiz::new(mne));
+        return None;
+    };
+    q.show(ui);
+    let passed = q.passed(mne);
+    let mut back = false;
+    let mut create = false;
+    ui.horizontal(|ui| {
+        back = ui.button("Show seed again").clicked();
+        create = ui.add_enabled(passed, egui::Button::new(label)).clicked();
+    });
+    if back {
+        *quiz = None;
+    }
+    create.then_some(true)
+}
+
+/// Register a newly created wallet and hand it to the background thread
+fn load_created(
+    state: &mut WalletApp,
+    wallet: PersistedWallet<Connection>,
+    watch_only: bool,
+    backup: Option<(Fingerprint, bool)>,
+) {
+    let db_file = register_created(state, &wallet, watch_only, backup);
     let wallet = CreatedWallet {
         wallet,
         name: state.splash.new_name.clone(),
+        db_file,
     };
     state.new_bg(wallet);
 }
 
+/// Add a newly created wallet to the registry, returning where its database is.
+/// `backup` is the seed the wallet's own key comes from and whether its backup was checked.
+fn register_created(
+    state: &mut WalletApp,
+    wallet: &PersistedWallet<Connection>,
+    watch_only: bool,
+    backup: Option<(Fingerprint, bool)>,
+) -> PathBuf {
+    let entry = WalletEntry::new(
+        &state.settings.wallet_db,
+        &state.splash.new_name,
+        wallet,
+        watch_only,
+        state.settings.backend,
+    );
+    let entry = match backup {
+        Some((seed, verified)) => entry.with_backup(seed, verified),
+        None => entry,
+    };
+    let db_file = entry.db_file.clone();
+    if let Err(e) = registry::register(&state.settings.wallet_db, entry) {
+        // the wallet opens next, so report it in the log shown on every page
+        state.debug.push(format!(
+            "Unable to register wallet, it won't be listed on the start page: {e}"
+        ));
+    }
+    db_file
+}
+
 fn xpub_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Enter xpub below:");
     ui.text_edit_singleline(&mut app_state.splash.new_1);
-    if &app_state.splash.new_1 != "" {
-        if ui.button("Proceed to load wallet").clicked() {
-            //
-        }
+    if !app_state.splash.new_1.is_empty() && ui.button("Proceed to load wallet").clicked() {
+        //
     }
 }
 
@@ -156,9 +656,645 @@ fn descriptor_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.text_edit_singleline(&mut app_state.splash.new_1);
     ui.heading("Enter change descriptor below:");
     ui.text_edit_singleline(&mut app_state.splash.new_2);
-    if &app_state.splash.new_1 != "" && &app_state.splash.new_2 != "" {
-        if ui.button("Proceed to load wallet").clicked() {
-            //
+    if !app_state.splash.new_name.is_empty()
+        && !app_state.splash.new_1.is_empty()
+        && !app_state.splash.new_2.is_empty()
+        && ui.button("Proceed to load wallet").clicked()
+    {
+        let (external, internal) = (
+            app_state.splash.new_1.trim().to_string(),
+            app_state.splash.new_2.trim().to_string(),
+        );
+        let network = app_state.settings.network;
+        if let Some((wallet, watch_only)) =
+            create_from_descriptors(app_state, &external, &internal, network)
+        {
+            load_created(app_state, wallet, watch_only, None);
+        }
+    }
+}
+
+/// Create the wallet named in the form, returning it and whether it is watch-only
+fn create_from_descriptors(
+    app_state: &mut WalletApp,
+    external: &str,
+    internal: &str,
+    network: Network,
+) -> Option<(PersistedWallet<Connection>, bool)> {
+    if !check_new_name(app_state) {
+        return None;
+    }
+    let dir = app_state.settings.wallet_db.clone();
+    let name = app_state.splash.new_name.clone();
+    match bdk_utils::from_descriptors(
+        &dir,
+        &name,
+        external,
+        internal,
+        app_state.splash.save_seed,
+        network,
+    ) {
+        Ok(wallet) => Some((wallet, !registry::files(&dir, &name).1.is_file())),
+        Err(e) => {
+            app_state.splash.message = Some(format!("Unable to create wallet: {e}"));
+            None
+        }
+    }
+}
+
+fn import_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    ui.heading("Wallet file to import:");
+    ui.label(
+        "Electrum wallet file, Sparrow descriptor export, Bitcoin Core listdescriptors \
+        output or JSON with descriptors.",
+    );
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut app_state.splash.new_1);
+        if ui.button("Read file").clicked() {
+            let parsed = std::fs::read_to_string(app_state.splash.new_1.trim())
+                .map_err(|e| e.to_string())
+                .and_then(|data| import::parse(&data));
+            if let Ok(wallet) = &parsed {
+                if let (Some(label), true) = (&wallet.label, app_state.splash.new_name.is_empty()) {
+                    app_state.splash.new_name = label.clone();
+                }
+                // start from a network the keys fit, the test networks share their keys
+                app_state.splash.import_network = match (wallet.network, app_state.settings.network)
+                {
+                    (Some(NetworkKind::Main), _) => Network::Bitcoin,
+                    (_, Network::Bitcoin) => Network::Testnet,
+                    (_, network) => network,
+                };
+            }
+            app_state.splash.import = Some(parsed);
+        }
+    });
+    let wallet = match &app_state.splash.import {
+        Some(Ok(wallet)) => wallet.clone(),
+        Some(Err(e)) => {
+            ui.colored_label(ui.visuals().error_fg_color, e);
+            return;
+        }
+        None => return,
+    };
+    egui::Grid::new("import").show(ui, |ui| {
+        ui.label("Format: ");
+        ui.label(wallet.format);
+        ui.end_row();
+        ui.label("Network: ");
+        ui.horizontal(|ui| {
+            for network in [
+                Network::Bitcoin,
+                Network::Testnet,
+                Network::Signet,
+                Network::Regtest,
+            ] {
+                ui.selectable_value(
+                    &mut app_state.splash.import_network,
+                    network,
+                    network.to_string(),
+                );
+            }
+        });
+        ui.end_row();
+        ui.label("Receive: ");
+        ui.monospace(&wallet.external);
+        ui.end_row();
+        ui.label("Change: ");
+        ui.monospace(&wallet.internal);
+        ui.end_row();
+    });
+    let network = app_state.splash.import_network;
+    if let Err(e) = wallet.check_network(network) {
+        ui.colored_label(ui.visuals().error_fg_color, e);
+        return;
+    }
+    if !app_state.splash.new_name.is_empty() && ui.button("Create wallet").clicked() {
+        if let Some((wallet, watch_only)) =
+            create_from_descriptors(app_state, &wallet.external, &wallet.internal, network)
+        {
+            load_created(app_state, wallet, watch_only, None);
+        }
+    }
+}
+
+/// Seed field of a wizard, hidden while its backup is being checked
+fn seed_input(ui: &mut egui::Ui, seed_gen: &mut SeedGen, seed: &mut String, quiz: &Option<Quiz>) {
+    if quiz.is_some() {
+        ui.label("Seed hidden while you check your backup");
+        return;
+    }
+    if let Some(mne) = seed_gen.show(ui) {
+        *seed = mne.to_string();
+    }
+    ui.text_edit_multiline(seed);
+}
+
+fn multisig_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    let network = app_state.settings.network;
+    let state = &mut app_state.splash.multisig;
+    ui.horizontal(|ui| {
+        ui.label("Script type: ");
+        ui.selectable_value(&mut state.script, MultisigScript::P2wsh, "P2WSH");
+        ui.selectable_value(&mut state.script, MultisigScript::P2tr, "P2TR");
+    });
+
+    ui.heading("Your key");
+    seed_input(
+        ui,
+        &mut app_state.splash.seed_gen,
+        &mut state.seed,
+        &state.quiz,
+    );
+    let mne = Mnemonic::parse(state.seed.trim()).ok();
+    let own = mne
+        .as_ref()
+        .map(|mne| OwnKey::new(mne, network, state.script));
+    if let Some(own) = &own {
+        ui.label("Share this key with the other cosigners:");
+        ui.horizontal(|ui| {
+            ui.monospace(&own.public);
+            if ui.button("Copy").clicked() {
+                ui.output_mut(|o| o.copied_text = own.public.clone());
+            }
+        });
+    } else if !state.seed.is_empty() {
+        ui.colored_label(ui.visuals().error_fg_color, "Invalid seed");
+    }
+
+    ui.heading("Cosigners");
+    let mut remove = None;
+    for (i, (_, key)) in state.cosigners.iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.monospace(key);
+            if ui.button("Remove").clicked() {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = remove {
+        state.cosigners.remove(i);
+    }
+    ui.horizontal(|ui| {
+        ui.label("[fingerprint/path]xpub: ");
+        ui.text_edit_singleline(&mut state.new_cosigner);
+        if ui.button("Add cosigner").clicked() {
+            match multisig::parse_cosigner(&state.new_cosigner) {
+                Ok(key) => {
+                    state.cosigners.push(key);
+                    state.new_cosigner.clear();
+                    state.error = None;
+                }
+                Err(e) => state.error = Some(e),
+            }
+        }
+    });
+
+    let total = state.cosigners.len() + 1;
+    ui.horizontal(|ui| {
+        ui.label("Signatures required: ");
+        ui.add(egui::DragValue::new(&mut state.threshold).clamp_range(1..=total));
+        ui.label(format!("of {total}"));
+    });
+    ui.horizontal(|ui| {
+        ui.label("BSMS file for cosigners: ");
+        if state.bsms_file.is_empty() {
+            let mut file = dirs::home_dir().unwrap_or_default();
+            file.push("multisig.bsms");
+            state.bsms_file = file.display().to_string();
         }
+        ui.text_edit_singleline(&mut state.bsms_file);
+    });
+    if let Some(e) = &state.error {
+        ui.colored_label(ui.visuals().error_fg_color, e);
+    }
+
+    let (Some(own), Some(mne)) = (own, mne) else {
+        return;
+    };
+    if app_state.splash.new_name.is_empty() {
+        return;
+    }
+    let made = app_state.splash.seed_gen.made(&mne);
+    let state = &mut app_state.splash.multisig;
+    let label = "Create multisig wallet";
+    let Some(verified) = wizard_create(ui, &mut state.quiz, &mne, made, label) else {
+        return;
+    };
+    let backup = Some((bdk_utils::seed_fingerprint(&mne), verified));
+    let (external, internal) =
+        match multisig::descriptors(state.script, state.threshold, &own, &state.cosigners) {
+            Ok(pair) => pair,
+            Err(e) => {
+                state.error = Some(e);
+                return;
+            }
+        };
+    let bsms_file = state.bsms_file.clone();
+    let Some((wallet, watch_only)) =
+        create_from_descriptors(app_state, &external, &internal, network)
+    else {
+        return;
+    };
+    app_state.splash.multisig.quiz = None;
+    let bsms = WalletExport::new(&wallet, &app_state.splash.new_name)
+        .bsms()
+        .expect("multisig wallets have a BSMS record");
+    if let Err(e) = std::fs::write(&bsms_file, bsms) {
+        // stay here so the cosigners aren't left without the record unnoticed
+        register_created(app_state, &wallet, watch_only, backup);
+        let dir = app_state.settings.wallet_db.clone();
+        app_state.splash.wallets = registry::load(&dir).unwrap_or_default();
+        app_state.splash.message = Some(format!(
+            "Created {} but could not write the BSMS file {bsms_file}: {e}. \
+            Load the wallet and copy the record from its Export page.",
+            app_state.splash.new_name
+        ));
+        return;
+    }
+    load_created(app_state, wallet, watch_only, backup);
+}
+
+fn policy_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    let network = app_state.settings.network;
+    let state = &mut app_state.splash.policy;
+    ui.heading("Spending policy");
+    ui.label(
+        "Keys are referred to by alias, as in pk(primary). \
+        older(n) waits n blocks after the coins confirm, after(n) until block n.",
+    );
+    ui.add(
+        egui::TextEdit::multiline(&mut state.policy)
+            .code_editor()
+            .desired_width(f32::INFINITY),
+    );
+    if ui.button("Reset to recovery template").clicked() {
+        state.policy = policy::RECOVERY_TEMPLATE.into();
+    }
+
+    ui.heading("Your key");
+    ui.horizontal(|ui| {
+        ui.label("Alias: ");
+        ui.text_edit_singleline(&mut state.own_alias);
+    });
+    seed_input(
+        ui,
+        &mut app_state.splash.seed_gen,
+        &mut state.seed,
+        &state.quiz,
+    );
+    // policies compile to P2WSH, so the key comes from the BIP48 P2WSH account the way a
+    // multisig cosigner's would, which hardware signers also offer for custom scripts
+    let mne = Mnemonic::parse(state.seed.trim()).ok();
+    let own = mne
+        .as_ref()
+        .map(|mne| OwnKey::new(mne, network, MultisigScript::P2wsh));
+    if state.own_alias.is_empty() && own.is_some() {
+        ui.colored_label(ui.visuals().error_fg_color, "Your key needs an alias");
+    }
+    if own.is_none() && !state.seed.is_empty() {
+        ui.colored_label(ui.visuals().error_fg_color, "Invalid seed");
+    }
+
+    ui.heading("Other keys");
+    let mut remove = None;
+    for (i, (alias, key)) in state.keys.iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(format!("{alias}: "));
+            ui.monospace(key);
+            if ui.button("Remove").clicked() {
+                remove = Some(i);
+            }
+        });
     }
+    if let Some(i) = remove {
+        state.keys.remove(i);
+    }
+    ui.horizontal(|ui| {
+        ui.label("Alias: ");
+        ui.add(egui::TextEdit::singleline(&mut state.new_alias).desired_width(80.));
+        ui.label("[fingerprint/path]xpub: ");
+        ui.text_edit_singleline(&mut state.new_key);
+        if ui.button("Add key").clicked() {
+            let alias = state.new_alias.trim().to_string();
+            let taken = alias == state.own_alias || state.keys.iter().any(|(a, _)| *a == alias);
+            match multisig::parse_cosigner(&state.new_key) {
+                _ if alias.is_empty() || taken => {
+                    state.error = Some("Every key needs its own alias".into())
+                }
+                Ok((_, key)) => {
+                    state.keys.push((alias, key));
+                    state.new_alias.clear();
+                    state.new_key.clear();
+                    state.error = None;
+                }
+                Err(e) => state.error = Some(e),
+            }
+        }
+    });
+    if let Some(e) = &state.error {
+        ui.colored_label(ui.visuals().error_fg_color, e);
+    }
+
+    let (Some(own), Some(mne)) = (own, mne) else {
+        return;
+    };
+    if app_state.splash.new_name.is_empty() {
+        return;
+    }
+    let made = app_state.splash.seed_gen.made(&mne);
+    let state = &mut app_state.splash.policy;
+    let label = "Create policy wallet";
+    let Some(verified) = wizard_create(ui, &mut state.quiz, &mne, made, label) else {
+        return;
+    };
+    let backup = Some((bdk_utils::seed_fingerprint(&mne), verified));
+    let mut keys = state.keys.clone();
+    keys.push((state.own_alias.trim().to_string(), own.public.clone()));
+    let (external, internal) = match policy::compile(&state.policy, &keys) {
+        Ok(pair) => pair,
+        Err(e) => {
+            state.error = Some(e);
+            return;
+        }
+    };
+    state.error = None;
+    let (external, internal) = (own.with_secret(&external), own.with_secret(&internal));
+    if let Some((wallet, watch_only)) =
+        create_from_descriptors(app_state, &external, &internal, network)
+    {
+        app_state.splash.policy.quiz = None;
+        load_created(app_state, wallet, watch_only, backup);
+    }
+}
+
+/// Wallets in registry order, with the accounts of a seed gathered where its first one is.
+/// Wallets that are not single key accounts of a seed, or are its only account, stand alone.
+fn seed_groups(wallets: &[WalletEntry]) -> Vec<(Option<Fingerprint>, Vec<&WalletEntry>)> {
+    let mut groups: Vec<(Option<Fingerprint>, Vec<&WalletEntry>)> = Vec::new();
+    let account_seed = |w: &WalletEntry| w.seed.filter(|_| w.account.is_some());
+    for entry in wallets {
+        let shared = account_seed(entry).filter(|fp| {
+            wallets
+                .iter()
+                .filter(|w| account_seed(w) == Some(*fp))
+                .count()
+                > 1
+        });
+        match groups
+            .iter_mut()
+            .find(|(seed, _)| shared.is_some() && *seed == shared)
+        {
+            Some((_, group)) => group.push(entry),
+            None => groups.push((shared, vec![entry])),
+        }
+    }
+    groups
+}
+
+/// Open the account form for a new account of `parent`'s seed, at the next free index
+fn start_account(splash: &mut SplashState, parent: String) {
+    let seed = splash
+        .wallets
+        .iter()
+        .find(|w| w.name == parent)
+        .and_then(|w| w.seed);
+    splash.account.index = splash
+        .wallets
+        .iter()
+        .filter(|w| seed.is_some() && w.seed == seed)
+        .filter_map(|w| w.account)
+        .max()
+        .map_or(0, |max| max + 1);
+    splash.account.parent = parent;
+    splash.account.error = None;
+    splash.selected_wallet = NEW_NAME.into();
+    splash.new_option = NewWallet::Account;
+    splash.save_seed = true;
+}
+
+fn account_opt(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    let state = &mut app_state.splash.account;
+    let parents: Vec<&WalletEntry> = app_state
+        .splash
+        .wallets
+        .iter()
+        .filter(|w| w.account.is_some() && w.keys_file.is_some() && w.exists())
+        .collect();
+    if parents.is_empty() {
+        ui.label("No signing wallet derived from a seed to add an account to.");
+        return;
+    }
+    egui::ComboBox::from_label("Seed of wallet")
+        .selected_text(&state.parent)
+        .show_ui(ui, |ui| {
+            for w in &parents {
+                ui.selectable_value(&mut state.parent, w.name.clone(), &w.name);
+            }
+        });
+    state.pick(ui);
+    if let Some(e) = &state.error {
+        ui.colored_label(ui.visuals().error_fg_color, e);
+    }
+    let Some(parent) = parents.iter().find(|w| w.name == state.parent) else {
+        return;
+    };
+    if app_state.splash.new_name.is_empty() || !ui.button("Create account").clicked() {
+        return;
+    }
+    let network = parent.network;
+    // accounts share the parent's seed and so its backup
+    let backup = parent.seed.zip(parent.backup_verified);
+    let xprv = parent
+        .keys_file
+        .as_ref()
+        .and_then(|f| std::fs::read_to_string(f).ok())
+        .and_then(|keys| bdk_utils::master_key(&keys));
+    let state = &mut app_state.splash.account;
+    let Some(xprv) = xprv else {
+        state.error = Some(format!("{} has no seed in its key file", state.parent));
+        return;
+    };
+    state.error = None;
+    let (external, internal) = bdk_utils::account_descriptors(xprv, state.script, state.index);
+    if let Some((wallet, watch_only)) =
+        create_from_descriptors(app_state, &external, &internal, network)
+    {
+        load_created(app_state, wallet, watch_only, backup);
+    }
+}
+
+fn restore(app_state: &mut WalletApp, ui: &mut egui::Ui) {
+    let state = &mut app_state.splash.restore;
+    egui::Grid::new("restore").show(ui, |ui| {
+        ui.label("Backup file: ");
+        ui.text_edit_singleline(&mut state.file);
+        ui.end_row();
+        ui.label("Passphrase: ");
+        ui.add(egui::TextEdit::singleline(&mut state.passphrase).password(true));
+        ui.end_row();
+        ui.label("Restore as: ");
+        ui.text_edit_singleline(&mut state.name);
+        ui.end_row();
+    });
+    ui.checkbox(&mut state.settings, "Also restore app settings");
+    let ready = !state.file.is_empty() && !state.passphrase.is_empty() && !state.name.is_empty();
+    if !ui
+        .add_enabled(ready, egui::Button::new("Restore wallet"))
+        .clicked()
+    {
+        return;
+    }
+    let dir = app_state.settings.wallet_db.clone();
+    let state = std::mem::take(&mut app_state.splash.restore);
+    let name = state.name.trim();
+    match bundle::restore(&dir, Path::new(&state.file), &state.passphrase, name) {
+        Ok(bundle) => {
+            if state.settings {
+                // the wallet folder is local to this machine
+                app_state.settings = bundle.settings;
+                app_state.settings.wallet_db = dir.clone();
+                app_state.settings.save();
+            }
+            app_state.splash.message = Some(format!("Restored {name}"));
+        }
+        Err(e) => {
+            app_state.splash.message = Some(format!("Restore failed: {e}"));
+            // keep what was typed so it can be corrected
+            app_state.splash.restore = RestoreState {
+                passphrase: String::new(),
+                ..state
+            };
+        }
+    }
+    match registry::load(&dir) {
+        Ok(wallets) => app_state.splash.wallets = wallets,
+        Err(e) => app_state.splash.error = Some(e),
+    }
+}
+
+enum CardEvent {
+    Load,
+    /// The pending manage action on this wallet was confirmed
+    Confirm,
+    AddAccount,
+}
+
+/// Run the confirmed manage action and refresh the wallet list
+fn manage_wallet(app_state: &mut WalletApp) {
+    let Some(manage) = app_state.splash.manage.take() else {
+        return;
+    };
+    let dir = &app_state.settings.wallet_db;
+    let (name, input) = (&manage.wallet, manage.input.trim());
+    let result = match manage.action {
+        Manage::Rename => {
+            registry::rename(dir, name, input).map(|_| format!("Renamed {name} to {input}"))
+        }
+        Manage::Duplicate => registry::duplicate_watch_only(dir, name, input)
+            .map(|_| format!("Created watch-only copy {input}")),
+        Manage::Delete => registry::delete(dir, name)
+            .map(|backup| format!("Deleted {name}, files moved to {}", backup.display())),
+    };
+    app_state.splash.message = Some(result.unwrap_or_else(|e| e));
+    match registry::load(dir) {
+        Ok(wallets) => app_state.splash.wallets = wallets,
+        Err(e) => app_state.splash.error = Some(e),
+    }
+}
+
+/// Summary of a registered wallet with its load and manage actions
+fn wallet_card(
+    ui: &mut egui::Ui,
+    entry: &WalletEntry,
+    manage: &mut Option<ManageState>,
+) -> Option<CardEvent> {
+    let mut event = None;
+    egui::Frame::group(ui.style()).show(ui, |ui| {
+        ui.set_min_width(300.);
+        ui.horizontal(|ui| {
+            ui.strong(&entry.name);
+            ui.label(entry.network.to_string());
+        });
+        let kind = if entry.watch_only {
+            "watch-only"
+        } else {
+            "signing"
+        };
+        ui.label(format!(
+            "{} · {kind} · {:?}",
+            entry.script_type, entry.backend
+        ));
+        ui.label(format!(
+            "Created {}",
+            registry::format_date(entry.created_at)
+        ));
+        if let Some(account) = entry.account {
+            ui.label(format!("Account #{account}"));
+        }
+        ui.small(entry.db_file.display().to_string());
+        if !entry.exists() {
+            ui.colored_label(egui::Color32::RED, "Wallet file is missing");
+        }
+        ui.horizontal(|ui| {
+            let mut start = |action| {
+                *manage = Some(ManageState {
+                    wallet: entry.name.clone(),
+                    action,
+                    input: String::new(),
+                })
+            };
+            if entry.exists() {
+                if ui.button("Load wallet").clicked() {
+                    event = Some(CardEvent::Load);
+                }
+                if ui.button("Rename").clicked() {
+                    start(Manage::Rename);
+                }
+                if !entry.watch_only && ui.button("Duplicate as watch-only").clicked() {
+                    start(Manage::Duplicate);
+                }
+                if entry.account.is_some()
+                    && entry.keys_file.is_some()
+                    && ui.button("Add account").clicked()
+                {
+                    event = Some(CardEvent::AddAccount);
+                }
+            }
+            if ui.button("Delete").clicked() {
+                start(Manage::Delete);
+            }
+        });
+
+        let Some(state) = manage.as_mut().filter(|m| m.wallet == entry.name) else {
+            return;
+        };
+        let (prompt, ready) = match state.action {
+            Manage::Rename => ("New name:", !state.input.trim().is_empty()),
+            Manage::Duplicate => ("Watch-only copy name:", !state.input.trim().is_empty()),
+            Manage::Delete => {
+                ui.label("The wallet files will be moved to the backups folder.");
+                (
+                    "Type the wallet name to confirm:",
+                    state.input == entry.name,
+                )
+            }
+        };
+        let mut cancel = false;
+        ui.horizontal(|ui| {
+            ui.label(prompt);
+            ui.text_edit_singleline(&mut state.input);
+            if ui
+                .add_enabled(ready, egui::Button::new("Confirm"))
+                .clicked()
+            {
+                event = Some(CardEvent::Confirm);
+            }
+            cancel = ui.button("Cancel").clicked();
+        });
+        if cancel {
+            *manage = None;
+        }
+    });
+    event
 }
@@ -4,6 +4,8 @@ pub fn page(app_state: &mut WalletApp, ui: &mut egui::Ui) {
     ui.heading("Transactions");
     ui.horizontal(|ui| {
         ui.label("Next receive address: ");
-        ui.label(format!("{}", app_state.receive.derivation));
+        if let Some(addr) = app_state.receive.next_addr.first() {
+            ui.label(addr.address.to_string());
+        }
     });
 }
@@ -1,198 +1,449 @@
 use bdk_wallet::{
     bitcoin::{
-        key::rand::{thread_rng, Rng},
+        bip32::{Fingerprint, Xpriv},
+        key::rand::{seq::index, thread_rng},
+        secp256k1::Secp256k1,
         Network, Transaction, Txid,
     },
     keys::{bip39::Mnemonic, DerivableKey, ExtendedKey},
-    template::Bip84,
+    miniscript::{
+        descriptor::{DescriptorSecretKey, KeyMap},
+        Descriptor,
+    },
     Balance, KeychainKind, PersistedWallet, Wallet,
 };
 
-use bdk_electrum::electrum_client;
-use bdk_electrum::BdkElectrumClient;
+use crate::{
+    app::settings::WalletSettings,
+    chain::{ChainError, ChainSource},
+};
 use bdk_wallet::rusqlite::Connection;
 use std::{
-    io::{LineWriter, Read, Write},
-    path::PathBuf,
+    io::{LineWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
-const STOP_GAP: usize = 50;
-const BATCH_SIZE: usize = 5;
+pub const STOP_GAP: usize = 50;
+pub const BATCH_SIZE: usize = 5;
 
-pub fn broadcast_tx(tx: &Transaction, elec_url: &str) -> Result<Txid, String> {
-    let client = BdkElectrumClient::new(electrum_client::Client::new(elec_url).unwrap());
+const SETTINGS_TABLE: &str = "seashell_wallet_settings";
 
-    client.transaction_broadcast(tx).map_err(|e| e.to_string())
+/// Read the scan settings saved in a wallet database, falling back to the defaults
+pub fn load_wallet_settings(db: &Connection) -> WalletSettings {
+    let stored: Option<String> = db
+        .query_row(
+            &format!("SELECT settings FROM {SETTINGS_TABLE} WHERE id = 0"),
+            [],
+            |row| row.get(0),
+        )
+        .ok();
+    stored
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
 }
 
-pub fn list_wallets(db_path: &str) -> Vec<String> {
-    let files = std::fs::read_dir(db_path).unwrap();
-    files
-        .into_iter()
-        .filter_map(|f| {
-            if let Ok(entry) = f {
-                return Some(entry.file_name().into_string().unwrap());
-            };
-            None
-        })
-        .filter(|f| !f.contains("keys"))
-        .collect()
+pub fn save_wallet_settings(
+    db: &Connection,
+    settings: &WalletSettings,
+) -> bdk_wallet::rusqlite::Result<()> {
+    db.execute(
+        &format!("CREATE TABLE IF NOT EXISTS {SETTINGS_TABLE} (id INTEGER PRIMARY KEY, settings TEXT NOT NULL)"),
+        [],
+    )?;
+    db.execute(
+        &format!("INSERT OR REPLACE INTO {SETTINGS_TABLE} (id, settings) VALUES (0, ?1)"),
+        [serde_json::to_string(settings).expect("settings always serialize")],
+    )?;
+    Ok(())
 }
 
-pub fn from_changeset(db_path: &str, name: &str) -> Result<PersistedWallet<Connection>, bool> {
-    let mut path = PathBuf::from(db_path);
-    path.push(name);
-    let mut db = Connection::open(&path).unwrap();
-    let kpath = String::from(name) + "_keys";
-    path.set_file_name(kpath);
-    let wallet = if let Ok(mut f) = std::fs::File::open(path) {
-        let wallet = Wallet::load().extract_keys();
-        let mut tprv = String::new();
-        f.read_to_string(&mut tprv);
-        let mut keys = tprv.lines();
-        let extkey: String = keys.next().unwrap().to_owned();
-        let intkey: String = keys.next().unwrap().to_owned();
-        wallet
-            .descriptor(KeychainKind::Internal, Some(intkey))
-            .descriptor(KeychainKind::External, Some(extkey))
-    } else {
-        Wallet::load()
-    };
-    let wallet = wallet.load_wallet(&mut db);
-    match wallet {
-        Ok(w) => match w {
-            Some(mut w) => {
-                return Ok(w);
-            }
-            None => Err(false),
-        },
-        Err(e) => {
-            println!("{:?}", e);
-            Err(false)
+pub fn broadcast_tx(tx: &Transaction, source: &mut dyn ChainSource) -> Result<Txid, ChainError> {
+    source.broadcast(tx)
+}
+
+/// Open the wallet stored in `db_file`, with the signing keys from `keys_file` when it has one
+pub fn load_wallet(
+    db_file: &Path,
+    keys_file: Option<&Path>,
+) -> Result<PersistedWallet<Connection>, String> {
+    let mut db = Connection::open(db_file).map_err(|e| e.to_string())?;
+    let lookahead = load_wallet_settings(&db).lookahead;
+    let mut wallet = Wallet::load()
+        .lookahead(lookahead)
+        .load_wallet(&mut db)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("{} is not a wallet database", db_file.display()))?;
+    if let Some(path) = keys_file {
+        // a wallet opened without its keys couldn't sign, so don't open it at all
+        let keys = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        for (kc, line) in [KeychainKind::External, KeychainKind::Internal]
+            .into_iter()
+            .zip(keys.lines())
+        {
+            let keymap = key_map(line)
+                .map_err(|e| format!("Unable to read {kc:?} key from {}: {e}", path.display()))?;
+            wallet.set_keymap(kc, keymap);
         }
     }
+    Ok(wallet)
+}
+
+/// Signing keys from one line of a `_keys` file, either a private descriptor or the bare
+/// extended private key that older versions wrote
+fn key_map(line: &str) -> Result<KeyMap, String> {
+    let secp = Secp256k1::new();
+    if let Ok((_, keymap)) = Descriptor::parse_descriptor(&secp, line) {
+        return Ok(keymap);
+    }
+    let secret = DescriptorSecretKey::from_str(line.trim()).map_err(|e| e.to_string())?;
+    let public = secret.to_public(&secp).map_err(|e| e.to_string())?;
+    Ok(KeyMap::from([(public, secret)]))
 }
 
-/// Create a wallet that is persisted to SQLite database.
-pub fn new_seed() -> Mnemonic {
-    // Create a new random number generator
-    let mut rng = thread_rng();
-    // Generate 256 bits of entropy
-    let entropy: [u8; 32] = rng.gen();
-    let words = Mnemonic::from_entropy(&entropy).unwrap();
-    // Create extended key to generate descriptors
-    words
+/// Single key script types, each with its own BIP44 style derivation purpose
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AccountScript {
+    Bip44,
+    Bip49,
+    Bip84,
+    Bip86,
 }
 
+impl AccountScript {
+    pub const ALL: [AccountScript; 4] = [
+        AccountScript::Bip44,
+        AccountScript::Bip49,
+        AccountScript::Bip84,
+        AccountScript::Bip86,
+    ];
+
+    pub fn purpose(self) -> u32 {
+        match self {
+            AccountScript::Bip44 => 44,
+            AccountScript::Bip49 => 49,
+            AccountScript::Bip84 => 84,
+            AccountScript::Bip86 => 86,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AccountScript::Bip44 => "Legacy (BIP44)",
+            AccountScript::Bip49 => "Nested segwit (BIP49)",
+            AccountScript::Bip84 => "Native segwit (BIP84)",
+            AccountScript::Bip86 => "Taproot (BIP86)",
+        }
+    }
+}
+
+/// Receive and change descriptors for `account` of a master key, written the way bdk's
+/// `Bip84` template writes account 0
+pub fn account_descriptors(xprv: Xpriv, script: AccountScript, account: u32) -> (String, String) {
+    let coin = if xprv.network.is_mainnet() { 0 } else { 1 };
+    let desc = |branch: u32| {
+        let key = format!("{xprv}/{}'/{coin}'/{account}'/{branch}/*", script.purpose());
+        match script {
+            AccountScript::Bip44 => format!("pkh({key})"),
+            AccountScript::Bip49 => format!("sh(wpkh({key}))"),
+            AccountScript::Bip84 => format!("wpkh({key})"),
+            AccountScript::Bip86 => format!("tr({key})"),
+        }
+    };
+    (desc(0), desc(1))
+}
+
+/// Master key of a wallet's `_keys` file, when its descriptors are derived from a seed
+pub fn master_key(keys: &str) -> Option<Xpriv> {
+    let keymap = key_map(keys.lines().next()?).ok()?;
+    keymap.into_values().find_map(|key| match key {
+        DescriptorSecretKey::XPrv(x) if x.origin.is_none() && x.xkey.depth == 0 => Some(x.xkey),
+        _ => None,
+    })
+}
+
+/// Master fingerprint of a seed, to match a phrase against the wallets made from it
+pub fn seed_fingerprint(words: &Mnemonic) -> Fingerprint {
+    Xpriv::new_master(Network::Bitcoin, &words.to_seed(""))
+        .expect("seed has a valid length")
+        .fingerprint(&Secp256k1::new())
+}
+
+/// `count` distinct word positions, in order, to check a written seed backup with
+pub fn quiz_positions(words: usize, count: usize) -> Vec<usize> {
+    let mut positions = index::sample(&mut thread_rng(), words, count.min(words)).into_vec();
+    positions.sort();
+    positions
+}
+
+/// Create a wallet for `account` of a seed, see `from_descriptors`
 pub fn from_words(
     db_path: &str,
     name: &str,
     words: Mnemonic,
+    script: AccountScript,
+    account: u32,
     save_seed: bool,
-) -> PersistedWallet<Connection> {
+    network: Network,
+) -> Result<PersistedWallet<Connection>, String> {
+    let xkey: ExtendedKey = words.into_extended_key().map_err(|e| e.to_string())?;
+    let xprv = xkey.into_xprv(network).ok_or("seed has no private key")?;
+    let (external, internal) = account_descriptors(xprv, script, account);
+    from_descriptors(db_path, name, &external, &internal, save_seed, network)
+}
+
+/// Write the private descriptors to the `_keys` file read by `load_wallet`
+fn save_keys(path: PathBuf, wallet: &Wallet) -> std::io::Result<()> {
+    let f = std::fs::File::create(path)?;
+    let mut lr = LineWriter::new(f);
+
+    for kc in [KeychainKind::External, KeychainKind::Internal] {
+        let keymap = wallet.get_signers(kc).as_key_map(wallet.secp_ctx());
+        let desc = wallet.public_descriptor(kc).to_string_with_secret(&keymap);
+        lr.write_all(format!("{desc}\n").as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Create a wallet from a pair of descriptors.
+/// Private keys in the descriptors are only kept on disk when `save_keys_file` is set.
+pub fn from_descriptors(
+    db_path: &str,
+    name: &str,
+    external: &str,
+    internal: &str,
+    save_keys_file: bool,
+    network: Network,
+) -> Result<PersistedWallet<Connection>, String> {
+    std::fs::create_dir_all(db_path).map_err(|e| e.to_string())?;
     let mut path = PathBuf::from(db_path);
     path.push(name);
-    let mut db = Connection::open(&path).unwrap();
-    let xkey: ExtendedKey = words.into_extended_key().unwrap();
-    let xprv = xkey.into_xprv(Network::Testnet).unwrap();
-    let wallet = Wallet::create(
-        Bip84(xprv.clone(), KeychainKind::External),
-        Bip84(xprv.clone(), KeychainKind::Internal),
-    )
-    .network(Network::Testnet)
-    .create_wallet(&mut db)
-    .unwrap();
-
-    if save_seed {
-        let keys_name = String::from(name) + "_keys";
-        path.set_file_name(keys_name);
-
-        let f = std::fs::File::create(path).unwrap();
-        let mut lr = LineWriter::new(f);
-
-        let mut bs = Vec::new();
-        for ele in wallet.get_signers(KeychainKind::External).signers() {
-            let test = ele.descriptor_secret_key().unwrap().to_string();
-            bs.push(format!("{test}\n"));
-        }
-        for ele in wallet.get_signers(KeychainKind::Internal).signers() {
-            let test = ele.descriptor_secret_key().unwrap().to_string();
-            bs.push(format!("{test}\n"));
+    if path.exists() {
+        return Err(format!("A wallet named \"{name}\" already exists"));
+    }
+    let mut db = Connection::open(&path).map_err(|e| e.to_string())?;
+    let wallet = Wallet::create(external.to_string(), internal.to_string())
+        .network(network)
+        .create_wallet(&mut db);
+    let wallet = match wallet {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            drop(db);
+            let _ = std::fs::remove_file(&path);
+            return Err(e.to_string());
         }
-
-        bs.iter().for_each(|key| {
-            lr.write(key.as_bytes()).unwrap();
-        });
+    };
+    let has_keys = [KeychainKind::External, KeychainKind::Internal]
+        .iter()
+        .any(|kc| !wallet.get_signers(*kc).signers().is_empty());
+    if save_keys_file && has_keys {
+        path.set_file_name(String::from(name) + "_keys");
+        save_keys(path, &wallet).map_err(|e| e.to_string())?;
     }
-
-    wallet
+    Ok(wallet)
 }
 
 pub fn cp_sync(
-    db_path: &str,
-    name: &str,
+    db: &mut Connection,
     wallet: &mut PersistedWallet<Connection>,
-    elec_url: &str,
-) -> Balance {
-    let client = BdkElectrumClient::new(electrum_client::Client::new(elec_url).unwrap());
-    let sync_request = wallet.start_sync_with_revealed_spks().build();
-    let update = client.sync(sync_request, BATCH_SIZE, true).unwrap();
+    source: &mut dyn ChainSource,
+    settings: &WalletSettings,
+) -> Result<Balance, ChainError> {
+    source.sync(wallet, settings.batch_size)?;
+    persist(db, wallet);
 
-    // Apply the update to the wallet
-    wallet.apply_update(update).unwrap();
-    persist(db_path, name, wallet);
-    let balance = wallet.balance();
-    balance
+    Ok(wallet.balance())
 }
 
 pub fn full_scan(
-    db_path: &str,
-    name: &str,
+    db: &mut Connection,
     wallet: &mut PersistedWallet<Connection>,
-    elec_url: &str,
-) -> Balance {
-    let client = BdkElectrumClient::new(electrum_client::Client::new(elec_url).unwrap());
-
+    source: &mut dyn ChainSource,
+    settings: &WalletSettings,
+) -> Result<Balance, ChainError> {
     // Perform the initial full scan on the wallet
-    let full_scan_request = wallet.start_full_scan().build();
-    let update = client
-        .full_scan(full_scan_request, STOP_GAP, BATCH_SIZE, true)
-        .unwrap();
+    source.full_scan(wallet, settings.stop_gap, settings.batch_size)?;
+    persist(db, wallet);
 
-    wallet.apply_update(update).unwrap();
-    persist(db_path, name, wallet);
-    let balance = wallet.balance();
-    balance
+    Ok(wallet.balance())
 }
 
-pub fn persist(db_path: &str, name: &str, wallet: &mut PersistedWallet<Connection>) {
-    let mut path = PathBuf::from(db_path);
-    path.push(name);
-    let mut db = Connection::open(&path).unwrap();
-    wallet.persist(&mut db).expect("persist error");
+pub fn open_db(db_file: &Path) -> Connection {
+    Connection::open(db_file).unwrap()
+}
+
+pub fn persist(db: &mut Connection, wallet: &mut PersistedWallet<Connection>) {
+    wallet.persist(db).expect("persist error");
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const SEED: &str = "section attitude true fabric foam ribbon chaos cradle ordinary venture fat ensure winter skate error glove pulse dolphin they cable verify wolf rain ribbon";
+
     #[test]
     fn test_wallet() {
         let words = "section attitude true fabric foam ribbon chaos cradle ordinary venture fat ensure winter skate error glove pulse dolphin they cable verify wolf rain ribbon";
         let mne = Mnemonic::parse(words).unwrap();
 
-        let mut p = PathBuf::from("./tests");
-        p.push("tw");
-        let _ = std::fs::remove_file(p);
-        from_words("./tests/", "tw", mne, true);
-        assert!(true);
+        let dir = crate::test_dir();
+        from_words(
+            dir.path().to_str().unwrap(),
+            "tw",
+            mne,
+            AccountScript::Bip84,
+            0,
+            true,
+            Network::Testnet,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn keys_file_format() {
+        let tmp = crate::test_dir();
+        let dir = tmp.path();
+        let mne = Mnemonic::parse(SEED).unwrap();
+        let d = dir.to_str().unwrap();
+        from_words(
+            d,
+            "tw",
+            mne.clone(),
+            AccountScript::Bip84,
+            0,
+            true,
+            Network::Testnet,
+        )
+        .unwrap();
+        // an existing wallet is never overwritten
+        assert!(from_words(
+            d,
+            "tw",
+            mne,
+            AccountScript::Bip84,
+            1,
+            true,
+            Network::Testnet
+        )
+        .is_err());
+
+        let keys = std::fs::read_to_string(dir.join("tw_keys")).unwrap();
+        assert!(keys.lines().all(|l| l.starts_with("wpkh(tprv")));
+        // the fixture holds the bare keys older versions wrote, both load with signers
+        let new = crate::load_test_wallet(d, "tw").unwrap();
+        let old = crate::load_test_wallet("./tests/", "tw").unwrap();
+        for w in [new, old] {
+            assert!(!w.get_signers(KeychainKind::External).signers().is_empty());
+            assert!(!w.get_signers(KeychainKind::Internal).signers().is_empty());
+        }
+        // keys that can't be read stop the wallet from opening without its signers
+        std::fs::write(dir.join("tw_keys"), "wpkh(nope)\n").unwrap();
+        assert!(crate::load_test_wallet(d, "tw").is_err());
+        std::fs::remove_file(dir.join("tw_keys")).unwrap();
+        assert!(load_wallet(&dir.join("tw"), Some(&dir.join("tw_keys"))).is_err());
+    }
+
+    /// Private receive and change descriptors of the `tests/tw` fixture
+    fn fixture_descriptors() -> (String, String) {
+        let w = crate::load_test_wallet("./tests/", "tw").unwrap();
+        let desc = |kc| {
+            let keymap = w.get_signers(kc).as_key_map(w.secp_ctx());
+            w.public_descriptor(kc).to_string_with_secret(&keymap)
+        };
+        (desc(KeychainKind::External), desc(KeychainKind::Internal))
+    }
+
+    #[test]
+    fn seed_accounts() {
+        let keys = std::fs::read_to_string("tests/tw_keys").unwrap();
+        let xprv = master_key(&keys).unwrap();
+        // account 0 of BIP84 matches the descriptors the template wrote
+        let (ext, _) = account_descriptors(xprv, AccountScript::Bip84, 0);
+        assert_eq!(ext, fixture_descriptors().0.split('#').next().unwrap());
+
+        let dir = crate::test_dir();
+        let d = dir.path().to_str().unwrap();
+        let (ext, int) = account_descriptors(xprv, AccountScript::Bip86, 3);
+        let wallet = from_descriptors(d, "taproot", &ext, &int, true, Network::Testnet).unwrap();
+        let public = wallet.public_descriptor(KeychainKind::External);
+        let fingerprint = xprv.fingerprint(&Secp256k1::new());
+        assert_eq!(crate::registry::account_of(public), Some((fingerprint, 3)));
+        assert!(public
+            .to_string()
+            .starts_with(&format!("tr([{fingerprint}/86'/1'/3']")));
+        assert!(master_key("wpkh(tpubD6NzVbkrYhZ4X/0/*)").is_none());
+
+        let entry =
+            crate::registry::WalletEntry::new(d, "taproot", &wallet, false, Default::default());
+        assert_eq!(
+            crate::registry::balance(&entry),
+            Ok(bdk_wallet::bitcoin::Amount::ZERO)
+        );
+    }
+
+    #[test]
+    fn backup_quiz() {
+        let mne = Mnemonic::parse(SEED).unwrap();
+        let keys = std::fs::read_to_string("tests/tw_keys").unwrap();
+        let xprv = master_key(&keys).unwrap();
+        assert_eq!(seed_fingerprint(&mne), xprv.fingerprint(&Secp256k1::new()));
+        let positions = quiz_positions(24, 3);
+        assert!(positions.len() == 3 && positions.windows(2).all(|p| p[0] < p[1] && p[1] < 24));
+        assert_eq!(quiz_positions(12, 30).len(), 12);
     }
 
     #[test]
     fn from_tprv() {
-        let w = from_changeset("./tests/", "tw").unwrap();
+        let w = crate::load_test_wallet("./tests/", "tw").unwrap();
         w.keychains().for_each(|kc| {
             println!("keychain:{:?}", kc);
         });
     }
+
+    #[test]
+    fn descriptor_wallets() {
+        let tmp = crate::test_dir();
+        let dir = tmp.path();
+        let d = dir.to_str().unwrap();
+        let (ext, int) = fixture_descriptors();
+        let (ext, int) = (ext.as_str(), int.as_str());
+
+        let signing = from_descriptors(d, "signing", ext, int, true, Network::Testnet).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("signing_keys")).unwrap(),
+            format!("{ext}\n{int}\n")
+        );
+        assert!(from_descriptors(d, "signing", ext, int, true, Network::Testnet).is_err());
+
+        let public = signing
+            .public_descriptor(KeychainKind::External)
+            .to_string();
+        let change = signing
+            .public_descriptor(KeychainKind::Internal)
+            .to_string();
+        from_descriptors(d, "watch", &public, &change, true, Network::Testnet).unwrap();
+        assert!(!dir.join("watch_keys").exists());
+        assert!(crate::load_test_wallet(d, "watch").is_ok());
+
+        assert!(
+            from_descriptors(d, "bad", "wpkh(nope)", &change, false, Network::Testnet).is_err()
+        );
+        assert!(!dir.join("bad").exists());
+    }
+
+    #[test]
+    fn wallet_settings_round_trip() {
+        let db = Connection::open_in_memory().unwrap();
+        assert_eq!(load_wallet_settings(&db), WalletSettings::default());
+
+        let settings = WalletSettings {
+            stop_gap: 500,
+            batch_size: 20,
+            lookahead: 1000,
+        };
+        save_wallet_settings(&db, &settings).unwrap();
+        assert_eq!(load_wallet_settings(&db), settings);
+    }
 }
@@ -0,0 +1,206 @@
+use std::{fmt, str::FromStr};
+
+use bdk_wallet::bitcoin::{address::NetworkUnchecked, Address, Amount, Denomination, Network};
+
+const SCHEME: &str = "bitcoin:";
+
+/// A BIP21 `bitcoin:` payment URI
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bip21Uri {
+    pub address: Address,
+    pub amount: Option<Amount>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bip21Error {
+    Address(String),
+    WrongNetwork,
+    Amount(String),
+    Encoding(String),
+    DuplicateParam(String),
+    /// A `req-` parameter we don't understand, the URI must be refused
+    UnknownRequired(String),
+}
+
+impl fmt::Display for Bip21Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bip21Error::Address(e) => write!(f, "Invalid address: {e}"),
+            Bip21Error::WrongNetwork => write!(f, "Address is for a different network"),
+            Bip21Error::Amount(e) => write!(f, "Invalid amount: {e}"),
+            Bip21Error::Encoding(p) => write!(f, "Invalid percent encoding in {p}"),
+            Bip21Error::DuplicateParam(p) => write!(f, "Parameter {p} given more than once"),
+            Bip21Error::UnknownRequired(p) => write!(f, "Unsupported required parameter {p}"),
+        }
+    }
+}
+
+impl Bip21Uri {
+    pub fn new(address: Address) -> Self {
+        Bip21Uri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+        }
+    }
+
+    /// Parse either a full `bitcoin:` URI or a bare address
+    pub fn parse(input: &str, network: Network) -> Result<Self, Bip21Error> {
+        let input = input.trim();
+        let rest = match input.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &input[SCHEME.len()..],
+            _ => input,
+        };
+        let (addr, query) = match rest.split_once('?') {
+            Some((addr, query)) => (addr, Some(query)),
+            None => (rest, None),
+        };
+
+        let address = Address::<NetworkUnchecked>::from_str(addr)
+            .map_err(|e| Bip21Error::Address(e.to_string()))?
+            .require_network(network)
+            .map_err(|_| Bip21Error::WrongNetwork)?;
+        let mut uri = Bip21Uri::new(address);
+
+        for param in query.unwrap_or_default().split('&') {
+            if param.is_empty() {
+                continue;
+            }
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode(value).ok_or(Bip21Error::Encoding(key.into()))?;
+            match key {
+                "amount" => {
+                    let amount = Amount::from_str_in(&value, Denomination::Bitcoin)
+                        .map_err(|e| Bip21Error::Amount(e.to_string()))?;
+                    set_once(&mut uri.amount, amount, key)?;
+                }
+                "label" => set_once(&mut uri.label, value, key)?,
+                "message" => set_once(&mut uri.message, value, key)?,
+                k if k.starts_with("req-") => return Err(Bip21Error::UnknownRequired(k.into())),
+                // optional parameters we don't know can be ignored
+                _ => {}
+            }
+        }
+
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for Bip21Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{SCHEME}{}", self.address)?;
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!(
+                "amount={}",
+                amount.to_string_in(Denomination::Bitcoin)
+            ));
+        }
+        if let Some(label) = &self.label {
+            params.push(format!("label={}", percent_encode(label)));
+        }
+        if let Some(message) = &self.message {
+            params.push(format!("message={}", percent_encode(message)));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+fn set_once<T>(field: &mut Option<T>, value: T, key: &str) -> Result<(), Bip21Error> {
+    if field.is_some() {
+        return Err(Bip21Error::DuplicateParam(key.into()));
+    }
+    *field = Some(value);
+    Ok(())
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    #[test]
+    fn parse_full_uri() {
+        let uri = format!(
+            "bitcoin:{ADDR}?amount=0.0005&label=Luke%20Jr&message=Donation%20for%20project%20xyz"
+        );
+        let parsed = Bip21Uri::parse(&uri, Network::Testnet).unwrap();
+        assert_eq!(parsed.address.to_string(), ADDR);
+        assert_eq!(parsed.amount, Some(Amount::from_sat(50_000)));
+        assert_eq!(parsed.label.as_deref(), Some("Luke Jr"));
+        assert_eq!(parsed.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(parsed.to_string(), uri);
+    }
+
+    #[test]
+    fn parse_bare_address() {
+        let parsed = Bip21Uri::parse(ADDR, Network::Testnet).unwrap();
+        assert_eq!(parsed, Bip21Uri::new(parsed.address.clone()));
+    }
+
+    #[test]
+    fn refuse_unknown_required() {
+        let uri = format!("bitcoin:{ADDR}?req-somethingyoudontunderstand=50");
+        assert_eq!(
+            Bip21Uri::parse(&uri, Network::Testnet),
+            Err(Bip21Error::UnknownRequired(
+                "req-somethingyoudontunderstand".into()
+            ))
+        );
+        let uri = format!("bitcoin:{ADDR}?somethingyoudontunderstand=50");
+        assert!(Bip21Uri::parse(&uri, Network::Testnet).is_ok());
+    }
+
+    #[test]
+    fn reject_bad_params() {
+        let uri = format!("bitcoin:{ADDR}?amount=1&amount=2");
+        assert!(matches!(
+            Bip21Uri::parse(&uri, Network::Testnet),
+            Err(Bip21Error::DuplicateParam(_))
+        ));
+        let uri = format!("bitcoin:{ADDR}?amount=abc");
+        assert!(matches!(
+            Bip21Uri::parse(&uri, Network::Testnet),
+            Err(Bip21Error::Amount(_))
+        ));
+        assert_eq!(
+            Bip21Uri::parse(ADDR, Network::Bitcoin),
+            Err(Bip21Error::WrongNetwork)
+        );
+    }
+}
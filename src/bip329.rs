@@ -0,0 +1,132 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Kind of object a BIP329 label refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+/// Written the way serde names it, so the database and the JSON export agree
+impl fmt::Display for LabelType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(s)) => f.write_str(&s),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+impl FromStr for LabelType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.into()))
+            .map_err(|_| format!("unknown label type {s}"))
+    }
+}
+
+/// A single BIP329 label record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub kind: LabelType,
+    /// Txid, address, outpoint (`txid:vout`), pubkey or xpub being labeled
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Only meaningful for outputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    pub fn new(kind: LabelType, reference: String, label: String) -> Self {
+        Label {
+            kind,
+            reference,
+            label,
+            origin: None,
+            spendable: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImportError {
+    pub line: usize,
+    pub error: String,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+/// Parse a BIP329 JSON lines export. Blank lines are skipped.
+pub fn import(data: &str) -> Result<Vec<Label>, ImportError> {
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| ImportError {
+                line: i + 1,
+                error: e.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Write labels in the BIP329 JSON lines format
+pub fn export(labels: &[Label]) -> String {
+    labels
+        .iter()
+        .map(|l| serde_json::to_string(l).expect("label always serializes") + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"{ "type": "tx", "ref": "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd", "label": "Transaction", "origin": "wpkh([d34db33f/84'/0'/0'])" }
+{ "type": "addr", "ref": "bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c", "label": "Address" }
+
+{ "type": "output", "ref": "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1", "label": "Output", "spendable": false }
+{ "type": "xpub", "ref": "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8", "label": "Extended Public Key" }
+"#;
+
+    #[test]
+    fn round_trip() {
+        let labels = import(EXAMPLE).unwrap();
+        assert_eq!(labels.len(), 4);
+        assert_eq!(labels[0].kind, LabelType::Tx);
+        assert_eq!(
+            labels[0].origin.as_deref(),
+            Some("wpkh([d34db33f/84'/0'/0'])")
+        );
+        assert_eq!(labels[2].spendable, Some(false));
+        assert_eq!(import(&export(&labels)).unwrap(), labels);
+        for l in &labels {
+            assert_eq!(l.kind.to_string().parse(), Ok(l.kind));
+        }
+        assert_eq!(LabelType::Xpub.to_string(), "xpub");
+    }
+
+    #[test]
+    fn report_bad_line() {
+        let data = "{ \"type\": \"addr\", \"ref\": \"a\", \"label\": \"ok\" }\nnot json\n";
+        assert_eq!(import(data).unwrap_err().line, 2);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use aws_lc_rs::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    pbkdf2, rand,
+};
+use bdk_wallet::{
+    bitcoin::{
+        hashes::{sha256, Hash},
+        hex::{DisplayHex, FromHex},
+    },
+    rusqlite::Connection,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::settings::Settings,
+    registry::{self, WalletEntry},
+};
+
+/// File header, also authenticated along with the contents
+const MAGIC: &[u8] = b"seashell-backup-1";
+const SALT_LEN: usize = 16;
+const ITERATIONS: u32 = 600_000;
+
+/// Everything needed to bring a wallet back on another machine.
+/// Labels, payment requests, drafts and scan settings live in the wallet database.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bundle {
+    pub entry: WalletEntry,
+    /// SQLite database, hex encoded
+    db: String,
+    db_sha256: String,
+    /// Private descriptors from the `_keys` file
+    keys: Option<String>,
+    pub settings: Settings,
+}
+
+fn key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(ITERATIONS).expect("non zero"),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).expect("key is 32 bytes"))
+}
+
+fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    rand::fill(&mut salt).map_err(|e| e.to_string())?;
+    rand::fill(&mut nonce).map_err(|e| e.to_string())?;
+    let mut sealed = data.to_vec();
+    key(passphrase, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut sealed,
+        )
+        .map_err(|e| e.to_string())?;
+    Ok([MAGIC, &salt, &nonce, &sealed].concat())
+}
+
+fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let data = data
+        .strip_prefix(MAGIC)
+        .filter(|d| d.len() > SALT_LEN + NONCE_LEN)
+        .ok_or("Not a wallet backup")?;
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|e| e.to_string())?;
+    let mut sealed = sealed.to_vec();
+    let plain = key(passphrase, salt)
+        .open_in_place(nonce, Aad::from(MAGIC), &mut sealed)
+        .map_err(|_| "Wrong passphrase or corrupted backup")?;
+    Ok(plain.to_vec())
+}
+
+/// Temporary copy of a database, removed however the backup ends
+struct Snapshot(PathBuf);
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Write an encrypted backup of the wallet open on `db` to `path`
+pub fn create(
+    db: &Connection,
+    entry: &WalletEntry,
+    settings: &Settings,
+    passphrase: &str,
+    path: &Path,
+) -> Result<(), String> {
+    if passphrase.is_empty() {
+        return Err("A passphrase is required".into());
+    }
+    // snapshot through the open connection so the copy is consistent. It goes next to the
+    // wallet rather than in a shared temp folder, under a name no other backup uses.
+    let mut id = [0; 8];
+    rand::fill(&mut id).map_err(|e| e.to_string())?;
+    let dir = entry.db_file.parent().unwrap_or(Path::new("."));
+    let snapshot = Snapshot(dir.join(format!(
+        ".{}-{}.snapshot",
+        entry.name,
+        id.to_lower_hex_string()
+    )));
+    db.execute("VACUUM INTO ?1", [snapshot.0.to_string_lossy()])
+        .map_err(|e| e.to_string())?;
+    let data = std::fs::read(&snapshot.0).map_err(|e| e.to_string())?;
+    let keys = match &entry.keys_file {
+        Some(file) => Some(std::fs::read_to_string(file).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let bundle = Bundle {
+        entry: entry.clone(),
+        db_sha256: sha256::Hash::hash(&data).to_string(),
+        db: data.to_lower_hex_string(),
+        keys,
+        settings: settings.clone(),
+    };
+    let plain = serde_json::to_vec(&bundle).map_err(|e| e.to_string())?;
+    std::fs::write(path, encrypt(&plain, passphrase)?).map_err(|e| e.to_string())
+}
+
+/// Decrypt the backup at `path` and add its wallet to `dir` as `name`.
+/// The restored database is checked against the recorded hash and must open as a wallet.
+pub fn restore(dir: &str, path: &Path, passphrase: &str, name: &str) -> Result<Bundle, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let plain = decrypt(&data, passphrase)?;
+    let mut bundle: Bundle = serde_json::from_slice(&plain).map_err(|e| e.to_string())?;
+    let db = Vec::<u8>::from_hex(&bundle.db).map_err(|e| e.to_string())?;
+    if sha256::Hash::hash(&db).to_string() != bundle.db_sha256 {
+        return Err("Backup failed integrity check".into());
+    }
+
+    check_name(dir, name)?;
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let (db_file, keys_file) = registry::files(dir, name);
+    std::fs::write(&db_file, db).map_err(|e| e.to_string())?;
+    match registry::read_wallet_table(&db_file) {
+        Some((_, network)) if network == bundle.entry.network => {}
+        _ => {
+            let _ = std::fs::remove_file(&db_file);
+            return Err("Backup does not contain a valid wallet".into());
+        }
+    }
+    if let Some(keys) = &bundle.keys {
+        std::fs::write(&keys_file, keys).map_err(|e| e.to_string())?;
+    }
+
+    let entry = &mut bundle.entry;
+    entry.name = name.into();
+    entry.db_file = db_file;
+    entry.keys_file = bundle.keys.is_some().then_some(keys_file);
+    registry::register(dir, entry.clone())?;
+    Ok(bundle)
+}
+
+fn check_name(dir: &str, name: &str) -> Result<(), String> {
+    registry::check_name(dir, &registry::load(dir)?, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_and_restore() {
+        let tmp = crate::test_dir();
+        let dir = tmp.path();
+        std::fs::copy("tests/tw", dir.join("tw")).unwrap();
+        std::fs::copy("tests/tw_keys", dir.join("tw_keys")).unwrap();
+        let d = dir.to_str().unwrap();
+        let entry = registry::load(d).unwrap().remove(0);
+        let db = Connection::open(&entry.db_file).unwrap();
+        let settings: Settings =
+            serde_json::from_str(r#"{"electrum_servers": [], "wallet_db": "/tmp/w"}"#).unwrap();
+        let file = dir.join("tw.backup");
+
+        assert!(create(&db, &entry, &settings, "", &file).is_err());
+        create(&db, &entry, &settings, "hunter2", &file).unwrap();
+        // the snapshot taken for the backup is gone afterwards
+        assert!(std::fs::read_dir(dir).unwrap().all(|f| !f
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .ends_with(".snapshot")));
+        assert!(restore(d, &file, "wrong", "copy").is_err());
+        assert!(restore(d, &file, "hunter2", "tw").is_err());
+
+        let mut data = std::fs::read(&file).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(dir.join("bad.backup"), data).unwrap();
+        assert!(restore(d, &dir.join("bad.backup"), "hunter2", "copy").is_err());
+
+        let bundle = restore(d, &file, "hunter2", "copy").unwrap();
+        assert_eq!(bundle.entry.name, "copy");
+        assert_eq!(bundle.settings.wallet_db, "/tmp/w");
+        assert_eq!(
+            std::fs::read(dir.join("copy_keys")).unwrap(),
+            std::fs::read(dir.join("tw_keys")).unwrap()
+        );
+        assert!(crate::load_test_wallet(d, "copy").is_ok());
+        assert_eq!(registry::load(d).unwrap().len(), 2);
+    }
+}
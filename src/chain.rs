@@ -0,0 +1,386 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use bdk_wallet::{
+    bitcoin::{FeeRate, Network, ScriptBuf, Transaction, Txid},
+    chain::SpkIterator,
+    KeychainKind, Wallet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::app::settings::Settings;
+
+mod cbf;
+mod electrum;
+mod esplora;
+mod pinning;
+mod rpc;
+
+pub use electrum::{is_pinned, test_server};
+pub use pinning::{
+    forget as forget_pin, take_changes as take_cert_changes, trust as trust_pin, CertChange,
+};
+
+/// Which kind of server the wallet talks to
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Backend {
+    #[default]
+    Electrum,
+    Esplora,
+    /// Bitcoin Core JSON-RPC
+    Rpc,
+    /// BIP157/158 compact block filters from a single peer
+    Cbf,
+}
+
+/// Connection details for a Bitcoin Core node
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcSettings {
+    pub url: String,
+    /// Authenticate with the node's `.cookie` file instead of user and password
+    pub use_cookie: bool,
+    pub cookie_file: String,
+    pub user: String,
+    pub password: String,
+    /// First block height to scan on a full scan
+    pub start_height: u32,
+}
+
+impl RpcSettings {
+    /// Bitcoin Core's default RPC port and cookie location for `network`
+    pub fn for_network(network: Network) -> Self {
+        let (port, dir) = match network {
+            Network::Bitcoin => (8332, ""),
+            Network::Testnet => (18332, "testnet3/"),
+            Network::Signet => (38332, "signet/"),
+            _ => (18443, "regtest/"),
+        };
+        let cookie_file = dirs::home_dir()
+            .map(|mut home| {
+                home.push(format!(".bitcoin/{dir}.cookie"));
+                home.to_string_lossy().to_string()
+            })
+            .unwrap_or_default();
+        RpcSettings {
+            url: format!("http://127.0.0.1:{port}"),
+            use_cookie: true,
+            cookie_file,
+            user: "".into(),
+            password: "".into(),
+            start_height: 0,
+        }
+    }
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        RpcSettings::for_network(Network::Testnet)
+    }
+}
+
+/// Peer to fetch compact block filters from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CbfSettings {
+    /// `host:port` of a node started with `-blockfilterindex=1 -peerblockfilters=1`
+    pub peer: String,
+    /// First block height to scan on a full scan
+    pub start_height: u32,
+}
+
+impl Default for CbfSettings {
+    fn default() -> Self {
+        CbfSettings {
+            peer: "127.0.0.1:18333".into(),
+            start_height: 0,
+        }
+    }
+}
+
+/// Proxy for every chain connection, e.g. a local Tor daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxySettings {
+    pub enabled: bool,
+    /// `host:port` of the SOCKS5 proxy used for Electrum, RPC and compact filters
+    pub socks5: String,
+    /// Optional SOCKS5 credentials, Tor uses them to isolate streams
+    pub user: String,
+    pub password: String,
+    /// `host:port` of an HTTP CONNECT proxy used for Esplora, whose blocking HTTP client
+    /// can't speak SOCKS. Tor provides one with `HTTPTunnelPort 9080`.
+    pub http_tunnel: String,
+}
+
+impl Default for ProxySettings {
+    fn default() -> Self {
+        ProxySettings {
+            enabled: false,
+            socks5: "127.0.0.1:9050".into(),
+            user: "".into(),
+            password: "".into(),
+            http_tunnel: "127.0.0.1:9080".into(),
+        }
+    }
+}
+
+/// Whether `url` points at a Tor hidden service
+pub fn is_onion(url: &str) -> bool {
+    let host = url.split("://").last().unwrap_or(url);
+    let host = host.split(['/', ':']).next().unwrap_or(host);
+    host.ends_with(".onion")
+}
+
+/// Why a chain request failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainError {
+    /// The server could not be reached or the connection broke, another attempt or
+    /// another server may succeed
+    Transport(String),
+    /// The server answered with an error, like a rejected transaction, or its answer did
+    /// not fit the wallet. Asking again elsewhere won't help.
+    Request(String),
+}
+
+impl ChainError {
+    pub fn is_transport(&self) -> bool {
+        matches!(self, ChainError::Transport(_))
+    }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainError::Transport(e) | ChainError::Request(e) => f.write_str(e),
+        }
+    }
+}
+
+impl From<String> for ChainError {
+    fn from(e: String) -> Self {
+        ChainError::Request(e)
+    }
+}
+
+impl From<&str> for ChainError {
+    fn from(e: &str) -> Self {
+        ChainError::Request(e.into())
+    }
+}
+
+impl From<ChainError> for String {
+    fn from(e: ChainError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Run `op` up to `retries` more times while it keeps failing to reach the server
+fn with_retries<T>(
+    retries: u8,
+    mut op: impl FnMut() -> Result<T, ChainError>,
+) -> Result<T, ChainError> {
+    let mut res = op();
+    for _ in 0..retries {
+        if !res.as_ref().is_err_and(ChainError::is_transport) {
+            break;
+        }
+        res = op();
+    }
+    res
+}
+
+/// A place to fetch chain data from and broadcast to.
+/// Implementations apply their updates directly to the wallet, the caller persists.
+pub trait ChainSource {
+    /// Sync the revealed script pubkeys of the wallet
+    fn sync(&mut self, wallet: &mut Wallet, batch_size: usize) -> Result<(), ChainError>;
+
+    /// Scan both keychains until `stop_gap` unused script pubkeys are found
+    fn full_scan(
+        &mut self,
+        wallet: &mut Wallet,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<(), ChainError>;
+
+    fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, ChainError>;
+
+    /// Fee rate needed to confirm within `target` blocks
+    fn estimate_fee(&mut self, target: usize) -> Result<FeeRate, ChainError>;
+
+    /// Server currently in use, `None` once the connection is lost
+    fn server(&self) -> Option<String>;
+}
+
+/// Scripts of both keychains up to a gap past the last revealed index, so sources that match
+/// blocks against scripts can look further than the wallet's lookahead
+#[derive(Debug, Default)]
+struct GapScripts {
+    spks: BTreeMap<ScriptBuf, (KeychainKind, u32)>,
+    /// Next index to derive for each keychain
+    next: BTreeMap<KeychainKind, u32>,
+}
+
+impl GapScripts {
+    /// Derive up to `gap` scripts past each keychain's last revealed index, returning the
+    /// ones not derived before
+    fn extend(&mut self, wallet: &Wallet, gap: u32) -> Vec<ScriptBuf> {
+        let mut new = Vec::new();
+        for (kc, desc) in wallet.keychains() {
+            let revealed = wallet.spk_index().last_revealed_index(kc);
+            let end = revealed.map_or(gap, |last| last.saturating_add(gap).saturating_add(1));
+            let next = self.next.entry(kc).or_default();
+            for (index, spk) in SpkIterator::new_with_range(desc, *next..end) {
+                if self.spks.insert(spk.clone(), (kc, index)).is_none() {
+                    new.push(spk);
+                }
+            }
+            *next = end.max(*next);
+        }
+        new
+    }
+
+    /// Reveal the wallet's addresses up to the furthest one `tx` pays, so outputs past the
+    /// lookahead are indexed when the transaction is applied
+    fn reveal_paid(&self, wallet: &mut Wallet, tx: &Transaction) {
+        for out in &tx.output {
+            if let Some((kc, index)) = self.spks.get(&out.script_pubkey) {
+                let _ = wallet.reveal_addresses_to(*kc, *index);
+            }
+        }
+    }
+}
+
+/// Result of probing an Electrum server from the settings page
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    pub latency: Duration,
+    pub version: String,
+    pub tip_height: u32,
+}
+
+/// Connect to the backend selected in settings for a wallet on `network`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn connect(settings: &Settings, network: Network) -> Result<Box<dyn ChainSource>, String> {
+    match settings.backend {
+        Backend::Electrum => Ok(Box::new(electrum::ElectrumSource::new(settings)?)),
+        Backend::Esplora => Ok(Box::new(esplora::EsploraSource::new(settings)?)),
+        Backend::Rpc => Ok(Box::new(rpc::RpcSource::new(settings)?)),
+        Backend::Cbf => Ok(Box::new(cbf::CbfSource::new(settings, network)?)),
+    }
+}
+
+/// Connect to the backend selected in settings. The browser only reaches servers over HTTP
+/// and can't block on them, so there Esplora is the one backend and its calls are async.
+#[cfg(target_arch = "wasm32")]
+pub fn connect(settings: &Settings, _network: Network) -> Result<esplora::EsploraSource, String> {
+    match settings.backend {
+        Backend::Esplora => esplora::EsploraSource::new(settings),
+        _ => Err("Only Esplora is available in the browser".into()),
+    }
+}
+
+/// Convert a fee estimate in sat/vB to a [`FeeRate`], rounding up
+fn fee_rate_from_sat_vb(sat_vb: f64) -> Result<FeeRate, ChainError> {
+    if !sat_vb.is_finite() || sat_vb <= 0. {
+        return Err(ChainError::Request("no fee estimate available".into()));
+    }
+    Ok(FeeRate::from_sat_per_kwu((sat_vb * 250.).ceil() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_only_transport_errors() {
+        let mut calls = 0;
+        let res: Result<(), _> = with_retries(3, || {
+            calls += 1;
+            Err(ChainError::Transport("timed out".into()))
+        });
+        assert!(res.is_err_and(|e| e.is_transport()));
+        assert_eq!(calls, 4);
+
+        calls = 0;
+        let res: Result<(), _> = with_retries(3, || {
+            calls += 1;
+            Err("bad-txns-inputs-missingorspent".into())
+        });
+        assert!(res.is_err_and(|e| !e.is_transport()));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn fee_rate_conversion() {
+        assert_eq!(
+            fee_rate_from_sat_vb(2.),
+            Ok(FeeRate::from_sat_per_vb(2).unwrap())
+        );
+        // fractional estimates round up rather than underpay
+        assert_eq!(fee_rate_from_sat_vb(1.001).unwrap().to_sat_per_kwu(), 251);
+        assert!(fee_rate_from_sat_vb(-1.).is_err());
+    }
+
+    #[test]
+    fn rpc_defaults_follow_network() {
+        let regtest = RpcSettings::for_network(Network::Regtest);
+        assert_eq!(regtest.url, "http://127.0.0.1:18443");
+        assert!(regtest.cookie_file.ends_with("regtest/.cookie"));
+        assert_eq!(
+            RpcSettings::default(),
+            RpcSettings::for_network(Network::Testnet)
+        );
+    }
+
+    #[test]
+    fn onion_urls() {
+        let onion = "explorerzydxu5ecjrkwceayqybizmpjjznk5izmitf2modhcusuqlid.onion";
+        assert!(is_onion(&format!("tcp://{onion}:110")));
+        assert!(is_onion(&format!("http://{onion}/testnet/api")));
+        assert!(!is_onion("ssl://electrum.blockstream.info:60002"));
+        assert!(!is_onion("https://example.com/onion"));
+
+        let mut server = crate::app::settings::ElectrumServer::new(format!("ssl://{onion}:50002"));
+        assert!(is_pinned(&server));
+        server.url = "ssl://electrum.blockstream.info:60002".into();
+        assert!(!is_pinned(&server));
+        server.validate_domain = false;
+        assert!(is_pinned(&server));
+    }
+
+    #[test]
+    fn gap_scripts_follow_payments() {
+        use bdk_wallet::bitcoin::{absolute, bip32::Xpriv, transaction, Amount, TxOut};
+        let xprv = Xpriv::new_master(Network::Testnet, &[7; 32]).unwrap();
+        let (external, internal) =
+            crate::bdk_utils::account_descriptors(xprv, crate::bdk_utils::AccountScript::Bip84, 0);
+        let mut wallet = Wallet::create(external, internal)
+            .network(Network::Testnet)
+            .create_wallet_no_persist()
+            .unwrap();
+        let mut gap = GapScripts::default();
+        assert_eq!(gap.extend(&wallet, 50).len(), 100);
+        assert!(gap.extend(&wallet, 50).is_empty());
+
+        // a payment past the lookahead is only indexed once its address is revealed
+        let far = wallet
+            .peek_address(KeychainKind::External, 40)
+            .script_pubkey();
+        assert!(!wallet.is_mine(far.clone()));
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::from_sat(1000),
+                script_pubkey: far,
+            }],
+        };
+        gap.reveal_paid(&mut wallet, &tx);
+        wallet.apply_unconfirmed_txs([(&tx, 0)]);
+        assert_eq!(wallet.balance().total(), Amount::from_sat(1000));
+        // and moves the gap along with it
+        assert_eq!(gap.extend(&wallet, 50).len(), 41);
+    }
+}
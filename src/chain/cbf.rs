@@ -0,0 +1,962 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bdk_electrum::electrum_client::socks::Socks5Stream;
+use bdk_wallet::{
+    bitcoin::{
+        bip158::BlockFilter,
+        block::Header,
+        consensus::{deserialize, serialize, Decodable},
+        constants::{genesis_block, SUBSIDY_HALVING_INTERVAL},
+        hashes::Hash,
+        p2p::{
+            message::{NetworkMessage, RawNetworkMessage},
+            message_blockdata::{GetHeadersMessage, Inventory},
+            message_filter::GetCFilters,
+            message_network::VersionMessage,
+            Address, Magic, ServiceFlags,
+        },
+        params::Params,
+        Block, BlockHash, CompactTarget, FeeRate, Network, ScriptBuf, Target, Transaction, Txid,
+        Work,
+    },
+    chain::{BlockId, CheckPoint},
+    Update, Wallet,
+};
+
+use super::{is_onion, ChainError, ChainSource, GapScripts};
+use crate::app::settings::Settings;
+
+/// Most headers a peer returns for one `getheaders`
+const MAX_HEADERS: usize = 2000;
+/// Most filters a peer serves for one `getcfilters`
+const MAX_FILTERS: u32 = 1000;
+/// Filter type of the BIP158 basic filter
+const BASIC_FILTER: u8 = 0;
+/// Serialized size of a block header
+const HEADER_SIZE: u64 = 80;
+/// Transactions asked for in one `getdata`
+const MAX_GETDATA: usize = 1000;
+/// Announced transactions remembered until the next sync, the rest are dropped
+const MAX_ANNOUNCED: usize = 50_000;
+/// Most recent blocks looked at for a fee estimate
+const FEE_BLOCKS: usize = 6;
+
+/// Light client talking to a single peer that serves BIP157 compact block filters.
+/// Filters are matched against the wallet's scripts locally and only matching blocks are
+/// downloaded, so the peer never learns which addresses belong to the wallet.
+/// Headers are checked for proof of work, difficulty adjustments and most total work, but
+/// the peer is trusted to serve the right filters, so point it at a node you run.
+pub struct CbfSource {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    magic: Magic,
+    params: Params,
+    peer: String,
+    start_height: u32,
+    headers: HeaderStore,
+    /// Transactions the peer announced since the last sync
+    announced: HashSet<Txid>,
+    /// Lowest fee rate the peer relays in sat/kvB, from its BIP133 `feefilter`
+    fee_filter: Option<u64>,
+}
+
+impl CbfSource {
+    pub fn new(settings: &Settings, network: Network) -> Result<Self, String> {
+        let timeout = Duration::from_secs(settings.timeout_secs.into());
+        let peer = &settings.cbf.peer;
+        let proxy = &settings.proxy;
+        let stream = if proxy.enabled {
+            // the proxy resolves the peer's name, so onion peers work too
+            let stream = if proxy.user.is_empty() {
+                Socks5Stream::connect(&proxy.socks5, peer.as_str(), Some(timeout))
+            } else {
+                Socks5Stream::connect_with_password(
+                    &proxy.socks5,
+                    peer.as_str(),
+                    &proxy.user,
+                    &proxy.password,
+                    Some(timeout),
+                )
+            };
+            stream.map_err(|e| format!("{peer}: {e}"))?.into_inner()
+        } else if is_onion(peer) {
+            return Err("Onion addresses need a proxy".into());
+        } else {
+            let addr = peer
+                .to_socket_addrs()
+                .map_err(|e| e.to_string())?
+                .next()
+                .ok_or(format!("{peer} did not resolve to an address"))?;
+            TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?
+        };
+        stream
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .map_err(|e| e.to_string())?;
+
+        std::fs::create_dir_all(&settings.wallet_db).map_err(|e| e.to_string())?;
+        let path = Path::new(&settings.wallet_db).join(format!("cbf_headers_{network}"));
+        let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let mut source = CbfSource {
+            stream,
+            reader,
+            magic: network.magic(),
+            params: Params::new(network),
+            peer: peer.clone(),
+            start_height: settings.cbf.start_height,
+            headers: HeaderStore::open(&path, network)?,
+            announced: HashSet::new(),
+            fee_filter: None,
+        };
+        source.handshake()?;
+        Ok(source)
+    }
+
+    fn send(&mut self, msg: NetworkMessage) -> Result<(), ChainError> {
+        let raw = RawNetworkMessage::new(self.magic, msg);
+        self.stream
+            .write_all(&serialize(&raw))
+            .map_err(|e| ChainError::Transport(e.to_string()))
+    }
+
+    /// Read messages until `want` picks one out, answering pings and noting transaction
+    /// announcements and fee filters along the way
+    fn receive<T>(
+        &mut self,
+        mut want: impl FnMut(&NetworkMessage) -> Option<T>,
+    ) -> Result<T, ChainError> {
+        loop {
+            let raw = RawNetworkMessage::consensus_decode(&mut self.reader)
+                .map_err(|e| ChainError::Transport(e.to_string()))?;
+            if *raw.magic() != self.magic {
+                return Err("Peer is on a different network".into());
+            }
+            match raw.payload() {
+                NetworkMessage::Ping(nonce) => self.send(NetworkMessage::Pong(*nonce))?,
+                NetworkMessage::Inv(items) => {
+                    let room = MAX_ANNOUNCED.saturating_sub(self.announced.len());
+                    let txids = items.iter().filter_map(|item| match item {
+                        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+                            Some(*txid)
+                        }
+                        _ => None,
+                    });
+                    self.announced.extend(txids.take(room));
+                }
+                NetworkMessage::FeeFilter(per_kvb) => {
+                    self.fee_filter = u64::try_from(*per_kvb).ok()
+                }
+                msg => {
+                    if let Some(found) = want(msg) {
+                        return Ok(found);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a ping and show `seen` every message that arrives before its pong. Bitcoin Core
+    /// handles a peer's messages in order, so whatever was asked for before has arrived.
+    fn round_trip(&mut self, mut seen: impl FnMut(&NetworkMessage)) -> Result<(), ChainError> {
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        self.send(NetworkMessage::Ping(nonce))?;
+        self.receive(|msg| match msg {
+            NetworkMessage::Pong(n) if *n == nonce => Some(()),
+            other => {
+                seen(other);
+                None
+            }
+        })
+    }
+
+    fn handshake(&mut self) -> Result<(), ChainError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let peer = self.stream.peer_addr().map_err(|e| e.to_string())?;
+        let local = self.stream.local_addr().map_err(|e| e.to_string())?;
+        let mut version = VersionMessage::new(
+            ServiceFlags::NONE,
+            now.as_secs() as i64,
+            Address::new(&peer, ServiceFlags::NONE),
+            Address::new(&local, ServiceFlags::NONE),
+            now.subsec_nanos() as u64,
+            format!("/seashell:{}/", env!("CARGO_PKG_VERSION")),
+            0,
+        );
+        // have new mempool transactions announced
+        version.relay = true;
+        self.send(NetworkMessage::Version(version))?;
+
+        let services = self.receive(|msg| match msg {
+            NetworkMessage::Version(v) => Some(v.services),
+            _ => None,
+        })?;
+        if !services.has(ServiceFlags::COMPACT_FILTERS) {
+            return Err("Peer does not serve compact block filters (bitcoind needs -blockfilterindex=1 -peerblockfilters=1)".into());
+        }
+        self.send(NetworkMessage::Verack)?;
+        self.receive(|msg| matches!(msg, NetworkMessage::Verack).then_some(()))?;
+        // peers with bloom filters enabled list their whole mempool on request, others
+        // disconnect, so they only announce what arrives from now on
+        if services.has(ServiceFlags::BLOOM) {
+            self.send(NetworkMessage::MemPool)?;
+        }
+        Ok(())
+    }
+
+    /// Download headers until the store holds the peer's best chain
+    fn sync_headers(&mut self) -> Result<(), ChainError> {
+        let locator = self.headers.locator()?;
+        let mut request: Vec<BlockHash> = locator.iter().map(|(_, hash)| *hash).collect();
+        let mut branch: Option<Branch> = None;
+        loop {
+            self.send(NetworkMessage::GetHeaders(GetHeadersMessage::new(
+                request,
+                BlockHash::all_zeros(),
+            )))?;
+            let headers = self.receive(|msg| match msg {
+                NetworkMessage::Headers(h) => Some(h.clone()),
+                _ => None,
+            })?;
+            let Some(first) = headers.first() else {
+                break;
+            };
+            let branch = match &mut branch {
+                Some(branch) => branch,
+                None => {
+                    let fork = locator
+                        .iter()
+                        .find(|(_, hash)| *hash == first.prev_blockhash)
+                        .map(|(height, _)| *height)
+                        .ok_or("Peer headers do not connect to the synced chain")?;
+                    branch.insert(Branch {
+                        fork,
+                        headers: Vec::new(),
+                    })
+                }
+            };
+            self.headers.connect(&self.params, branch, &headers)?;
+            if headers.len() < MAX_HEADERS {
+                break;
+            }
+            request = vec![match branch.headers.last() {
+                Some(last) => last.block_hash(),
+                None => self.headers.hash(branch.fork)?,
+            }];
+        }
+        if branch.is_some_and(|b| !b.headers.is_empty()) {
+            return Err("Peer's chain has less work than the headers already synced".into());
+        }
+        Ok(())
+    }
+
+    /// Heights in `from..=to` whose filters match any of `spks`
+    fn matching_blocks(
+        &mut self,
+        from: u32,
+        to: u32,
+        spks: &[ScriptBuf],
+    ) -> Result<Vec<u32>, ChainError> {
+        let mut matches = Vec::new();
+        if spks.is_empty() {
+            return Ok(matches);
+        }
+        let mut start = from;
+        while start <= to {
+            let stop = (start + MAX_FILTERS - 1).min(to);
+            let hashes: Vec<BlockHash> = self
+                .headers
+                .range(start, stop)?
+                .iter()
+                .map(|h| h.block_hash())
+                .collect();
+            self.send(NetworkMessage::GetCFilters(GetCFilters {
+                filter_type: BASIC_FILTER,
+                start_height: start,
+                stop_hash: hashes[hashes.len() - 1],
+            }))?;
+            for (height, hash) in (start..=stop).zip(hashes) {
+                let filter = self.receive(|msg| match msg {
+                    NetworkMessage::CFilter(f) => Some(f.clone()),
+                    _ => None,
+                })?;
+                if filter.block_hash != hash {
+                    return Err(format!("Peer sent the wrong filter for block {height}").into());
+                }
+                let matched = BlockFilter::new(&filter.filter)
+                    .match_any(&filter.block_hash, spks.iter().map(|s| s.as_bytes()))
+                    .map_err(|e| e.to_string())?;
+                if matched {
+                    matches.push(height);
+                }
+            }
+            start = stop + 1;
+        }
+        Ok(matches)
+    }
+
+    fn block(&mut self, height: u32) -> Result<Block, ChainError> {
+        let hash = self.headers.hash(height)?;
+        self.send(NetworkMessage::GetData(vec![Inventory::WitnessBlock(hash)]))?;
+        self.receive(|msg| match msg {
+            NetworkMessage::Block(b) if b.block_hash() == hash => Some(b.clone()),
+            _ => None,
+        })
+    }
+
+    /// Bring the wallet up to the peer's tip, scanning from `start_height` when `rescan` is set.
+    /// Scripts are matched `gap` past the last revealed index of each keychain, and blocks
+    /// are matched again against the scripts a matched block brings into that gap.
+    fn scan(&mut self, wallet: &mut Wallet, rescan: bool, gap: u32) -> Result<(), ChainError> {
+        self.sync_headers()?;
+        let tip = self.headers.tip();
+
+        // the last wallet block still on the best chain, anything above it was reorged out
+        let local_tip = wallet.latest_checkpoint();
+        let mut agreement = None;
+        for cp in local_tip.iter() {
+            if cp.height() <= tip && self.headers.hash(cp.height())? == cp.hash() {
+                agreement = Some(cp.block_id());
+                break;
+            }
+        }
+        let agreement = agreement.ok_or("The wallet's chain shares no block with the peer's")?;
+        if local_tip.height() > tip {
+            return Err("Peer is behind the wallet's chain".into());
+        }
+        let base = if rescan {
+            BlockId {
+                height: 0,
+                hash: self.headers.hash(0)?,
+            }
+        } else {
+            agreement
+        };
+        let start = self.start_height.max(base.height + 1);
+        // a block at the height of every reorged checkpoint displaces it
+        let stale: Vec<u32> = local_tip
+            .iter()
+            .map(|cp| cp.height())
+            .take_while(|height| *height > agreement.height)
+            .collect();
+
+        let mut gap_spks = GapScripts::default();
+        let mut spks = gap_spks.extend(wallet, gap);
+        let mut applied = BTreeSet::new();
+        loop {
+            let matches: Vec<u32> = self
+                .matching_blocks(start, tip, &spks)?
+                .into_iter()
+                .filter(|height| !applied.contains(height))
+                .collect();
+
+            // parents of matched blocks are needed to connect them
+            let mut blocks = BTreeMap::new();
+            for height in matches
+                .iter()
+                .flat_map(|h| [h - 1, *h])
+                .chain(stale.iter().copied())
+                .chain([tip])
+                .filter(|height| *height > base.height)
+            {
+                blocks.insert(height, self.headers.hash(height)?);
+            }
+            let chain = CheckPoint::new(base)
+                .extend(
+                    blocks
+                        .into_iter()
+                        .map(|(height, hash)| BlockId { height, hash }),
+                )
+                .map_err(|_| "Could not build chain update".to_string())?;
+            wallet
+                .apply_update(Update {
+                    chain: Some(chain),
+                    ..Default::default()
+                })
+                .map_err(|e| e.to_string())?;
+
+            for height in matches {
+                let block = self.block(height)?;
+                for tx in &block.txdata {
+                    gap_spks.reveal_paid(wallet, tx);
+                }
+                wallet
+                    .apply_block(&block, height)
+                    .map_err(|e| e.to_string())?;
+                applied.insert(height);
+            }
+
+            // payments found reveal addresses and so move the gap, whose new scripts
+            // may have been paid anywhere in the range
+            spks = gap_spks.extend(wallet, gap);
+            if spks.is_empty() {
+                break;
+            }
+        }
+        self.mempool(wallet)
+    }
+
+    /// Apply the announced transactions that pay to or spend from the wallet as unconfirmed
+    fn mempool(&mut self, wallet: &mut Wallet) -> Result<(), ChainError> {
+        // collect the announcements already waiting on the socket
+        self.round_trip(|_| {})?;
+        let txids: Vec<Txid> = self.announced.drain().collect();
+        let mut relevant = Vec::new();
+        for chunk in txids.chunks(MAX_GETDATA) {
+            let items = chunk
+                .iter()
+                .map(|txid| Inventory::WitnessTransaction(*txid))
+                .collect();
+            self.send(NetworkMessage::GetData(items))?;
+            self.round_trip(|msg| {
+                if let NetworkMessage::Tx(tx) = msg {
+                    let pays = tx
+                        .output
+                        .iter()
+                        .any(|o| wallet.is_mine(o.script_pubkey.clone()));
+                    let spends = tx
+                        .input
+                        .iter()
+                        .any(|i| wallet.spk_index().txout(i.previous_output).is_some());
+                    if pays || spends {
+                        relevant.push(tx.clone());
+                    }
+                }
+            })?;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        wallet.apply_unconfirmed_txs(relevant.iter().map(|tx| (tx, now)));
+        Ok(())
+    }
+}
+
+impl ChainSource for CbfSource {
+    /// Filters are matched against every revealed and lookahead script
+    fn sync(&mut self, wallet: &mut Wallet, _batch_size: usize) -> Result<(), ChainError> {
+        let lookahead = wallet.spk_index().lookahead();
+        self.scan(wallet, false, lookahead)
+    }
+
+    /// Starts again from `start_height`, matching filters `stop_gap` scripts past the last
+    /// revealed one of each keychain, or the lookahead when that reaches further
+    fn full_scan(
+        &mut self,
+        wallet: &mut Wallet,
+        stop_gap: usize,
+        _batch_size: usize,
+    ) -> Result<(), ChainError> {
+        let gap = wallet.spk_index().lookahead().max(stop_gap as u32);
+        self.scan(wallet, true, gap)
+    }
+
+    fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, ChainError> {
+        self.send(NetworkMessage::Tx(tx.clone()))?;
+        // peers don't acknowledge transactions, a ping round trip at least shows it was read
+        self.round_trip(|_| {})?;
+        Ok(tx.compute_txid())
+    }
+
+    /// Peers don't estimate fees, so this reads what recent blocks paid: the lowest average
+    /// fee rate among the last `target` blocks (at most six), never below what the peer relays
+    fn estimate_fee(&mut self, target: usize) -> Result<FeeRate, ChainError> {
+        self.sync_headers()?;
+        let tip = self.headers.tip();
+        let count = target.clamp(1, FEE_BLOCKS) as u32;
+        let mut lowest: Option<f64> = None;
+        for height in tip.saturating_sub(count - 1)..=tip {
+            let block = self.block(height)?;
+            if let Some(rate) = block_fee_rate(&block, height, self.params.network) {
+                lowest = Some(lowest.map_or(rate, |l| l.min(rate)));
+            }
+        }
+        let floor = self.fee_filter.map_or(1., |per_kvb| per_kvb as f64 / 1000.);
+        super::fee_rate_from_sat_vb(lowest.map_or(floor, |rate| rate.max(floor)))
+    }
+
+    fn server(&self) -> Option<String> {
+        Some(self.peer.clone())
+    }
+}
+
+/// Validated headers of the chain with the most work seen so far, one 80 byte record per
+/// height in a file shared by every wallet on the network
+struct HeaderStore {
+    file: File,
+    len: u32,
+}
+
+/// Headers following the block at `fork` that are not stored yet, because they don't carry
+/// more work than the stored headers they would replace
+struct Branch {
+    fork: u32,
+    headers: Vec<Header>,
+}
+
+impl HeaderStore {
+    fn open(path: &Path, network: Network) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        let mut store = HeaderStore {
+            file,
+            len: (size / HEADER_SIZE) as u32,
+        };
+        let genesis = genesis_block(network).header;
+        if store.len == 0 {
+            store.append(&[genesis])?;
+        } else if store.get(0)? != genesis {
+            return Err(format!(
+                "{} holds headers of another network",
+                path.display()
+            ));
+        }
+        // drop a record left half written
+        store.truncate(store.len)?;
+        Ok(store)
+    }
+
+    fn tip(&self) -> u32 {
+        self.len - 1
+    }
+
+    /// Headers at heights `from..=to`
+    fn range(&mut self, from: u32, to: u32) -> Result<Vec<Header>, String> {
+        let mut data = vec![0; ((to - from + 1) as u64 * HEADER_SIZE) as usize];
+        self.file
+            .seek(SeekFrom::Start(from as u64 * HEADER_SIZE))
+            .and_then(|_| self.file.read_exact(&mut data))
+            .map_err(|e| e.to_string())?;
+        data.chunks(HEADER_SIZE as usize)
+            .map(|record| deserialize(record).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    fn get(&mut self, height: u32) -> Result<Header, String> {
+        Ok(self.range(height, height)?[0])
+    }
+
+    fn hash(&mut self, height: u32) -> Result<BlockHash, String> {
+        Ok(self.get(height)?.block_hash())
+    }
+
+    /// Keep the first `len` headers
+    fn truncate(&mut self, len: u32) -> Result<(), String> {
+        self.file
+            .set_len(len as u64 * HEADER_SIZE)
+            .map_err(|e| e.to_string())?;
+        self.len = len;
+        Ok(())
+    }
+
+    fn append(&mut self, headers: &[Header]) -> Result<(), String> {
+        let data: Vec<u8> = headers.iter().flat_map(serialize).collect();
+        self.file
+            .seek(SeekFrom::Start(self.len as u64 * HEADER_SIZE))
+            .and_then(|_| self.file.write_all(&data))
+            .map_err(|e| e.to_string())?;
+        self.len += headers.len() as u32;
+        Ok(())
+    }
+
+    /// Heights and hashes of the tip and the ten blocks below it, then twice as far back
+    /// each step, ending with genesis
+    fn locator(&mut self) -> Result<Vec<(u32, BlockHash)>, String> {
+        let mut heights = Vec::new();
+        let mut height = self.tip();
+        let mut step = 1;
+        while height > 0 {
+            heights.push(height);
+            if heights.len() > 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        heights.push(0);
+        heights
+            .into_iter()
+            .map(|height| Ok((height, self.hash(height)?)))
+            .collect()
+    }
+
+    /// Total work of the stored headers above `height`
+    fn work_above(&mut self, height: u32) -> Result<Work, String> {
+        let mut work = Work::from_be_bytes([0; 32]);
+        let mut from = height + 1;
+        while from <= self.tip() {
+            let to = (from + MAX_HEADERS as u32 - 1).min(self.tip());
+            for header in self.range(from, to)? {
+                work = work + header.work();
+            }
+            from = to + 1;
+        }
+        Ok(work)
+    }
+
+    /// Check `headers`, which follow `branch`, and store the branch once it has more work
+    /// than the stored headers above its fork
+    fn connect(
+        &mut self,
+        params: &Params,
+        branch: &mut Branch,
+        headers: &[Header],
+    ) -> Result<(), String> {
+        // testnet walks back through the current difficulty period for every header, keep
+        // the stored part of it in memory
+        let interval = params.difficulty_adjustment_interval() as u32;
+        let period_from = branch.fork - branch.fork % interval;
+        let period = self.range(period_from, branch.fork)?;
+        for header in headers {
+            let height = branch.fork + branch.headers.len() as u32 + 1;
+            // a peer that is behind resends headers already stored
+            if branch.headers.is_empty()
+                && height <= self.tip()
+                && self.hash(height)? == header.block_hash()
+            {
+                branch.fork = height;
+                continue;
+            }
+            let prev = match branch.headers.last() {
+                Some(prev) => *prev,
+                None => self.get(branch.fork)?,
+            };
+            if header.prev_blockhash != prev.block_hash() {
+                return Err("Peer sent headers that do not connect".into());
+            }
+            let bits = required_bits(params, height, header, &prev, |h| {
+                if h > branch.fork {
+                    Ok(branch.headers[(h - branch.fork - 1) as usize])
+                } else if let Some(header) = h
+                    .checked_sub(period_from)
+                    .and_then(|i| period.get(i as usize))
+                {
+                    Ok(*header)
+                } else {
+                    self.get(h)
+                }
+            })?;
+            header
+                .validate_pow(Target::from_compact(bits))
+                .map_err(|e| format!("Invalid header at height {height}: {e}"))?;
+            branch.headers.push(*header);
+        }
+
+        let work = branch
+            .headers
+            .iter()
+            .fold(Work::from_be_bytes([0; 32]), |work, h| work + h.work());
+        if !branch.headers.is_empty() && work > self.work_above(branch.fork)? {
+            self.truncate(branch.fork + 1)?;
+            self.append(&branch.headers)?;
+            branch.fork = self.tip();
+            branch.headers.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Difficulty the header at `height` has to meet, following Bitcoin Core's
+/// `GetNextWorkRequired`. `header_at` looks up earlier headers of the same chain.
+fn required_bits(
+    params: &Params,
+    height: u32,
+    header: &Header,
+    prev: &Header,
+    mut header_at: impl FnMut(u32) -> Result<Header, String>,
+) -> Result<CompactTarget, String> {
+    let interval = params.difficulty_adjustment_interval() as u32;
+    let pow_limit = params.max_attainable_target.to_compact_lossy();
+    if height % interval != 0 {
+        if !params.allow_min_difficulty_blocks {
+            return Ok(prev.bits);
+        }
+        // testnet allows a minimum difficulty block after twenty minutes without one
+        if header.time > prev.time + 2 * params.pow_target_spacing as u32 {
+            return Ok(pow_limit);
+        }
+        // otherwise the last difficulty that was not the minimum still applies
+        let (mut last_height, mut last) = (height - 1, *prev);
+        while last_height % interval != 0 && last.bits == pow_limit {
+            last_height -= 1;
+            last = header_at(last_height)?;
+        }
+        return Ok(last.bits);
+    }
+    if params.no_pow_retargeting {
+        return Ok(prev.bits);
+    }
+    let first = header_at(height - interval)?;
+    Ok(retarget(
+        prev.bits,
+        prev.time.saturating_sub(first.time).into(),
+        params,
+    ))
+}
+
+/// Scale the target of `bits` by how long the last period took against how long it should
+/// have, at most by a factor of four either way and never above the network's limit
+fn retarget(bits: CompactTarget, timespan: u64, params: &Params) -> CompactTarget {
+    let expected = params.pow_target_timespan;
+    let timespan = timespan.clamp(expected / 4, expected * 4);
+    // 256 bit multiply then divide, on little endian 64 bit limbs with one to spare
+    let bytes = Target::from_compact(bits).to_le_bytes();
+    let mut limbs = [0u64; 5];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks(8)) {
+        *limb = u64::from_le_bytes(chunk.try_into().expect("8 byte chunks"));
+    }
+    let mut carry = 0u128;
+    for limb in limbs.iter_mut() {
+        let product = *limb as u128 * timespan as u128 + carry;
+        *limb = product as u64;
+        carry = product >> 64;
+    }
+    let mut rest = 0u128;
+    for limb in limbs.iter_mut().rev() {
+        let value = (rest << 64) | *limb as u128;
+        *limb = (value / expected as u128) as u64;
+        rest = value % expected as u128;
+    }
+    if limbs[4] != 0 {
+        return params.max_attainable_target.to_compact_lossy();
+    }
+    let mut bytes = [0; 32];
+    for (chunk, limb) in bytes.chunks_mut(8).zip(limbs) {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+    Target::from_le_bytes(bytes)
+        .min(params.max_attainable_target)
+        .to_compact_lossy()
+}
+
+/// Average fee rate in sat/vB paid by the transactions of `block`, from what its coinbase
+/// claims above the subsidy. `None` for a block holding only its coinbase.
+fn block_fee_rate(block: &Block, height: u32, network: Network) -> Option<f64> {
+    let interval = match network {
+        Network::Regtest => 150,
+        _ => SUBSIDY_HALVING_INTERVAL,
+    };
+    let subsidy = (50 * 100_000_000u64)
+        .checked_shr(height / interval)
+        .unwrap_or(0);
+    let claimed: u64 = block
+        .txdata
+        .first()?
+        .output
+        .iter()
+        .map(|o| o.value.to_sat())
+        .sum();
+    let vsize: usize = block.txdata[1..].iter().map(|tx| tx.vsize()).sum();
+    (vsize > 0).then(|| claimed.saturating_sub(subsidy) as f64 / vsize as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::{
+        bitcoin::{absolute, transaction, Amount, OutPoint, Sequence, TxIn, TxOut, Witness},
+        KeychainKind,
+    };
+
+    use super::*;
+
+    /// Next regtest header on top of `prev`, where about every other nonce meets the target
+    fn mine(prev: &Header, time: u32) -> Header {
+        let mut header = Header {
+            prev_blockhash: prev.block_hash(),
+            time,
+            nonce: 0,
+            ..*prev
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    fn chain(from: &Header, count: u32, time: u32) -> Vec<Header> {
+        let mut headers = vec![mine(from, time)];
+        for i in 1..count {
+            headers.push(mine(&headers[headers.len() - 1], time + i));
+        }
+        headers
+    }
+
+    #[test]
+    fn header_chain() {
+        let dir = crate::test_dir();
+        let path = dir.path().join("headers");
+        let params = Params::new(Network::Regtest);
+        let mut store = HeaderStore::open(&path, Network::Regtest).unwrap();
+        let genesis = store.get(0).unwrap();
+
+        let main = chain(&genesis, 5, 1_700_000_000);
+        let mut branch = Branch {
+            fork: 0,
+            headers: Vec::new(),
+        };
+        store.connect(&params, &mut branch, &main).unwrap();
+        assert_eq!(store.tip(), 5);
+        assert_eq!(branch.fork, 5);
+
+        // a fork with the same work as the headers it would replace waits for more
+        let fork = chain(&main[2], 2, 1_800_000_000);
+        let mut branch = Branch {
+            fork: 3,
+            headers: Vec::new(),
+        };
+        store.connect(&params, &mut branch, &fork).unwrap();
+        assert_eq!(store.hash(5).unwrap(), main[4].block_hash());
+        assert_eq!(branch.headers.len(), 2);
+        let more = chain(&fork[1], 1, 1_800_000_010);
+        store.connect(&params, &mut branch, &more).unwrap();
+        assert_eq!(store.tip(), 6);
+        assert_eq!(store.hash(6).unwrap(), more[0].block_hash());
+        assert!(branch.headers.is_empty());
+
+        // headers already stored are skipped
+        let mut branch = Branch {
+            fork: 0,
+            headers: Vec::new(),
+        };
+        store.connect(&params, &mut branch, &main[..3]).unwrap();
+        assert_eq!((branch.fork, branch.headers.len()), (3, 0));
+
+        // claiming an easier target than the chain requires is refused
+        let mut easy = mine(&more[0], 1_800_000_020);
+        easy.bits = CompactTarget::from_consensus(0x207f_fffe);
+        let mut branch = Branch {
+            fork: 6,
+            headers: Vec::new(),
+        };
+        assert!(store.connect(&params, &mut branch, &[easy]).is_err());
+
+        drop(store);
+        let mut store = HeaderStore::open(&path, Network::Regtest).unwrap();
+        assert_eq!(store.tip(), 6);
+        assert_eq!(store.locator().unwrap().last().unwrap().0, 0);
+        assert!(HeaderStore::open(&path, Network::Testnet).is_err());
+    }
+
+    #[test]
+    fn difficulty_adjustment() {
+        let params = Params::new(Network::Bitcoin);
+        let bits = CompactTarget::from_consensus(0x1d00_ffff);
+        let two_weeks = params.pow_target_timespan;
+        assert_eq!(retarget(bits, two_weeks, &params), bits);
+        // twice as fast halves the target
+        assert_eq!(
+            retarget(bits, two_weeks / 2, &params).to_consensus(),
+            0x1c7f_ff80
+        );
+        // changes are limited to a factor of four
+        assert_eq!(
+            retarget(bits, two_weeks / 8, &params).to_consensus(),
+            0x1c3f_ffc0
+        );
+        assert_eq!(retarget(bits, two_weeks * 8, &params), bits);
+    }
+
+    #[test]
+    fn fee_rate_from_coinbase() {
+        let spend = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let vsize = spend.vsize() as f64;
+        let block = |height: u32, subsidy: u64| {
+            let mut coinbase = spend.clone();
+            coinbase.output[0].value = Amount::from_sat(subsidy + 10_000);
+            let block = Block {
+                header: genesis_block(Network::Bitcoin).header,
+                txdata: vec![coinbase, spend.clone()],
+            };
+            block_fee_rate(&block, height, Network::Bitcoin)
+        };
+        assert_eq!(block(1, 5_000_000_000), Some(10_000. / vsize));
+        assert_eq!(block(210_000, 2_500_000_000), Some(10_000. / vsize));
+    }
+
+    /// Needs `bitcoind -regtest -blockfilterindex=1 -peerblockfilters=1` with RPC on the
+    /// default port, its cookie at the default path or in `SEASHELL_RPC_COOKIE`.
+    /// Run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn regtest_sync() {
+        use bdk_bitcoind_rpc::bitcoincore_rpc::{Auth, Client, RpcApi};
+        use bdk_wallet::bitcoin::bip32::Xpriv;
+
+        use crate::{
+            bdk_utils::{account_descriptors, AccountScript},
+            chain::RpcSettings,
+        };
+
+        let mut rpc = RpcSettings::for_network(Network::Regtest);
+        if let Ok(cookie) = std::env::var("SEASHELL_RPC_COOKIE") {
+            rpc.cookie_file = cookie;
+        }
+        let node = Client::new(&rpc.url, Auth::CookieFile(rpc.cookie_file.into())).unwrap();
+
+        let dir = crate::test_dir();
+        let mut settings: Settings =
+            serde_json::from_str(r#"{"electrum_servers": [], "wallet_db": ""}"#).unwrap();
+        settings.wallet_db = dir.path().to_string_lossy().to_string();
+        settings.cbf.peer = "127.0.0.1:18444".into();
+
+        let xprv = Xpriv::new_master(Network::Regtest, &[9; 32]).unwrap();
+        let (external, internal) = account_descriptors(xprv, AccountScript::Bip84, 0);
+        let mut wallet = Wallet::create(external, internal)
+            .network(Network::Regtest)
+            .create_wallet_no_persist()
+            .unwrap();
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        node.generate_to_address(101, &address).unwrap();
+
+        let mut source = CbfSource::new(&settings, Network::Regtest).unwrap();
+        source.full_scan(&mut wallet, 20, 5).unwrap();
+        let best = node.get_best_block_hash().unwrap();
+        assert_eq!(wallet.latest_checkpoint().hash(), best);
+        assert!(wallet.balance().confirmed.to_sat() > 0);
+
+        // replace the tip with a longer branch, the stale checkpoint goes away
+        let stale = wallet.latest_checkpoint().block_id();
+        node.invalidate_block(&best).unwrap();
+        node.generate_to_address(2, &address).unwrap();
+        source.sync(&mut wallet, 5).unwrap();
+        assert_eq!(
+            wallet.latest_checkpoint().hash(),
+            node.get_best_block_hash().unwrap()
+        );
+        assert_ne!(
+            wallet.local_chain().get(stale.height).map(|cp| cp.hash()),
+            Some(stale.hash)
+        );
+        assert!(source.estimate_fee(6).is_ok());
+    }
+}
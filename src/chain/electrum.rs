@@ -0,0 +1,332 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bdk_electrum::{
+    electrum_client::{
+        self, raw_client::RawClient, socks::Socks5Stream, Config, ElectrumApi, Socks5Config,
+    },
+    BdkElectrumClient,
+};
+use bdk_wallet::{
+    bitcoin::{FeeRate, Transaction, Txid},
+    chain::spk_client::{FullScanRequest, FullScanResult, SyncRequest, SyncResult},
+    KeychainKind, Wallet,
+};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, StreamOwned};
+
+use crate::app::settings::{ElectrumServer, Settings};
+
+use super::{is_onion, pinning::PinnedVerifier, ChainError, ChainSource, ServerInfo};
+
+/// The calls made against a server, so regular and pinned clients can be used alike
+trait Electrum {
+    fn sync(
+        &self,
+        request: SyncRequest<(KeychainKind, u32)>,
+        batch_size: usize,
+    ) -> Result<SyncResult, electrum_client::Error>;
+
+    fn full_scan(
+        &self,
+        request: FullScanRequest<KeychainKind>,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<FullScanResult<KeychainKind>, electrum_client::Error>;
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, electrum_client::Error>;
+
+    /// BTC/kvB
+    fn estimate_fee(&self, target: usize) -> Result<f64, electrum_client::Error>;
+
+    /// Server version and tip height
+    fn info(&self) -> Result<(String, u32), electrum_client::Error>;
+}
+
+impl<E: ElectrumApi> Electrum for BdkElectrumClient<E> {
+    fn sync(
+        &self,
+        request: SyncRequest<(KeychainKind, u32)>,
+        batch_size: usize,
+    ) -> Result<SyncResult, electrum_client::Error> {
+        BdkElectrumClient::sync(self, request, batch_size, true)
+    }
+
+    fn full_scan(
+        &self,
+        request: FullScanRequest<KeychainKind>,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<FullScanResult<KeychainKind>, electrum_client::Error> {
+        BdkElectrumClient::full_scan(self, request, stop_gap, batch_size, true)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, electrum_client::Error> {
+        self.transaction_broadcast(tx)
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<f64, electrum_client::Error> {
+        self.inner.estimate_fee(target)
+    }
+
+    fn info(&self) -> Result<(String, u32), electrum_client::Error> {
+        let features = self.inner.server_features()?;
+        let tip = self.inner.block_headers_subscribe()?;
+        Ok((features.server_version, tip.height as u32))
+    }
+}
+
+type Client = Box<dyn Electrum>;
+
+/// Error responses from the server are about the request, anything else is the connection
+fn chain_error(e: electrum_client::Error) -> ChainError {
+    use electrum_client::Error;
+    match e {
+        Error::Protocol(_)
+        | Error::InvalidResponse(_)
+        | Error::AlreadySubscribed(_)
+        | Error::NotSubscribed(_)
+        | Error::Bitcoin(_)
+        | Error::Hex(_) => ChainError::Request(e.to_string()),
+        _ => ChainError::Transport(e.to_string()),
+    }
+}
+
+fn socks5(settings: &Settings) -> Option<Socks5Config> {
+    let proxy = &settings.proxy;
+    if !proxy.enabled {
+        None
+    } else if proxy.user.is_empty() {
+        Some(Socks5Config::new(&proxy.socks5))
+    } else {
+        Some(Socks5Config::with_credentials(
+            &proxy.socks5,
+            proxy.user.clone(),
+            proxy.password.clone(),
+        ))
+    }
+}
+
+/// Whether `server` is reached over TLS with a pinned certificate instead of a validated domain.
+/// Onion servers are always pinned, their certificates can't be issued for the onion address.
+pub fn is_pinned(server: &ElectrumServer) -> bool {
+    server.url.starts_with("ssl://") && (!server.validate_domain || is_onion(&server.url))
+}
+
+/// Open a client to `server` with the proxy, timeout and retry settings applied
+fn open(server: &ElectrumServer, settings: &Settings) -> Result<Client, String> {
+    let url = &server.url;
+    let socks5 = socks5(settings);
+    if socks5.is_none() && is_onion(url) {
+        return Err(format!("{url}: onion addresses need a proxy"));
+    }
+    if is_pinned(server) {
+        return open_pinned(url, socks5, settings).map_err(|e| format!("{url}: {e}"));
+    }
+    let config = Config::builder()
+        .socks5(socks5)
+        .timeout(Some(settings.timeout_secs))
+        .retry(settings.retries)
+        .build();
+    let client =
+        electrum_client::Client::from_config(url, config).map_err(|e| format!("{url}: {e}"))?;
+    Ok(Box::new(BdkElectrumClient::new(client)))
+}
+
+/// TLS connection that trusts the certificate pinned for `url` instead of the usual roots.
+/// The bare client doesn't retry by itself, [`ElectrumSource::with_failover`] does.
+fn open_pinned(
+    url: &str,
+    socks5: Option<Socks5Config>,
+    settings: &Settings,
+) -> Result<Client, String> {
+    let addr = url.trim_start_matches("ssl://");
+    let (host, _) = addr.rsplit_once(':').ok_or("missing port")?;
+    let timeout = Duration::from_secs(settings.timeout_secs as u64);
+    let tcp = match socks5 {
+        Some(proxy) => {
+            let stream = match proxy.credentials {
+                Some(cred) => Socks5Stream::connect_with_password(
+                    &proxy.addr,
+                    addr,
+                    &cred.username,
+                    &cred.password,
+                    Some(timeout),
+                ),
+                None => Socks5Stream::connect(&proxy.addr, addr, Some(timeout)),
+            };
+            stream.map_err(|e| e.to_string())?.into_inner()
+        }
+        None => {
+            let socket = addr
+                .to_socket_addrs()
+                .map_err(|e| e.to_string())?
+                .next()
+                .ok_or("could not resolve address")?;
+            TcpStream::connect_timeout(&socket, timeout).map_err(|e| e.to_string())?
+        }
+    };
+    tcp.set_read_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+    tcp.set_write_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier::new(url)))
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    let conn = ClientConnection::new(Arc::new(config), name).map_err(|e| e.to_string())?;
+    let client = RawClient::from(StreamOwned::new(conn, tcp));
+    Ok(Box::new(BdkElectrumClient::new(client)))
+}
+
+/// Electrum source over an ordered list of servers.
+/// When a server fails the next one in the list is tried, wrapping around to the start.
+pub struct ElectrumSource {
+    settings: Settings,
+    current: usize,
+    client: Option<Client>,
+}
+
+impl ElectrumSource {
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        if settings.electrum_servers.is_empty() {
+            return Err("No Electrum servers configured".into());
+        }
+        let mut source = ElectrumSource {
+            settings: settings.clone(),
+            current: 0,
+            client: None,
+        };
+        source.connect_from(0)?;
+        Ok(source)
+    }
+
+    fn servers(&self) -> &[ElectrumServer] {
+        &self.settings.electrum_servers
+    }
+
+    /// Connect to the first server that answers, starting at `start`
+    fn connect_from(&mut self, start: usize) -> Result<(), String> {
+        let count = self.servers().len();
+        let mut errors = Vec::new();
+        for i in (0..count).map(|offset| (start + offset) % count) {
+            match open(&self.servers()[i], &self.settings) {
+                Ok(client) => {
+                    self.current = i;
+                    self.client = Some(client);
+                    return Ok(());
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        Err(errors.join("; "))
+    }
+
+    /// Run `op` against the current server, moving on to the next one whenever it can't be
+    /// reached. An error the server answers with, like a rejected transaction, is returned.
+    fn with_failover<T>(
+        &mut self,
+        mut op: impl FnMut(&dyn Electrum) -> Result<T, electrum_client::Error>,
+    ) -> Result<T, ChainError> {
+        let mut errors = Vec::new();
+        let mut failed = 0;
+        let mut retried = 0;
+        while failed < self.servers().len() {
+            let Some(client) = &self.client else {
+                break;
+            };
+            let server = self.servers()[self.current].clone();
+            match op(client.as_ref()).map_err(chain_error) {
+                Ok(res) => return Ok(res),
+                Err(ChainError::Request(e)) => {
+                    return Err(ChainError::Request(format!("{}: {e}", server.url)))
+                }
+                Err(e) => {
+                    errors.push(format!("{}: {e}", server.url));
+                    self.client = None;
+                    // regular clients reconnect and retry on their own, pinned ones are
+                    // reopened here
+                    if is_pinned(&server) && retried < self.settings.retries {
+                        retried += 1;
+                        match open(&server, &self.settings) {
+                            Ok(client) => {
+                                self.client = Some(client);
+                                continue;
+                            }
+                            Err(e) => errors.push(e),
+                        }
+                    }
+                    failed += 1;
+                    retried = 0;
+                    if let Err(e) = self.connect_from(self.current + 1) {
+                        errors.push(e);
+                        break;
+                    }
+                }
+            }
+        }
+        Err(ChainError::Transport(errors.join("; ")))
+    }
+}
+
+impl ChainSource for ElectrumSource {
+    fn sync(&mut self, wallet: &mut Wallet, batch_size: usize) -> Result<(), ChainError> {
+        let update = self.with_failover(|client| {
+            let request = wallet.start_sync_with_revealed_spks().build();
+            client.sync(request, batch_size)
+        })?;
+        wallet
+            .apply_update(update)
+            .map_err(|e| ChainError::Request(e.to_string()))
+    }
+
+    fn full_scan(
+        &mut self,
+        wallet: &mut Wallet,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<(), ChainError> {
+        let update = self.with_failover(|client| {
+            let request = wallet.start_full_scan().build();
+            client.full_scan(request, stop_gap, batch_size)
+        })?;
+        wallet
+            .apply_update(update)
+            .map_err(|e| ChainError::Request(e.to_string()))
+    }
+
+    fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, ChainError> {
+        self.with_failover(|client| client.broadcast(tx))
+    }
+
+    fn estimate_fee(&mut self, target: usize) -> Result<FeeRate, ChainError> {
+        // electrum reports BTC/kvB
+        let btc_kvb = self.with_failover(|client| client.estimate_fee(target))?;
+        super::fee_rate_from_sat_vb(btc_kvb * 100_000.)
+    }
+
+    fn server(&self) -> Option<String> {
+        self.client
+            .as_ref()
+            .map(|_| self.servers()[self.current].url.clone())
+    }
+}
+
+/// Connect to `server` and ask for its version and tip
+pub fn test_server(server: &ElectrumServer, settings: &Settings) -> Result<ServerInfo, String> {
+    let start = Instant::now();
+    let client = open(server, settings)?;
+    let (version, tip_height) = client.info().map_err(|e| e.to_string())?;
+    Ok(ServerInfo {
+        latency: start.elapsed(),
+        version,
+        tip_height,
+    })
+}
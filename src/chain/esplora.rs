@@ -0,0 +1,208 @@
+#[cfg(target_arch = "wasm32")]
+use std::{borrow::Borrow, future::Future};
+
+use bdk_esplora::esplora_client;
+#[cfg(target_arch = "wasm32")]
+use bdk_esplora::{esplora_client::AsyncClient, EsploraAsyncExt};
+#[cfg(not(target_arch = "wasm32"))]
+use bdk_esplora::{esplora_client::BlockingClient, EsploraExt};
+use bdk_wallet::{
+    bitcoin::{FeeRate, Transaction, Txid},
+    Wallet,
+};
+
+use crate::app::settings::Settings;
+
+use super::ChainError;
+#[cfg(not(target_arch = "wasm32"))]
+use super::{is_onion, with_retries, ChainSource};
+
+/// Esplora over HTTP. Native builds block on requests, in the browser they are async.
+pub struct EsploraSource {
+    #[cfg(not(target_arch = "wasm32"))]
+    client: BlockingClient,
+    #[cfg(target_arch = "wasm32")]
+    client: AsyncClient,
+    url: String,
+    retries: u8,
+}
+
+impl EsploraSource {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        let url = &settings.esplora_url;
+        let proxy = &settings.proxy;
+        let mut builder = esplora_client::Builder::new(url).timeout(settings.timeout_secs as u64);
+        if proxy.enabled {
+            if proxy.http_tunnel.is_empty() {
+                return Err("Esplora needs an HTTP tunnel proxy to go through Tor".into());
+            }
+            let auth = if proxy.user.is_empty() {
+                String::new()
+            } else {
+                format!("{}:{}@", proxy.user, proxy.password)
+            };
+            builder = builder.proxy(&format!("http://{auth}{}", proxy.http_tunnel));
+        } else if is_onion(url) {
+            return Err("Onion addresses need a proxy".into());
+        }
+        Ok(EsploraSource {
+            client: builder.build_blocking(),
+            url: url.clone(),
+            retries: settings.retries,
+        })
+    }
+
+    /// The browser routes requests through its own proxy and has no timeout to set
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        let url = &settings.esplora_url;
+        let client = esplora_client::Builder::new(url)
+            .build_async()
+            .map_err(|e| e.to_string())?;
+        Ok(EsploraSource {
+            client,
+            url: url.clone(),
+            retries: settings.retries,
+        })
+    }
+}
+
+/// Failed requests, server errors and rate limiting are worth another attempt, other
+/// responses are the server's answer to the request
+fn chain_error(e: &esplora_client::Error) -> ChainError {
+    match e {
+        #[cfg(not(target_arch = "wasm32"))]
+        esplora_client::Error::Minreq(_) => ChainError::Transport(e.to_string()),
+        #[cfg(target_arch = "wasm32")]
+        esplora_client::Error::Reqwest(_) => ChainError::Transport(e.to_string()),
+        esplora_client::Error::HttpResponse { status, .. } if *status >= 500 || *status == 429 => {
+            ChainError::Transport(e.to_string())
+        }
+        _ => ChainError::Request(e.to_string()),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ChainSource for EsploraSource {
+    fn sync(&mut self, wallet: &mut Wallet, batch_size: usize) -> Result<(), ChainError> {
+        let update = with_retries(self.retries, || {
+            let request = wallet.start_sync_with_revealed_spks().build();
+            self.client
+                .sync(request, batch_size)
+                .map_err(|e| chain_error(&e))
+        })?;
+        wallet
+            .apply_update(update)
+            .map_err(|e| ChainError::Request(e.to_string()))
+    }
+
+    fn full_scan(
+        &mut self,
+        wallet: &mut Wallet,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<(), ChainError> {
+        let update = with_retries(self.retries, || {
+            let request = wallet.start_full_scan().build();
+            self.client
+                .full_scan(request, stop_gap, batch_size)
+                .map_err(|e| chain_error(&e))
+        })?;
+        wallet
+            .apply_update(update)
+            .map_err(|e| ChainError::Request(e.to_string()))
+    }
+
+    fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, ChainError> {
+        with_retries(self.retries, || {
+            self.client.broadcast(tx).map_err(|e| chain_error(&e))
+        })?;
+        Ok(tx.compute_txid())
+    }
+
+    fn estimate_fee(&mut self, target: usize) -> Result<FeeRate, ChainError> {
+        let estimates = with_retries(self.retries, || {
+            self.client.get_fee_estimates().map_err(|e| chain_error(&e))
+        })?;
+        let sat_vb =
+            esplora_client::convert_fee_rate(target, estimates).map_err(|e| chain_error(&e))?;
+        super::fee_rate_from_sat_vb(sat_vb as f64)
+    }
+
+    fn server(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+}
+
+/// Like [`with_retries`](super::with_retries), for the browser's async requests
+#[cfg(target_arch = "wasm32")]
+async fn with_retries_async<T, E, F>(
+    retries: u8,
+    mut op: impl FnMut() -> F,
+) -> Result<T, ChainError>
+where
+    E: Borrow<esplora_client::Error>,
+    F: Future<Output = Result<T, E>>,
+{
+    let mut res = op().await.map_err(|e| chain_error(e.borrow()));
+    for _ in 0..retries {
+        if !res.as_ref().is_err_and(ChainError::is_transport) {
+            break;
+        }
+        res = op().await.map_err(|e| chain_error(e.borrow()));
+    }
+    res
+}
+
+/// The [`ChainSource`](super::ChainSource) calls for the browser, which can't block on a
+/// request
+#[cfg(target_arch = "wasm32")]
+impl EsploraSource {
+    pub async fn sync(&mut self, wallet: &mut Wallet, batch_size: usize) -> Result<(), ChainError> {
+        let client = &self.client;
+        let update = with_retries_async(self.retries, || {
+            let request = wallet.start_sync_with_revealed_spks().build();
+            client.sync(request, batch_size)
+        })
+        .await?;
+        wallet
+            .apply_update(update)
+            .map_err(|e| ChainError::Request(e.to_string()))
+    }
+
+    pub async fn full_scan(
+        &mut self,
+        wallet: &mut Wallet,
+        stop_gap: usize,
+        batch_size: usize,
+    ) -> Result<(), ChainError> {
+        let client = &self.client;
+        let update = with_retries_async(self.retries, || {
+            let request = wallet.start_full_scan().build();
+            client.full_scan(request, stop_gap, batch_size)
+        })
+        .await?;
+        wallet
+            .apply_update(update)
+            .map_err(|e| ChainError::Request(e.to_string()))
+    }
+
+    pub async fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, ChainError> {
+        let client = &self.client;
+        with_retries_async(self.retries, || client.broadcast(tx)).await?;
+        Ok(tx.compute_txid())
+    }
+
+    pub async fn estimate_fee(&mut self, target: usize) -> Result<FeeRate, ChainError> {
+        let client = &self.client;
+        let estimates = with_retries_async(self.retries, || client.get_fee_estimates()).await?;
+        let sat_vb =
+            esplora_client::convert_fee_rate(target, estimates).map_err(|e| chain_error(&e))?;
+        super::fee_rate_from_sat_vb(sat_vb as f64)
+    }
+
+    pub fn server(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bdk_wallet::bitcoin::hashes::{sha256, Hash};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+
+use crate::app::settings;
+
+/// Certificate fingerprints of servers trusted on first use, keyed by server url
+const KNOWN_SERVERS: &str = "known_servers.json";
+
+/// Connections refused because a pinned certificate changed, waiting to be shown to the user
+static CHANGES: Mutex<Vec<CertChange>> = Mutex::new(Vec::new());
+
+/// A server presented a different certificate than the one pinned for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertChange {
+    pub url: String,
+    pub pinned: String,
+    pub presented: String,
+}
+
+fn path() -> Option<PathBuf> {
+    let mut dir = dirs::config_dir()?;
+    dir.push(settings::FOLDER);
+    dir.push(KNOWN_SERVERS);
+    Some(dir)
+}
+
+fn load() -> HashMap<String, String> {
+    path()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn store(known: &HashMap<String, String>) -> Result<(), String> {
+    write_known(&path().ok_or("Unable to find config dir")?, known)
+}
+
+/// Write the pins to `path`, creating its folder on first use
+fn write_known(path: &Path, known: &HashMap<String, String>) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    serde_json::to_writer_pretty(file, known).map_err(|e| e.to_string())
+}
+
+/// Pinned fingerprint for `url`, if any
+pub fn pinned(url: &str) -> Option<String> {
+    load().remove(url)
+}
+
+/// Pin `fingerprint` for `url`, replacing any previous pin
+pub fn trust(url: &str, fingerprint: &str) -> Result<(), String> {
+    let mut known = load();
+    known.insert(url.into(), fingerprint.into());
+    store(&known)
+}
+
+pub fn forget(url: &str) -> Result<(), String> {
+    let mut known = load();
+    known.remove(url);
+    store(&known)
+}
+
+/// Certificate changes seen since the last call
+pub fn take_changes() -> Vec<CertChange> {
+    std::mem::take(&mut *CHANGES.lock().unwrap())
+}
+
+pub fn fingerprint(cert: &[u8]) -> String {
+    sha256::Hash::hash(cert).to_string()
+}
+
+/// Accepts any certificate the first time a server is seen and pins its fingerprint,
+/// afterwards only that certificate is accepted. Handshake signatures are still checked
+/// so the server has to hold the pinned certificate's key.
+#[derive(Debug)]
+pub struct PinnedVerifier {
+    url: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedVerifier {
+    pub fn new(url: &str) -> Self {
+        PinnedVerifier {
+            url: url.into(),
+            provider: Arc::new(rustls::crypto::aws_lc_rs::default_provider()),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let presented = fingerprint(end_entity);
+        match pinned(&self.url) {
+            None => trust(&self.url, &presented).map_err(rustls::Error::General)?,
+            Some(pinned) if pinned != presented => {
+                CHANGES.lock().unwrap().push(CertChange {
+                    url: self.url.clone(),
+                    pinned,
+                    presented,
+                });
+                return Err(rustls::Error::General(
+                    "server certificate does not match the pinned one".into(),
+                ));
+            }
+            Some(_) => {}
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_pin_creates_folder() {
+        let tmp = crate::test_dir();
+        let path = tmp.path().join("config").join(KNOWN_SERVERS);
+        let known = HashMap::from([("ssl://a:50002".to_string(), fingerprint(b"cert"))]);
+        write_known(&path, &known).unwrap();
+        let read: HashMap<String, String> =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(read, known);
+    }
+}
@@ -0,0 +1,218 @@
+use bdk_bitcoind_rpc::{
+    bitcoincore_rpc::{self, jsonrpc, Auth, Client, RpcApi},
+    Emitter,
+};
+use bdk_wallet::{
+    bitcoin::{FeeRate, Transaction, Txid},
+    chain::{BlockId, CheckPoint},
+    Wallet,
+};
+
+use std::time::Duration;
+
+use crate::app::settings::Settings;
+
+use super::{is_onion, ChainError, ChainSource, GapScripts};
+
+/// Chain source backed by a Bitcoin Core node, emitting blocks one at a time plus the mempool
+pub struct RpcSource {
+    client: Client,
+    url: String,
+    start_height: u32,
+}
+
+/// Node replies, rejections included, are about the request; failing to reach it is not
+fn chain_error(e: bitcoincore_rpc::Error) -> ChainError {
+    match e {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(_))
+        | bitcoincore_rpc::Error::Io(_) => ChainError::Transport(e.to_string()),
+        _ => ChainError::Request(e.to_string()),
+    }
+}
+
+impl RpcSource {
+    /// Connect to the node in `settings.rpc`, through the SOCKS5 proxy when one is enabled.
+    /// The node's host name is resolved locally, so an onion address can't be reached.
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        let rpc = &settings.rpc;
+        if is_onion(&rpc.url) {
+            return Err("RPC can't reach onion addresses, use a node on the local network".into());
+        }
+        let auth = if rpc.use_cookie {
+            Auth::CookieFile(rpc.cookie_file.clone().into())
+        } else {
+            Auth::UserPass(rpc.user.clone(), rpc.password.clone())
+        };
+        let (user, password) = auth.get_user_pass().map_err(|e| e.to_string())?;
+        let mut builder = jsonrpc::simple_http::Builder::new()
+            .url(&rpc.url)
+            .map_err(|e| e.to_string())?
+            .timeout(Duration::from_secs(settings.timeout_secs.into()));
+        if let Some(user) = user {
+            builder = builder.auth(user, password);
+        }
+        let proxy = &settings.proxy;
+        if proxy.enabled {
+            builder = builder
+                .proxy_addr(&proxy.socks5)
+                .map_err(|e| e.to_string())?;
+            if !proxy.user.is_empty() {
+                builder = builder.proxy_auth(&proxy.user, &proxy.password);
+            }
+        }
+        Ok(RpcSource {
+            client: Client::from_jsonrpc(jsonrpc::Client::with_transport(builder.build())),
+            url: rpc.url.clone(),
+            start_height: rpc.start_height,
+        })
+    }
+
+    /// Apply every block after `last_cp` followed by the current mempool. Blocks are matched
+    /// against scripts `gap` past the last revealed index of each keychain, and when a payment
+    /// moves the gap the blocks before it are applied again for the scripts it moved over.
+    fn emit(&self, wallet: &mut Wallet, last_cp: CheckPoint, gap: u32) -> Result<(), ChainError> {
+        let mut gap_spks = GapScripts::default();
+        gap_spks.extend(wallet, gap);
+        let mut emitter = Emitter::new(&self.client, last_cp.clone(), self.start_height);
+        let mut moved = apply_blocks(wallet, &mut emitter, &mut gap_spks, gap, u32::MAX)?;
+        let mempool = emitter.mempool().map_err(chain_error)?;
+        while let Some(height) = moved {
+            let mut emitter = Emitter::new(&self.client, last_cp.clone(), self.start_height);
+            moved = apply_blocks(wallet, &mut emitter, &mut gap_spks, gap, height)?;
+        }
+        for (tx, _) in &mempool {
+            gap_spks.reveal_paid(wallet, tx);
+        }
+        wallet.apply_unconfirmed_txs(mempool.iter().map(|(tx, time)| (tx, *time)));
+        Ok(())
+    }
+}
+
+/// Apply the blocks `emitter` emits up to height `until`, returning the last height after
+/// which the gap moved
+fn apply_blocks(
+    wallet: &mut Wallet,
+    emitter: &mut Emitter<'_, Client>,
+    gap_spks: &mut GapScripts,
+    gap: u32,
+    until: u32,
+) -> Result<Option<u32>, ChainError> {
+    let mut moved = None;
+    while let Some(event) = emitter.next_block().map_err(chain_error)? {
+        let height = event.block_height();
+        if height > until {
+            break;
+        }
+        for tx in &event.block.txdata {
+            gap_spks.reveal_paid(wallet, tx);
+        }
+        wallet
+            .apply_block_connected_to(&event.block, height, event.connected_to())
+            .map_err(|e| ChainError::Request(e.to_string()))?;
+        if !gap_spks.extend(wallet, gap).is_empty() {
+            moved = Some(height);
+        }
+    }
+    Ok(moved)
+}
+
+impl ChainSource for RpcSource {
+    /// Blocks are scanned against every revealed and lookahead script
+    fn sync(&mut self, wallet: &mut Wallet, _batch_size: usize) -> Result<(), ChainError> {
+        let tip = wallet.latest_checkpoint();
+        let lookahead = wallet.spk_index().lookahead();
+        self.emit(wallet, tip, lookahead)
+    }
+
+    /// Starts again from `start_height`, scanning `stop_gap` scripts past the last revealed
+    /// one of each keychain, or the lookahead when that reaches further
+    fn full_scan(
+        &mut self,
+        wallet: &mut Wallet,
+        stop_gap: usize,
+        _batch_size: usize,
+    ) -> Result<(), ChainError> {
+        let genesis = BlockId {
+            height: 0,
+            hash: wallet.local_chain().genesis_hash(),
+        };
+        let gap = wallet.spk_index().lookahead().max(stop_gap as u32);
+        self.emit(wallet, CheckPoint::new(genesis), gap)
+    }
+
+    fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, ChainError> {
+        self.client.send_raw_transaction(tx).map_err(chain_error)
+    }
+
+    fn estimate_fee(&mut self, target: usize) -> Result<FeeRate, ChainError> {
+        let res = self
+            .client
+            .estimate_smart_fee(target as u16, None)
+            .map_err(chain_error)?;
+        match res.fee_rate {
+            // reported per kvB
+            Some(per_kvb) => super::fee_rate_from_sat_vb(per_kvb.to_sat() as f64 / 1000.),
+            None => Err(ChainError::Request(
+                res.errors
+                    .map(|e| e.join(", "))
+                    .unwrap_or("no fee estimate available".into()),
+            )),
+        }
+    }
+
+    fn server(&self) -> Option<String> {
+        Some(self.url.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::{
+        bitcoin::{bip32::Xpriv, Network},
+        KeychainKind,
+    };
+
+    use super::*;
+    use crate::bdk_utils::{account_descriptors, AccountScript};
+    use crate::chain::RpcSettings;
+
+    /// Needs `bitcoind -regtest` on the default port with its default cookie, or the
+    /// cookie path in `SEASHELL_RPC_COOKIE`. Run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn regtest_sync() {
+        let mut settings = Settings::new();
+        settings.rpc = RpcSettings::for_network(Network::Regtest);
+        if let Ok(cookie) = std::env::var("SEASHELL_RPC_COOKIE") {
+            settings.rpc.cookie_file = cookie;
+        }
+        let mut source = RpcSource::new(&settings).unwrap();
+
+        let xprv = Xpriv::new_master(Network::Regtest, &[7; 32]).unwrap();
+        let (external, internal) = account_descriptors(xprv, AccountScript::Bip84, 0);
+        let mut wallet = Wallet::create(external, internal)
+            .network(Network::Regtest)
+            .create_wallet_no_persist()
+            .unwrap();
+        let address = wallet.reveal_next_address(KeychainKind::External).address;
+        source.client.generate_to_address(101, &address).unwrap();
+
+        source.full_scan(&mut wallet, 20, 5).unwrap();
+        let tip = source.client.get_block_count().unwrap() as u32;
+        assert_eq!(wallet.latest_checkpoint().height(), tip);
+        assert!(wallet.balance().confirmed.to_sat() > 0);
+
+        source.client.generate_to_address(1, &address).unwrap();
+        source.sync(&mut wallet, 5).unwrap();
+        assert_eq!(wallet.latest_checkpoint().height(), tip + 1);
+
+        // a payment past the lookahead is found by a scan with a larger stop gap
+        let far = wallet.peek_address(KeychainKind::External, 60).address;
+        source.client.generate_to_address(1, &far).unwrap();
+        source.full_scan(&mut wallet, 100, 5).unwrap();
+        let revealed = wallet
+            .spk_index()
+            .last_revealed_index(KeychainKind::External);
+        assert_eq!(revealed, Some(60));
+    }
+}
@@ -0,0 +1,186 @@
+use bdk_wallet::{
+    bitcoin::{
+        hashes::{sha256, Hash},
+        hex::DisplayHex,
+        key::rand::{thread_rng, Rng},
+    },
+    keys::bip39::Mnemonic,
+};
+
+/// Mnemonic lengths offered when making a new seed
+pub const WORD_COUNTS: [usize; 3] = [12, 18, 24];
+
+/// Where user supplied entropy comes from
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Source {
+    None,
+    Dice,
+    Coins,
+}
+
+impl Source {
+    fn bits_per_symbol(self) -> f64 {
+        match self {
+            Source::None => 0.,
+            Source::Dice => 6f64.log2(),
+            Source::Coins => 1.,
+        }
+    }
+
+    /// The symbols in `input`, ignoring whitespace. Coin flips are written as 1 for heads
+    /// and 0 for tails.
+    fn normalize(self, input: &str) -> Result<String, String> {
+        input
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match (self, c.to_ascii_uppercase()) {
+                (Source::Dice, '1'..='6') => Ok(c),
+                (Source::Coins, '1' | 'H') => Ok('1'),
+                (Source::Coins, '0' | 'T') => Ok('0'),
+                (Source::Dice, _) => Err(format!("\"{c}\" is not a die roll, use 1 to 6")),
+                _ => Err(format!("\"{c}\" is not a coin flip, use H/T or 1/0")),
+            })
+            .collect()
+    }
+}
+
+/// A new seed and every input that went into it
+#[derive(Debug, Clone)]
+pub struct SeedEntropy {
+    pub mnemonic: Mnemonic,
+    /// System randomness, when it was mixed in
+    pub rng: Option<Vec<u8>>,
+    pub source: Source,
+    /// User symbols as hashed
+    pub user: String,
+    pub entropy: Vec<u8>,
+}
+
+/// Make a `words` long seed from system randomness, user entropy or both.
+/// With user entropy the seed entropy is the start of SHA256(system bytes || symbols), so
+/// without system randomness anyone can recompute it from the symbols alone.
+pub fn generate(
+    words: usize,
+    use_rng: bool,
+    source: Source,
+    input: &str,
+) -> Result<SeedEntropy, String> {
+    if !WORD_COUNTS.contains(&words) {
+        return Err(format!("A seed can't have {words} words"));
+    }
+    let len = words / 3 * 4;
+    let user = match source {
+        Source::None => String::new(),
+        _ => source.normalize(input)?,
+    };
+    let user_bits = user.len() as f64 * source.bits_per_symbol();
+    if !use_rng && user_bits < (len * 8) as f64 {
+        let needed = ((len * 8) as f64 / source.bits_per_symbol()).ceil();
+        return Err(format!(
+            "{} symbols give about {user_bits:.0} bits, a {words} word seed needs {} \
+            ({needed} symbols) without system randomness",
+            user.len(),
+            len * 8,
+        ));
+    }
+    let rng = use_rng.then(|| {
+        let mut bytes = vec![0u8; len];
+        thread_rng().fill(&mut bytes[..]);
+        bytes
+    });
+    let entropy = if user.is_empty() {
+        rng.clone().unwrap_or_default()
+    } else {
+        let mut data = rng.clone().unwrap_or_default();
+        data.extend_from_slice(user.as_bytes());
+        sha256::Hash::hash(&data).as_byte_array()[..len].to_vec()
+    };
+    let mnemonic = Mnemonic::from_entropy(&entropy).map_err(|e| e.to_string())?;
+    Ok(SeedEntropy {
+        mnemonic,
+        rng,
+        source,
+        user,
+        entropy,
+    })
+}
+
+impl SeedEntropy {
+    /// Step by step account of how the seed was derived
+    pub fn explain(&self) -> Vec<String> {
+        let words = self.mnemonic.word_count();
+        let bits = self.entropy.len() * 8;
+        let mut lines = vec![format!(
+            "{words} words: {bits} bits of entropy and a {} bit checksum",
+            bits / 32
+        )];
+        match &self.rng {
+            Some(rng) => lines.push(format!("System randomness: {}", rng.to_lower_hex_string())),
+            None => lines.push("System randomness: not used".into()),
+        }
+        if !self.user.is_empty() {
+            let kind = match self.source {
+                Source::Dice => "Dice rolls",
+                _ => "Coin flips (1 heads, 0 tails)",
+            };
+            lines.push(format!(
+                "{kind}: {} ({} symbols, about {:.0} bits)",
+                self.user,
+                self.user.len(),
+                self.user.len() as f64 * self.source.bits_per_symbol()
+            ));
+            let hashed = if self.rng.is_some() {
+                "system randomness bytes followed by the symbols as ASCII"
+            } else {
+                "the symbols as ASCII"
+            };
+            lines.push(format!(
+                "Entropy: first {} bytes of SHA256 of {hashed}",
+                self.entropy.len()
+            ));
+            if self.rng.is_none() {
+                lines.push(format!("Check with: printf %s {} | sha256sum", self.user));
+            }
+        } else {
+            lines.push("Entropy: the system randomness as is".into());
+        }
+        lines.push(format!("Entropy: {}", self.entropy.to_lower_hex_string()));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_lengths() {
+        for words in WORD_COUNTS {
+            let seed = generate(words, true, Source::None, "").unwrap();
+            assert_eq!(seed.mnemonic.word_count(), words);
+            assert_eq!(seed.entropy, seed.rng.clone().unwrap());
+        }
+        assert!(generate(15, true, Source::None, "").is_err());
+    }
+
+    #[test]
+    fn dice_only() {
+        // 100 rolls carry about 258 bits, 99 and 49 fall short of 256 and 128
+        let rolls = "1".repeat(100);
+        assert!(generate(24, false, Source::Dice, &rolls[..99]).is_err());
+        assert!(generate(12, false, Source::Dice, &rolls[..49]).is_err());
+        assert!(generate(12, false, Source::Dice, "12x").is_err());
+
+        let seed = generate(24, false, Source::Dice, &rolls).unwrap();
+        let expected = sha256::Hash::hash(rolls.as_bytes());
+        assert_eq!(seed.entropy, expected.as_byte_array().to_vec());
+        // same rolls, same seed
+        let again = generate(24, false, Source::Dice, &rolls).unwrap();
+        assert_eq!(seed.mnemonic, again.mnemonic);
+        assert!(seed.explain().iter().any(|l| l.starts_with("Check with")));
+
+        let coins = generate(12, true, Source::Coins, "H T h t 1 0").unwrap();
+        assert_eq!(coins.user, "101010");
+        assert_ne!(coins.entropy, coins.rng.clone().unwrap());
+    }
+}
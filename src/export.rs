@@ -0,0 +1,161 @@
+use bdk_wallet::{
+    bitcoin::{bip32::Fingerprint, Network},
+    chain::ChainPosition,
+    miniscript::{
+        descriptor::{DescriptorXKey, Wildcard},
+        Descriptor, DescriptorPublicKey, ForEachKey,
+    },
+    KeychainKind, Wallet,
+};
+use serde_json::json;
+
+use crate::multisig::NUMS;
+
+/// Public data a watch-only coordinator needs to track the wallet
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletExport {
+    pub name: String,
+    pub network: Network,
+    /// Public descriptors with checksums
+    pub external: String,
+    pub internal: String,
+    /// Master fingerprint and the account key with its origin, one per signer
+    pub keys: Vec<(Fingerprint, String)>,
+    /// Height and time of the first confirmed transaction
+    pub birth: Option<(u32, u64)>,
+    /// Highest derivation index a rescan should cover
+    pub range_end: u32,
+    /// Receive address at index 0, lets cosigners check they built the same wallet
+    pub first_address: String,
+}
+
+/// Account keys in `desc` with the derivation below the account stripped
+fn account_keys(desc: &Descriptor<DescriptorPublicKey>) -> Vec<(Fingerprint, String)> {
+    let mut keys = Vec::new();
+    desc.for_each_key(|key| {
+        // the unspendable taproot internal key of a multisig belongs to nobody
+        if key.to_string() == NUMS {
+            return true;
+        }
+        let account = match key {
+            DescriptorPublicKey::XPub(xkey) => DescriptorPublicKey::XPub(DescriptorXKey {
+                derivation_path: Default::default(),
+                wildcard: Wildcard::None,
+                ..xkey.clone()
+            }),
+            other => other.clone(),
+        };
+        let entry = (key.master_fingerprint(), account.to_string());
+        if !keys.contains(&entry) {
+            keys.push(entry);
+        }
+        true
+    });
+    keys
+}
+
+impl WalletExport {
+    pub fn new(wallet: &Wallet, name: &str) -> Self {
+        let external = wallet.public_descriptor(KeychainKind::External);
+        let birth = wallet
+            .transactions()
+            .filter_map(|tx| match tx.chain_position {
+                ChainPosition::Confirmed(anchor) => {
+                    Some((anchor.block_id.height, anchor.confirmation_time))
+                }
+                ChainPosition::Unconfirmed(_) => None,
+            })
+            .min();
+        let revealed = [KeychainKind::External, KeychainKind::Internal]
+            .into_iter()
+            .filter_map(|kc| wallet.derivation_index(kc))
+            .max()
+            .unwrap_or(0);
+        WalletExport {
+            name: name.into(),
+            network: wallet.network(),
+            external: external.to_string(),
+            internal: wallet.public_descriptor(KeychainKind::Internal).to_string(),
+            keys: account_keys(external),
+            birth,
+            range_end: revealed + wallet.spk_index().lookahead(),
+            first_address: wallet.peek_address(KeychainKind::External, 0).to_string(),
+        }
+    }
+
+    /// Request body for Bitcoin Core's `importdescriptors`
+    pub fn core_import(&self) -> String {
+        // without a known birth there is nothing to rescan for
+        let timestamp = match self.birth {
+            Some((_, time)) => json!(time),
+            None => json!("now"),
+        };
+        let entry = |desc: &str, internal: bool| {
+            json!({
+                "desc": desc,
+                "timestamp": timestamp,
+                "active": true,
+                "internal": internal,
+                "range": [0, self.range_end],
+            })
+        };
+        let body = json!([entry(&self.external, false), entry(&self.internal, true)]);
+        serde_json::to_string_pretty(&body).expect("json values serialize")
+    }
+
+    /// BIP129 descriptor record for the other signers of a multisig wallet
+    pub fn bsms(&self) -> Option<String> {
+        if self.keys.len() < 2 {
+            return None;
+        }
+        let desc = self.external.split('#').next().unwrap_or(&self.external);
+        Some(format!(
+            "BSMS 1.0\n{}\n/0/*,/1/*\n{}\n",
+            desc.replace("/0/*", "/**"),
+            self.first_address
+        ))
+    }
+
+    /// Wallet file in the format Specter Desktop exports, which Sparrow can import
+    pub fn specter_file(&self) -> String {
+        let devices: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(fp, _)| json!({ "type": "other", "label": format!("Signer {fp}") }))
+            .collect();
+        let body = json!({
+            "label": self.name,
+            "blockheight": self.birth.map(|(height, _)| height).unwrap_or(0),
+            "descriptor": self.external,
+            "devices": devices,
+        });
+        serde_json::to_string_pretty(&body).expect("json values serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_test_wallet() {
+        let wallet = crate::load_test_wallet("tests", "tw").unwrap();
+        let export = WalletExport::new(&wallet, "tw");
+        assert!(export.external.starts_with("wpkh(["));
+        assert!(export.external.contains("/0/*)#"));
+        assert!(export.internal.contains("/1/*)#"));
+        assert!(!export.external.contains("tprv"));
+
+        assert_eq!(export.keys.len(), 1);
+        let (fp, key) = &export.keys[0];
+        assert!(key.starts_with(&format!("[{fp}/84'/1'/0']tpub")));
+        assert!(!key.ends_with("/*"));
+
+        let core: serde_json::Value = serde_json::from_str(&export.core_import()).unwrap();
+        assert_eq!(core[0]["desc"], export.external.as_str());
+        assert_eq!(core[1]["internal"], true);
+        let specter: serde_json::Value = serde_json::from_str(&export.specter_file()).unwrap();
+        assert_eq!(specter["descriptor"], export.external.as_str());
+        assert_eq!(specter["label"], "tw");
+    }
+}
@@ -0,0 +1,468 @@
+use std::str::FromStr;
+
+use bdk_wallet::{
+    bitcoin::{base58, secp256k1::Secp256k1, Address, Network, NetworkKind},
+    miniscript::{Descriptor, DescriptorPublicKey, ForEachKey},
+};
+use serde_json::Value;
+
+/// Descriptors recovered from another wallet's export file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedWallet {
+    /// Which kind of file the descriptors came from
+    pub format: &'static str,
+    pub external: String,
+    pub internal: String,
+    /// Whether the extended keys are for mainnet or the test networks, `None` when there
+    /// are only bare keys that don't say
+    pub network: Option<NetworkKind>,
+    pub label: Option<String>,
+}
+
+/// Read an Electrum wallet file, a Sparrow descriptor export, Bitcoin Core `listdescriptors`
+/// output or a JSON object holding descriptors
+pub fn parse(data: &str) -> Result<ImportedWallet, String> {
+    let data = data.trim();
+    if data.starts_with("BIE1") {
+        return Err(
+            "Encrypted Electrum wallets can't be imported, disable the password first".into(),
+        );
+    }
+    let (format, external, internal, label) = match serde_json::from_str::<Value>(data) {
+        Ok(json) => {
+            // bitcoin-cli prints the result, the raw RPC response wraps it
+            let json = json.get("result").unwrap_or(&json);
+            if json.get("keystore").is_some() || json.get("x1/").is_some() {
+                let (ext, int) = electrum(json)?;
+                ("Electrum", ext, int, None)
+            } else if let Some(descriptors) = json.get("descriptors") {
+                let (ext, int) = core(descriptors)?;
+                let label = str_field(json, &["wallet_name"]);
+                ("Bitcoin Core", ext, int, label)
+            } else {
+                let (ext, int) = generic(json)?;
+                let label = str_field(json, &["label", "name", "wallet_name"]);
+                ("Descriptor JSON", ext, int, label)
+            }
+        }
+        Err(_) if data.starts_with("BSMS") => {
+            let (ext, int) = bsms(data)?;
+            ("BSMS", ext, int, None)
+        }
+        Err(_) => {
+            let (ext, int) = text(data)?;
+            ("Descriptor text", ext, int, None)
+        }
+    };
+    let network = network_kind(&[&external, &internal])?;
+    Ok(ImportedWallet {
+        format,
+        external,
+        internal,
+        network,
+        label,
+    })
+}
+
+impl ImportedWallet {
+    /// Reject `network` when the keys were made for another one
+    pub fn check_network(&self, network: Network) -> Result<(), String> {
+        match self.network {
+            Some(kind) if kind != NetworkKind::from(network) => Err(format!(
+                "The keys are for {}, not {network}",
+                match kind {
+                    NetworkKind::Main => "mainnet",
+                    NetworkKind::Test => "the test networks",
+                }
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Network of the extended keys in `descriptors`, which must all agree
+fn network_kind(descriptors: &[&str]) -> Result<Option<NetworkKind>, String> {
+    let secp = Secp256k1::new();
+    let mut kinds = Vec::new();
+    for desc in descriptors {
+        // checksums are left for wallet creation to verify
+        let desc = desc.split('#').next().unwrap_or(desc);
+        let (desc, _) = Descriptor::parse_descriptor(&secp, desc).map_err(|e| e.to_string())?;
+        desc.for_each_key(|key| {
+            match key {
+                DescriptorPublicKey::XPub(x) => kinds.push(x.xkey.network),
+                DescriptorPublicKey::MultiXPub(x) => kinds.push(x.xkey.network),
+                DescriptorPublicKey::Single(_) => {}
+            }
+            true
+        });
+    }
+    match kinds.split_first() {
+        Some((first, rest)) if rest.iter().any(|k| k != first) => {
+            Err("The descriptors mix mainnet and testnet keys".into())
+        }
+        first => Ok(first.map(|(kind, _)| *kind)),
+    }
+}
+
+fn str_field(json: &Value, names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|n| json.get(n)?.as_str())
+        .map(String::from)
+}
+
+fn strip_checksum(desc: &str) -> &str {
+    desc.split('#').next().unwrap_or(desc)
+}
+
+/// Receive and change descriptors from a BIP389 `<0;1>` descriptor
+fn split_multipath(desc: &str) -> Option<(String, String)> {
+    let desc = strip_checksum(desc);
+    desc.contains("<0;1>")
+        .then(|| (desc.replace("<0;1>", "0"), desc.replace("<0;1>", "1")))
+}
+
+/// Change descriptor matching a receive descriptor on the usual `/0/*` branch
+fn change_for(desc: &str) -> Option<String> {
+    let desc = strip_checksum(desc);
+    desc.contains("/0/*").then(|| desc.replace("/0/*", "/1/*"))
+}
+
+/// A single descriptor that expands to both keychains, or a receive and change pair
+fn pair(external: &str, internal: Option<&str>) -> Result<(String, String), String> {
+    if let Some(pair) = split_multipath(external) {
+        return Ok(pair);
+    }
+    match internal {
+        Some(internal) => Ok((external.into(), internal.into())),
+        None => change_for(external)
+            .map(|internal| (external.into(), internal))
+            .ok_or_else(|| "No change descriptor found".into()),
+    }
+}
+
+fn generic(json: &Value) -> Result<(String, String), String> {
+    let external = str_field(
+        json,
+        &[
+            "descriptor",
+            "external_descriptor",
+            "receive_descriptor",
+            "external",
+            "desc",
+        ],
+    )
+    .ok_or("No descriptor found in file")?;
+    let internal = str_field(
+        json,
+        &[
+            "change_descriptor",
+            "internal_descriptor",
+            "internal",
+            "change",
+        ],
+    );
+    pair(&external, internal.as_deref())
+}
+
+/// Descriptor lines of a text export, `#` comment lines are skipped
+fn text(data: &str) -> Result<(String, String), String> {
+    let lines: Vec<&str> = data
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+    let external = lines.first().ok_or("No descriptor found in file")?;
+    if let Some(pair) = split_multipath(external) {
+        return Ok(pair);
+    }
+    let internal = lines.iter().find(|l| l.contains("/1/*")).copied();
+    let external = lines
+        .iter()
+        .find(|l| l.contains("/0/*"))
+        .unwrap_or(external);
+    pair(external, internal)
+}
+
+/// BIP129 descriptor record: version, template with `/**` keys, path restrictions, address
+fn bsms(data: &str) -> Result<(String, String), String> {
+    let mut lines = data.lines().map(str::trim);
+    if lines.next() != Some("BSMS 1.0") {
+        return Err("Unsupported BSMS version".into());
+    }
+    let template = lines.next().ok_or("Missing BSMS descriptor")?;
+    let (external, internal) = pair(&template.replace("/**", "/<0;1>/*"), None)?;
+    // the wallet uses /0/* and /1/*, which is all a record without restrictions allows too
+    match lines.next() {
+        Some("/0/*,/1/*" | "No path restrictions") => {}
+        Some(paths) => return Err(format!("Unsupported BSMS path restrictions \"{paths}\"")),
+        None => return Err("Missing BSMS path restrictions".into()),
+    }
+    let address = lines.next().ok_or("Missing BSMS first address")?;
+    let expected = Address::from_str(address)
+        .map_err(|e| format!("Invalid BSMS first address: {e}"))?
+        .assume_checked()
+        .script_pubkey();
+    let secp = Secp256k1::new();
+    let desc = external.split('#').next().unwrap_or(&external);
+    let (desc, _) = Descriptor::parse_descriptor(&secp, desc).map_err(|e| e.to_string())?;
+    let first = desc
+        .at_derivation_index(0)
+        .map_err(|e| e.to_string())?
+        .script_pubkey();
+    if first != expected {
+        return Err("The BSMS first address does not match its descriptor".into());
+    }
+    Ok((external, internal))
+}
+
+/// Script part of a descriptor before its first key, e.g. `sh(wpkh(`
+fn script_of(desc: &str) -> &str {
+    let mut end = 0;
+    loop {
+        let rest = &desc.as_bytes()[end..];
+        let name = rest.iter().take_while(|b| b.is_ascii_lowercase()).count();
+        if name == 0 || rest.get(name) != Some(&b'(') {
+            return &desc[..end];
+        }
+        end += name + 1;
+    }
+}
+
+/// Active descriptor pair from `listdescriptors`, native segwit preferred
+fn core(descriptors: &Value) -> Result<(String, String), String> {
+    let list = descriptors.as_array().ok_or("descriptors is not a list")?;
+    let parsed: Vec<(&str, bool, bool)> = list
+        .iter()
+        .filter_map(|d| {
+            let desc = d.get("desc")?.as_str()?;
+            let internal = d.get("internal").and_then(Value::as_bool).unwrap_or(false);
+            let active = d.get("active").and_then(Value::as_bool).unwrap_or(false);
+            Some((desc, internal, active))
+        })
+        .collect();
+    let any_active = parsed.iter().any(|(_, _, active)| *active);
+    let usable = |internal: bool| {
+        parsed
+            .iter()
+            .filter(move |(_, i, a)| *i == internal && (*a || !any_active))
+            .map(|(d, _, _)| *d)
+    };
+    let external = ["wpkh(", "tr(", "sh(wpkh(", "pkh("]
+        .iter()
+        .find_map(|script| usable(false).find(|d| script_of(d) == *script))
+        .or_else(|| usable(false).next())
+        .ok_or("No receive descriptor found")?;
+    let internal = usable(true).find(|d| script_of(d) == script_of(external));
+    pair(external, internal)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Script {
+    Legacy,
+    NestedSegwit,
+    Segwit,
+}
+
+/// Convert a SLIP-132 key (ypub, zpub, Vpub...) to plain xpub/tpub and report the script
+/// type its prefix stands for
+fn normalize_key(key: &str) -> Result<(String, Script), String> {
+    const XPUB: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+    const TPUB: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+    let mut data = base58::decode_check(key).map_err(|e| format!("{key}: {e}"))?;
+    if data.len() != 78 {
+        return Err(format!("{key}: not an extended public key"));
+    }
+    let (version, script) = match data[..4] {
+        [0x04, 0x88, 0xb2, 0x1e] => (XPUB, Script::Legacy),
+        [0x04, 0x9d, 0x7c, 0xb2] | [0x02, 0x95, 0xb4, 0x3f] => (XPUB, Script::NestedSegwit),
+        [0x04, 0xb2, 0x47, 0x46] | [0x02, 0xaa, 0x7e, 0xd3] => (XPUB, Script::Segwit),
+        [0x04, 0x35, 0x87, 0xcf] => (TPUB, Script::Legacy),
+        [0x04, 0x4a, 0x52, 0x62] | [0x02, 0x42, 0x89, 0xef] => (TPUB, Script::NestedSegwit),
+        [0x04, 0x5f, 0x1c, 0xf6] | [0x02, 0x57, 0x54, 0x83] => (TPUB, Script::Segwit),
+        _ => return Err(format!("{key}: unknown key version")),
+    };
+    data[..4].copy_from_slice(&version);
+    Ok((base58::encode_check(&data), script))
+}
+
+/// Plain xpub/tpub for a key in any SLIP-132 encoding
+pub fn to_xpub(key: &str) -> Result<String, String> {
+    normalize_key(key).map(|(key, _)| key)
+}
+
+/// `[fingerprint/path]xpub/0/*` for an Electrum keystore
+fn electrum_key(keystore: &Value) -> Result<(String, Script), String> {
+    let kind = keystore
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("bip32");
+    let xpub = keystore
+        .get("xpub")
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("Unsupported Electrum keystore type {kind}"))?;
+    let (xpub, script) = normalize_key(xpub)?;
+    let fingerprint = keystore.get("root_fingerprint").and_then(Value::as_str);
+    let path = keystore.get("derivation").and_then(Value::as_str);
+    let key = match (fingerprint, path) {
+        (Some(fp), Some(path)) => {
+            let path = path.trim_start_matches('m');
+            format!("[{fp}{path}]{xpub}/0/*")
+        }
+        _ => format!("{xpub}/0/*"),
+    };
+    Ok((key, script))
+}
+
+/// Descriptors for an Electrum standard or multisig wallet file
+fn electrum(json: &Value) -> Result<(String, String), String> {
+    let wallet_type = json
+        .get("wallet_type")
+        .and_then(Value::as_str)
+        .unwrap_or("standard");
+    let external = if wallet_type == "standard" {
+        let keystore = json.get("keystore").ok_or("Missing keystore")?;
+        let (key, script) = electrum_key(keystore)?;
+        match script {
+            Script::Legacy => format!("pkh({key})"),
+            Script::NestedSegwit => format!("sh(wpkh({key}))"),
+            Script::Segwit => format!("wpkh({key})"),
+        }
+    } else {
+        let (m, n) = wallet_type
+            .split_once("of")
+            .and_then(|(m, n)| Some((m.parse::<usize>().ok()?, n.parse::<usize>().ok()?)))
+            .ok_or_else(|| format!("Unsupported Electrum wallet type {wallet_type}"))?;
+        let keys = (1..=n)
+            .map(|i| {
+                let keystore = json
+                    .get(format!("x{i}/"))
+                    .ok_or_else(|| format!("Missing keystore x{i}/"))?;
+                electrum_key(keystore)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let script = keys[0].1;
+        let keys: Vec<String> = keys.into_iter().map(|(key, _)| key).collect();
+        // electrum sorts the cosigner keys
+        let multi = format!("sortedmulti({m},{})", keys.join(","));
+        match script {
+            Script::Legacy => format!("sh({multi})"),
+            Script::NestedSegwit => format!("sh(wsh({multi}))"),
+            Script::Segwit => format!("wsh({multi})"),
+        }
+    };
+    pair(&external, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::bitcoin::{
+        bip32::{Xpriv, Xpub},
+        secp256k1::Secp256k1,
+        Network,
+    };
+
+    use super::*;
+
+    fn tpub(seed: u8) -> String {
+        let xprv = Xpriv::new_master(Network::Testnet, &[seed; 32]).unwrap();
+        Xpub::from_priv(&Secp256k1::new(), &xprv).to_string()
+    }
+
+    /// Re-encode `key` with another SLIP-132 version
+    fn with_version(key: &str, version: [u8; 4]) -> String {
+        let mut data = base58::decode_check(key).unwrap();
+        data[..4].copy_from_slice(&version);
+        base58::encode_check(&data)
+    }
+
+    #[test]
+    fn electrum_standard() {
+        let key = tpub(1);
+        let vpub = with_version(&key, [0x04, 0x5f, 0x1c, 0xf6]);
+        let file = format!(
+            r#"{{"keystore": {{"type": "bip32", "xpub": "{vpub}",
+            "root_fingerprint": "f00dbabe", "derivation": "m/84'/1'/0'"}},
+            "wallet_type": "standard"}}"#
+        );
+        let wallet = parse(&file).unwrap();
+        assert_eq!(wallet.format, "Electrum");
+        assert_eq!(
+            wallet.external,
+            format!("wpkh([f00dbabe/84'/1'/0']{key}/0/*)")
+        );
+        assert_eq!(
+            wallet.internal,
+            format!("wpkh([f00dbabe/84'/1'/0']{key}/1/*)")
+        );
+        assert_eq!(wallet.network, Some(NetworkKind::Test));
+        assert!(wallet.check_network(Network::Signet).is_ok());
+        assert!(wallet.check_network(Network::Bitcoin).is_err());
+
+        let xpub = with_version(&key, [0x04, 0x88, 0xb2, 0x1e]);
+        let mixed = [format!("wpkh({key}/0/*)"), format!("wpkh({xpub}/1/*)")];
+        assert!(network_kind(&[&mixed[0], &mixed[1]]).is_err());
+        assert_eq!(network_kind(&[&mixed[1]]), Ok(Some(NetworkKind::Main)));
+    }
+
+    #[test]
+    fn electrum_multisig() {
+        let (a, b) = (tpub(1), tpub(2));
+        let va = with_version(&a, [0x02, 0x57, 0x54, 0x83]);
+        let vb = with_version(&b, [0x02, 0x57, 0x54, 0x83]);
+        let file = format!(
+            r#"{{"wallet_type": "2of2", "x1/": {{"xpub": "{va}"}}, "x2/": {{"xpub": "{vb}"}}}}"#
+        );
+        let wallet = parse(&file).unwrap();
+        assert_eq!(
+            wallet.external,
+            format!("wsh(sortedmulti(2,{a}/0/*,{b}/0/*))")
+        );
+        assert!(wallet.internal.contains(&format!("{b}/1/*")));
+    }
+
+    #[test]
+    fn descriptor_text_and_json() {
+        let key = tpub(3);
+        let text = format!(
+            "# Receive and change descriptor (BIP389):\nwpkh([f00dbabe/84h/1h/0h]{key}/<0;1>/*)#abcdefgh\n"
+        );
+        let wallet = parse(&text).unwrap();
+        assert_eq!(
+            wallet.external,
+            format!("wpkh([f00dbabe/84h/1h/0h]{key}/0/*)")
+        );
+        assert_eq!(
+            wallet.internal,
+            format!("wpkh([f00dbabe/84h/1h/0h]{key}/1/*)")
+        );
+
+        let json = format!(r#"{{"label": "team", "descriptor": "tr({key}/0/*)"}}"#);
+        let wallet = parse(&json).unwrap();
+        assert_eq!(wallet.internal, format!("tr({key}/1/*)"));
+        assert_eq!(wallet.label.as_deref(), Some("team"));
+    }
+
+    #[test]
+    fn core_listdescriptors() {
+        let key = tpub(4);
+        let file = format!(
+            r#"{{"wallet_name": "core", "descriptors": [
+            {{"desc": "pkh({key}/44h/1h/0h/0/*)#aaaa", "active": true, "internal": false}},
+            {{"desc": "wpkh({key}/84h/1h/0h/1/*)#bbbb", "active": true, "internal": true}},
+            {{"desc": "wpkh({key}/84h/1h/0h/0/*)#cccc", "active": true, "internal": false}},
+            {{"desc": "pkh({key}/44h/1h/0h/1/*)#dddd", "active": true, "internal": true}}]}}"#
+        );
+        let wallet = parse(&file).unwrap();
+        assert_eq!(wallet.format, "Bitcoin Core");
+        assert_eq!(wallet.external, format!("wpkh({key}/84h/1h/0h/0/*)#cccc"));
+        assert_eq!(wallet.internal, format!("wpkh({key}/84h/1h/0h/1/*)#bbbb"));
+        assert_eq!(wallet.label.as_deref(), Some("core"));
+    }
+
+    #[test]
+    fn encrypted_electrum_is_refused() {
+        assert!(parse("BIE1QmFzZTY0").is_err());
+    }
+}
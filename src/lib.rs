@@ -2,6 +2,36 @@
 
 mod app;
 mod bdk_utils;
+mod bip21;
+mod bip329;
+mod bundle;
+mod chain;
+mod entropy;
+mod export;
+mod import;
 mod messages;
+mod multisig;
+mod policy;
+mod registry;
 mod wallet;
 pub use app::WalletApp;
+
+#[cfg(test)]
+use bdk_wallet::{rusqlite::Connection, PersistedWallet};
+
+/// Fresh directory for a test, removed once the returned handle is dropped
+#[cfg(test)]
+fn test_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("seashell")
+        .tempdir()
+        .expect("temp dir")
+}
+
+/// Wallet `name` stored in `dir` the way the registry lays wallets out, watch-only when it
+/// has no keys file
+#[cfg(test)]
+fn load_test_wallet(dir: &str, name: &str) -> Result<PersistedWallet<Connection>, String> {
+    let (db_file, keys_file) = registry::files(dir, name);
+    bdk_utils::load_wallet(&db_file, keys_file.is_file().then_some(keys_file.as_path()))
+}
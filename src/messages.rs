@@ -1,52 +1,174 @@
+use std::path::PathBuf;
+
 use bdk_sqlite::rusqlite::Connection;
 use bdk_wallet::{
-    bitcoin::{Address, Psbt},
-    AddressInfo, Balance, LocalOutput, PersistedWallet,
+    bitcoin::{Address, Amount, FeeRate, Psbt, Txid},
+    AddressInfo, Balance, KeychainKind, LocalOutput, PersistedWallet,
 };
 
-use crate::app::{send::SendState, settings::Settings};
+use crate::{
+    app::{
+        send::SendState,
+        settings::{Settings, WalletSettings},
+    },
+    bip329::Label,
+    chain::{CertChange, ServerInfo},
+    export::WalletExport,
+    policy::{PathChoice, PolicyNode},
+};
 
 pub struct CreatedWallet {
     pub wallet: PersistedWallet<Connection>,
     pub name: String,
+    /// Database the wallet was loaded from, as recorded in the registry
+    pub db_file: PathBuf,
 }
 
 pub struct TxParts {
     pub sats_amount: u64,
     pub addr: Address,
     pub utxos: Option<Vec<LocalOutput>>,
+    pub fee_rate: FeeRate,
+    /// Spending path picked for wallets whose policy has more than one
+    pub policy_path: PathChoice,
 }
 
 impl From<SendState> for TxParts {
     fn from(value: SendState) -> Self {
-        let utxos = if value.selected_utxos.len() > 0 {
+        let utxos = if !value.selected_utxos.is_empty() {
             Some(value.selected_utxos)
         } else {
             None
         };
         TxParts {
+            // sat/vB to sat/kwu
+            fee_rate: FeeRate::from_sat_per_kwu((value.fee_rate * 250.).ceil() as u64),
             sats_amount: value.sats_amount,
             addr: value.pay_to_addr.unwrap(),
             utxos,
+            policy_path: value.policy_choice,
         }
     }
 }
 
+/// Summary of a wallet transaction for display in the UI
+#[derive(Debug, Clone)]
+pub struct TxDetails {
+    pub txid: Txid,
+    pub sent: Amount,
+    pub received: Amount,
+    pub fee: Option<Amount>,
+    /// Confirmation height, `None` while the transaction is pending
+    pub height: Option<u32>,
+}
+
+/// A request for payment to one of our receive addresses
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    /// Derivation index on the external keychain
+    pub index: u32,
+    pub address: Address,
+    pub amount: Option<Amount>,
+    pub label: String,
+    pub message: String,
+    pub created_at: u64,
+}
+
+/// A transaction created but not broadcast yet, kept in the wallet database
+#[derive(Debug, Clone)]
+pub struct Draft {
+    pub psbt: Psbt,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentStatus {
+    Unpaid,
+    PartiallyPaid(Amount),
+    Paid(Amount),
+}
+
+/// An external address taken out of the unused list by hand
+#[derive(Debug, Clone)]
+pub struct MarkedAddress {
+    pub address: AddressInfo,
+    /// Counterparty the address is being kept for, `None` if simply marked used
+    pub reserved_for: Option<String>,
+}
+
+/// Everything the address explorer shows about one derivation index
+#[derive(Debug, Clone)]
+pub struct AddressDetails {
+    pub keychain: KeychainKind,
+    pub index: u32,
+    pub path: String,
+    pub address: Address,
+    pub used: bool,
+    pub revealed: bool,
+    pub times_received: usize,
+    pub balance: Amount,
+}
+
 pub enum WalletRequest {
     Debug(String),
     Sync,
     AppConfig(Settings),
+    WalletConfig(WalletSettings),
+    /// Full scan with the given stop gap, ignoring existing checkpoints
+    Rescan(usize),
+    /// Fee estimate for confirmation within the given number of blocks
+    EstimateFee(usize),
     CreateTransaction(TxParts),
-    SendTransaction(Psbt),
+    /// Broadcast the PSBT, labeling the transaction once it is accepted
+    SendTransaction(Psbt, String),
+    /// Forget the draft with this unsigned txid
+    DiscardDraft(Txid),
     MarkUsed(AddressInfo),
+    UnmarkUsed(AddressInfo),
+    Reserve(AddressInfo, String),
+    SavePaymentRequest(PaymentRequest),
+    SetLabel(Label),
+    ImportLabels(PathBuf),
+    ExportLabels(PathBuf),
+    /// Write an encrypted backup to the path using the passphrase
+    Backup(PathBuf, String),
+    /// Public descriptors and keys for the export page
+    Export,
+    AddressPage(KeychainKind, u32),
+    SearchAddress(String),
     Close,
 }
 
 pub enum WalletResponse {
     WalletReady,
+    WalletConfig(WalletSettings),
     Debug(String),
     Sync(Balance),
     RecvAddresses(Vec<AddressInfo>),
+    MarkedAddresses(Vec<MarkedAddress>),
     UtxoList(Vec<LocalOutput>),
+    TxHistory(Vec<TxDetails>),
+    PaymentRequests(Vec<(PaymentRequest, PaymentStatus)>),
+    Labels(Vec<Label>),
+    /// Rows of the requested page and whether a next page exists
+    AddressPage(Vec<AddressDetails>, bool),
+    AddressSearch(Result<Option<AddressDetails>, String>),
     NewPsbt(Psbt),
+    Drafts(Vec<Draft>),
+    FeeEstimate(FeeRate),
+    Connection(ConnectionStatus),
+    ServerTest(String, Result<ServerInfo, String>),
+    CertificateChanged(CertChange),
+    Export(WalletExport),
+    /// Spending policy of each keychain of the loaded wallet
+    Policies(Vec<(KeychainKind, PolicyNode)>),
+}
+
+/// State of the chain source after the last request that used it
+#[derive(Debug, Clone, Default)]
+pub enum ConnectionStatus {
+    #[default]
+    Unknown,
+    Connected(String),
+    Offline(String),
 }
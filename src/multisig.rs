@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use bdk_wallet::{
+    bitcoin::{
+        bip32::{DerivationPath, Fingerprint, Xpriv, Xpub},
+        secp256k1::Secp256k1,
+        Network,
+    },
+    keys::bip39::Mnemonic,
+};
+
+use crate::import::to_xpub;
+
+/// BIP341 point with no known private key, used so taproot multisig can only spend by script
+pub const NUMS: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MultisigScript {
+    P2wsh,
+    P2tr,
+}
+
+impl MultisigScript {
+    /// BIP48 script type 2 for P2WSH, BIP87 for taproot
+    fn account_path(self, network: Network) -> DerivationPath {
+        let coin = if network == Network::Bitcoin { 0 } else { 1 };
+        let path = match self {
+            MultisigScript::P2wsh => format!("m/48'/{coin}'/0'/2'"),
+            MultisigScript::P2tr => format!("m/87'/{coin}'/0'"),
+        };
+        DerivationPath::from_str(&path).expect("valid path")
+    }
+}
+
+/// Key expression `[fingerprint/path]xpub` without the `m/` prefix on the path
+fn key_expr(fingerprint: Fingerprint, path: &DerivationPath, key: &str) -> String {
+    let path = path.to_string();
+    let path = path.trim_start_matches('m').trim_start_matches('/');
+    format!("[{fingerprint}/{path}]{key}")
+}
+
+/// Check a cosigner key given as `[fingerprint/path]xpub` and return it in canonical form.
+/// SLIP-132 keys are converted and a trailing derivation such as `/0/*` is dropped.
+pub fn parse_cosigner(key: &str) -> Result<(Fingerprint, String), String> {
+    let key = key.trim();
+    let (origin, xpub) = key
+        .strip_prefix('[')
+        .and_then(|k| k.split_once(']'))
+        .ok_or("Key needs its origin, as in [fingerprint/48h/0h/0h/2h]xpub...")?;
+    let (fingerprint, path) = origin.split_once('/').ok_or("Origin needs a path")?;
+    let fingerprint = Fingerprint::from_str(fingerprint).map_err(|e| e.to_string())?;
+    let path = DerivationPath::from_str(&format!("m/{}", path.replace('h', "'")))
+        .map_err(|e| e.to_string())?;
+    let xpub = xpub.split('/').next().unwrap_or(xpub);
+    let xpub = to_xpub(xpub)?;
+    Ok((fingerprint, key_expr(fingerprint, &path, &xpub)))
+}
+
+/// This wallet's own cosigner key, derived from a seed
+#[derive(Debug, Clone)]
+pub struct OwnKey {
+    pub fingerprint: Fingerprint,
+    /// Shared with the other cosigners
+    pub public: String,
+    private: String,
+}
+
+impl OwnKey {
+    pub fn new(mnemonic: &Mnemonic, network: Network, script: MultisigScript) -> Self {
+        let secp = Secp256k1::new();
+        let master =
+            Xpriv::new_master(network, &mnemonic.to_seed("")).expect("seed has a valid length");
+        let fingerprint = master.fingerprint(&secp);
+        let path = script.account_path(network);
+        let account = master
+            .derive_priv(&secp, &path)
+            .expect("hardened derivation can't fail");
+        let xpub = Xpub::from_priv(&secp, &account);
+        OwnKey {
+            fingerprint,
+            public: key_expr(fingerprint, &path, &xpub.to_string()),
+            private: key_expr(fingerprint, &path, &account.to_string()),
+        }
+    }
+
+    /// `desc` with our public key swapped for the private one, so the wallet can sign.
+    /// The checksum no longer matches and is dropped.
+    pub fn with_secret(&self, desc: &str) -> String {
+        let desc = desc.split('#').next().unwrap_or(desc);
+        desc.replace(&self.public, &self.private)
+    }
+}
+
+/// Receive and change descriptors for a `threshold` of our key plus `cosigners`.
+/// Taproot has no `sortedmulti_a` yet, so its keys are put in a fixed sorted order instead.
+pub fn descriptors(
+    script: MultisigScript,
+    threshold: usize,
+    own: &OwnKey,
+    cosigners: &[(Fingerprint, String)],
+) -> Result<(String, String), String> {
+    let total = cosigners.len() + 1;
+    if total < 2 {
+        return Err("Add at least one cosigner".into());
+    }
+    if !(1..=total).contains(&threshold) {
+        return Err(format!("Threshold must be between 1 and {total}"));
+    }
+    if script == MultisigScript::P2wsh && total > 20 {
+        return Err("P2WSH multisig is limited to 20 keys".into());
+    }
+    let mut fingerprints: Vec<_> = cosigners.iter().map(|(fp, _)| *fp).collect();
+    fingerprints.push(own.fingerprint);
+    fingerprints.sort();
+    fingerprints.dedup();
+    if fingerprints.len() != total {
+        return Err("Every cosigner needs a different key".into());
+    }
+
+    let mut keys: Vec<(&str, &str)> = cosigners
+        .iter()
+        .map(|(_, key)| (key.as_str(), key.as_str()))
+        .chain([(own.public.as_str(), own.private.as_str())])
+        .collect();
+    keys.sort();
+    let branch = |b: u32| {
+        keys.iter()
+            .map(|(_, key)| format!("{key}/{b}/*"))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let desc = |b| match script {
+        MultisigScript::P2wsh => format!("wsh(sortedmulti({threshold},{}))", branch(b)),
+        MultisigScript::P2tr => format!("tr({NUMS},multi_a({threshold},{}))", branch(b)),
+    };
+    Ok((desc(0), desc(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::KeychainKind;
+
+    use super::*;
+    use crate::{bdk_utils, export::WalletExport, import};
+
+    fn cosigner(seed: &str, script: MultisigScript) -> (Fingerprint, String) {
+        let mne = Mnemonic::parse(seed).unwrap();
+        let key = OwnKey::new(&mne, Network::Testnet, script);
+        parse_cosigner(&key.public).unwrap()
+    }
+
+    const SEEDS: [&str; 3] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+    ];
+
+    #[test]
+    fn cosigner_keys() {
+        let (fp, key) = cosigner(SEEDS[1], MultisigScript::P2wsh);
+        assert!(key.starts_with(&format!("[{fp}/48'/1'/0'/2']tpub")));
+        let with_branch = format!("{key}/0/*");
+        assert_eq!(parse_cosigner(&with_branch).unwrap().1, key);
+        assert!(parse_cosigner("tpubD6NzVbkrYhZ4X").is_err());
+    }
+
+    #[test]
+    fn own_key_secret() {
+        let own = OwnKey::new(
+            &Mnemonic::parse(SEEDS[0]).unwrap(),
+            Network::Testnet,
+            MultisigScript::P2wsh,
+        );
+        let (_, other) = cosigner(SEEDS[1], MultisigScript::P2wsh);
+        let desc = format!(
+            "wsh(sortedmulti(1,{}/0/*,{other}/0/*))#abcdefgh",
+            own.public
+        );
+        let signing = own.with_secret(&desc);
+        assert!(!signing.contains('#') && !signing.contains(&own.public));
+        assert!(signing.contains(&format!("[{}/48'/1'/0'/2']tprv", own.fingerprint)));
+        let (_, keymap) =
+            bdk_wallet::miniscript::Descriptor::parse_descriptor(&Secp256k1::new(), &signing)
+                .unwrap();
+        assert_eq!(keymap.len(), 1);
+
+        // without our key only the checksum goes
+        let foreign = format!("wsh(pk({other}/0/*))");
+        assert_eq!(own.with_secret(&format!("{foreign}#abcdefgh")), foreign);
+    }
+
+    #[test]
+    fn two_of_three() {
+        for script in [MultisigScript::P2wsh, MultisigScript::P2tr] {
+            let own = OwnKey::new(
+                &Mnemonic::parse(SEEDS[0]).unwrap(),
+                Network::Testnet,
+                script,
+            );
+            let cosigners = [cosigner(SEEDS[1], script), cosigner(SEEDS[2], script)];
+            assert!(descriptors(script, 2, &own, &cosigners[..0]).is_err());
+            assert!(descriptors(script, 4, &own, &cosigners).is_err());
+            let (ext, int) = descriptors(script, 2, &own, &cosigners).unwrap();
+
+            let dir = crate::test_dir();
+            let d = dir.path().to_str().unwrap();
+            let mut wallet =
+                bdk_utils::from_descriptors(d, "ms", &ext, &int, false, Network::Testnet).unwrap();
+            assert!(!wallet
+                .get_signers(KeychainKind::External)
+                .signers()
+                .is_empty());
+            let first = wallet.peek_address(KeychainKind::External, 0).to_string();
+            wallet.reveal_next_address(KeychainKind::External);
+
+            let export = WalletExport::new(&wallet, "ms");
+            assert_eq!(export.keys.len(), 3);
+            let bsms = export.bsms().unwrap();
+            let lines: Vec<&str> = bsms.lines().collect();
+            assert_eq!(lines[0], "BSMS 1.0");
+            assert!(lines[1].contains("/**") && !lines[1].contains("prv"));
+            assert_eq!(lines[2], "/0/*,/1/*");
+            assert_eq!(lines[3], first);
+
+            // the other signers can read it back
+            let imported = import::parse(&bsms).unwrap();
+            assert_eq!(imported.format, "BSMS");
+            assert!(imported.external.ends_with("/0/*))"));
+
+            // records whose paths or first address don't match are rejected
+            let tampered = bsms.replace("/0/*,/1/*", "/0/*");
+            assert!(import::parse(&tampered).is_err());
+            let other = wallet.peek_address(KeychainKind::External, 1).to_string();
+            assert!(import::parse(&bsms.replace(&first, &other)).is_err());
+            assert!(import::parse(&bsms.replace(&format!("{first}\n"), "")).is_err());
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use bdk_wallet::{
+    bitcoin::{absolute, relative},
+    descriptor::policy::{PkOrF, Policy, Satisfaction, SatisfiableItem},
+    miniscript::{policy::Concrete, Descriptor, DescriptorPublicKey, Segwitv0},
+};
+
+/// Starting point for the policy editor: the primary key alone, or the recovery key
+/// once the coins are about six months old
+pub const RECOVERY_TEMPLATE: &str = "or(99@pk(primary),and(pk(recovery),older(26280)))";
+
+/// Average time between blocks, used to explain relative timelocks
+const BLOCK_MINUTES: u32 = 10;
+
+/// Compile `policy` into receive and change descriptors.
+/// `keys` maps each alias used in `pk(...)` to a `[fingerprint/path]xpub` key.
+pub fn compile(policy: &str, keys: &[(String, String)]) -> Result<(String, String), String> {
+    let desc = |branch: u32| {
+        let mut policy = policy.replace(char::is_whitespace, "");
+        for (alias, key) in keys {
+            policy = policy.replace(&format!("pk({alias})"), &format!("pk({key}/{branch}/*)"));
+        }
+        let concrete = Concrete::<DescriptorPublicKey>::from_str(&policy)
+            .map_err(|e| format!("Invalid policy: {e}"))?;
+        let ms = concrete
+            .compile::<Segwitv0>()
+            .map_err(|e| format!("Unable to compile policy: {e}"))?;
+        Descriptor::new_wsh(ms)
+            .map(|d| d.to_string())
+            .map_err(|e| e.to_string())
+    };
+    Ok((desc(0)?, desc(1)?))
+}
+
+/// Plain words for a relative timelock
+pub fn describe_relative(value: relative::LockTime) -> String {
+    match value {
+        relative::LockTime::Blocks(blocks) => {
+            let blocks = u32::from(blocks.value());
+            let days = blocks * BLOCK_MINUTES / (60 * 24);
+            format!("coins must have {blocks} confirmations (about {days} days)")
+        }
+        relative::LockTime::Time(time) => {
+            let days = u32::from(time.value()) * 512 / (60 * 60 * 24);
+            format!("coins must be confirmed for about {days} days")
+        }
+    }
+}
+
+/// Plain words for an absolute timelock
+pub fn describe_absolute(value: absolute::LockTime) -> String {
+    match value {
+        absolute::LockTime::Blocks(height) => format!("only after block {height}"),
+        absolute::LockTime::Seconds(time) => format!("only after unix time {time}"),
+    }
+}
+
+fn describe_key(key: &PkOrF) -> String {
+    match key {
+        PkOrF::Fingerprint(fp) => fp.to_string(),
+        PkOrF::Pubkey(pk) => pk.to_string(),
+        PkOrF::XOnlyPubkey(pk) => pk.to_string(),
+    }
+}
+
+/// What the signers we hold can do towards satisfying a policy node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Contribution {
+    Complete,
+    /// `have` of the `need` keys or branches, the rest must come from elsewhere
+    Partial {
+        have: usize,
+        need: usize,
+    },
+    None,
+}
+
+impl From<&Satisfaction> for Contribution {
+    fn from(value: &Satisfaction) -> Self {
+        match value {
+            Satisfaction::Complete { .. } | Satisfaction::PartialComplete { .. } => {
+                Contribution::Complete
+            }
+            Satisfaction::Partial { items, .. } if items.is_empty() => Contribution::None,
+            Satisfaction::Partial { m, items, .. } => Contribution::Partial {
+                have: items.len(),
+                need: *m,
+            },
+            Satisfaction::None => Contribution::None,
+        }
+    }
+}
+
+/// A spending policy node, shown in the policy viewer and the send flow
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyNode {
+    pub description: String,
+    /// Timelock the node requires, explained
+    pub timelock: Option<String>,
+    /// Keys the node needs signatures from, and whether we hold each one
+    pub keys: Vec<(String, bool)>,
+    pub contribution: Contribution,
+    /// How many children must be satisfied, with the choice left to the user when it is
+    /// less than all of them
+    pub threshold: usize,
+    pub children: Vec<PolicyNode>,
+}
+
+impl PolicyNode {
+    pub fn new(policy: &Policy) -> Self {
+        let contribution = Contribution::from(&policy.contribution);
+        let leaf = |description: String, timelock: Option<String>| PolicyNode {
+            description,
+            timelock,
+            keys: Vec::new(),
+            contribution,
+            threshold: 0,
+            children: Vec::new(),
+        };
+        match &policy.item {
+            SatisfiableItem::EcdsaSignature(key) | SatisfiableItem::SchnorrSignature(key) => {
+                PolicyNode {
+                    keys: vec![(describe_key(key), contribution == Contribution::Complete)],
+                    ..leaf("Signature".into(), None)
+                }
+            }
+            SatisfiableItem::Multisig { keys, threshold } => {
+                let ours: &[usize] = match &policy.contribution {
+                    Satisfaction::Partial { items, .. }
+                    | Satisfaction::PartialComplete { items, .. } => items,
+                    _ => &[],
+                };
+                PolicyNode {
+                    keys: keys
+                        .iter()
+                        .enumerate()
+                        .map(|(i, key)| (describe_key(key), ours.contains(&i)))
+                        .collect(),
+                    ..leaf(format!("{threshold} of {} signatures", keys.len()), None)
+                }
+            }
+            SatisfiableItem::RelativeTimelock { value } => {
+                leaf("Relative timelock".into(), Some(describe_relative(*value)))
+            }
+            SatisfiableItem::AbsoluteTimelock { value } => {
+                leaf("Absolute timelock".into(), Some(describe_absolute(*value)))
+            }
+            SatisfiableItem::Sha256Preimage { hash } => {
+                leaf(format!("SHA256 preimage of {hash}"), None)
+            }
+            SatisfiableItem::Hash256Preimage { hash } => {
+                leaf(format!("HASH256 preimage of {hash}"), None)
+            }
+            SatisfiableItem::Ripemd160Preimage { hash } => {
+                leaf(format!("RIPEMD160 preimage of {hash}"), None)
+            }
+            SatisfiableItem::Hash160Preimage { hash } => {
+                leaf(format!("HASH160 preimage of {hash}"), None)
+            }
+            SatisfiableItem::Thresh { items, threshold } => PolicyNode {
+                description: match (*threshold, items.len()) {
+                    (1, _) => "Any of".into(),
+                    (t, n) if t == n => "All of".into(),
+                    (t, n) => format!("{t} of {n}"),
+                },
+                threshold: *threshold,
+                children: items.iter().map(PolicyNode::new).collect(),
+                ..leaf(String::new(), None)
+            },
+        }
+    }
+
+    /// Whether the user has to pick which children to satisfy
+    pub fn is_choice(&self) -> bool {
+        self.threshold > 0 && self.threshold < self.children.len()
+    }
+
+    /// Whether anywhere in the tree needs a choice
+    pub fn has_choice(&self) -> bool {
+        self.is_choice() || self.children.iter().any(PolicyNode::has_choice)
+    }
+
+    /// The first `threshold` children at every choice node
+    pub fn default_choice(&self) -> PathChoice {
+        fn walk(node: &PolicyNode, position: &mut Vec<usize>, choice: &mut PathChoice) {
+            if node.is_choice() {
+                choice.insert(position.clone(), (0..node.threshold).collect());
+            }
+            for (i, child) in node.children.iter().enumerate() {
+                position.push(i);
+                walk(child, position, choice);
+                position.pop();
+            }
+        }
+        let mut choice = PathChoice::new();
+        walk(self, &mut Vec::new(), &mut choice);
+        choice
+    }
+
+    pub fn child(&self, position: &[usize]) -> Option<&PolicyNode> {
+        match position.split_first() {
+            None => Some(self),
+            Some((i, rest)) => self.children.get(*i)?.child(rest),
+        }
+    }
+}
+
+/// Children picked at each choice node, keyed by the node's position in the tree
+pub type PathChoice = BTreeMap<Vec<usize>, Vec<usize>>;
+
+/// bdk policy path for `policy` from choices made on a tree of the same shape,
+/// so one choice can be applied to the receive and change keychains alike
+pub fn policy_path(policy: &Policy, choice: &PathChoice) -> BTreeMap<String, Vec<usize>> {
+    fn walk(
+        policy: &Policy,
+        position: &mut Vec<usize>,
+        choice: &PathChoice,
+        path: &mut BTreeMap<String, Vec<usize>>,
+    ) {
+        if let Some(picked) = choice.get(position) {
+            path.insert(policy.id.clone(), picked.clone());
+        }
+        if let SatisfiableItem::Thresh { items, .. } = &policy.item {
+            for (i, item) in items.iter().enumerate() {
+                position.push(i);
+                walk(item, position, choice, path);
+                position.pop();
+            }
+        }
+    }
+    let mut path = BTreeMap::new();
+    walk(policy, &mut Vec::new(), choice, &mut path);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::{
+        bitcoin::{
+            bip32::{Xpriv, Xpub},
+            secp256k1::Secp256k1,
+            Network,
+        },
+        rusqlite::Connection,
+        KeychainKind, PersistedWallet,
+    };
+
+    use super::*;
+    use crate::bdk_utils;
+
+    fn key(seed: u8) -> String {
+        let xprv = Xpriv::new_master(Network::Testnet, &[seed; 32]).unwrap();
+        let xpub = Xpub::from_priv(&Secp256k1::new(), &xprv);
+        format!("[{}/48'/1'/0'/2']{xpub}", xpub.fingerprint())
+    }
+
+    fn wallet(name: &str, ext: &str, int: &str) -> PersistedWallet<Connection> {
+        let dir = crate::test_dir();
+        bdk_utils::from_descriptors(
+            dir.path().to_str().unwrap(),
+            name,
+            ext,
+            int,
+            false,
+            Network::Testnet,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn recovery_wallet() {
+        let keys = vec![
+            ("primary".to_string(), key(1)),
+            ("recovery".to_string(), key(2)),
+        ];
+        assert!(compile("or(pk(primary),pk(nobody))", &keys).is_err());
+        let (ext, int) = compile(RECOVERY_TEMPLATE, &keys).unwrap();
+        assert!(ext.starts_with("wsh(") && ext.contains("/0/*") && ext.contains("older(26280)"));
+        assert!(int.contains("/1/*"));
+
+        let wallet = wallet("p", &ext, &int);
+        let external = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let internal = wallet.policies(KeychainKind::Internal).unwrap().unwrap();
+        let tree = PolicyNode::new(&external);
+        assert!(tree.is_choice() && tree.has_choice());
+        assert_eq!(tree.children.len(), 2);
+        // only the key fingerprints, we hold neither key
+        assert_eq!(tree.contribution, Contribution::None);
+        let primary = tree.child(&[0]).unwrap();
+        assert_eq!(primary.keys, vec![(key(1)[1..9].to_string(), false)]);
+        assert_eq!(tree.default_choice(), PathChoice::from([(vec![], vec![0])]));
+        let recovery = tree.child(&[1]).unwrap();
+        let timelock = recovery.children.iter().find_map(|c| c.timelock.clone());
+        assert_eq!(
+            timelock.as_deref(),
+            Some("coins must have 26280 confirmations (about 182 days)")
+        );
+
+        let choice = PathChoice::from([(vec![], vec![1])]);
+        let ext_path = policy_path(&external, &choice);
+        let int_path = policy_path(&internal, &choice);
+        assert_eq!(ext_path.get(&external.id), Some(&vec![1]));
+        assert_eq!(int_path.get(&internal.id), Some(&vec![1]));
+    }
+
+    #[test]
+    fn signable_branches() {
+        let xprv = Xpriv::new_master(Network::Testnet, &[1; 32]).unwrap();
+        let public = key(1);
+        let private = public.replace(
+            &Xpub::from_priv(&Secp256k1::new(), &xprv).to_string(),
+            &xprv.to_string(),
+        );
+        let keys = vec![
+            ("primary".to_string(), public.clone()),
+            ("recovery".to_string(), key(2)),
+        ];
+        let (ext, int) = compile(RECOVERY_TEMPLATE, &keys).unwrap();
+        let with_secret = |desc: &str| desc.split('#').next().unwrap().replace(&public, &private);
+        let wallet = wallet("signing", &with_secret(&ext), &with_secret(&int));
+        let policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let tree = PolicyNode::new(&policy);
+        assert_eq!(tree.contribution, Contribution::Complete);
+        let primary = tree.child(&[0]).unwrap();
+        assert_eq!(primary.contribution, Contribution::Complete);
+        assert!(primary.keys[0].1);
+        let recovery = tree.child(&[1]).unwrap();
+        assert_ne!(recovery.contribution, Contribution::Complete);
+    }
+}
@@ -0,0 +1,492 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bdk_wallet::{
+    bitcoin::{
+        bip32::{ChildNumber, Fingerprint},
+        Amount, Network,
+    },
+    miniscript::{descriptor::DescriptorType, Descriptor, DescriptorPublicKey, ForEachKey},
+    rusqlite::{Connection, OpenFlags},
+    KeychainKind, Wallet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::chain::Backend;
+
+/// Registry file kept next to the wallet databases
+const REGISTRY: &str = "wallets.json";
+/// Folder inside the wallet directory that deleted wallets are moved to
+const BACKUPS: &str = "backups";
+
+/// What the splash screen needs to know about a wallet without loading it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletEntry {
+    pub name: String,
+    pub network: Network,
+    pub script_type: String,
+    /// Unix time in seconds
+    pub created_at: u64,
+    pub watch_only: bool,
+    /// Chain source the wallet was last used with, selected again when it is loaded
+    pub backend: Backend,
+    pub db_file: PathBuf,
+    pub keys_file: Option<PathBuf>,
+    /// Master fingerprint of the seed the wallet's own key comes from, when known, so its
+    /// backup can be checked
+    #[serde(default)]
+    pub seed: Option<Fingerprint>,
+    /// Account index for single key wallets derived from a seed, so accounts of the same
+    /// seed can be grouped
+    #[serde(default)]
+    pub account: Option<u32>,
+    /// Whether the written seed was checked, `None` when the wallet wasn't made from a seed here
+    #[serde(default)]
+    pub backup_verified: Option<bool>,
+}
+
+impl WalletEntry {
+    /// Describe a freshly created wallet stored as `name` in `dir`
+    pub fn new(dir: &str, name: &str, wallet: &Wallet, watch_only: bool, backend: Backend) -> Self {
+        let (db_file, keys_file) = files(dir, name);
+        let desc = wallet.public_descriptor(KeychainKind::External);
+        let account = account_of(desc);
+        WalletEntry {
+            name: name.into(),
+            network: wallet.network(),
+            script_type: script_type(desc.desc_type()).into(),
+            created_at: now(),
+            watch_only,
+            backend,
+            db_file,
+            keys_file: (!watch_only).then_some(keys_file),
+            seed: account.map(|(fp, _)| fp),
+            account: account.map(|(_, index)| index),
+            backup_verified: None,
+        }
+    }
+
+    /// Record the seed the wallet's own key comes from and whether its written backup was
+    /// checked. A watch-only wallet holds no seed, so there is no backup to check for it.
+    pub fn with_backup(mut self, seed: Fingerprint, verified: bool) -> Self {
+        if !self.watch_only {
+            self.seed = Some(seed);
+            self.backup_verified = Some(verified);
+        }
+        self
+    }
+
+    /// Whether the wallet database is still where the registry says
+    pub fn exists(&self) -> bool {
+        self.db_file.is_file()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Database and key file paths for wallet `name`
+pub fn files(dir: &str, name: &str) -> (PathBuf, PathBuf) {
+    let dir = Path::new(dir);
+    (dir.join(name), dir.join(format!("{name}_keys")))
+}
+
+pub fn script_type(desc: DescriptorType) -> &'static str {
+    match desc {
+        DescriptorType::Bare => "Bare",
+        DescriptorType::Pkh => "P2PKH",
+        DescriptorType::Wpkh => "P2WPKH",
+        DescriptorType::ShWpkh => "P2SH-P2WPKH",
+        DescriptorType::Tr => "P2TR",
+        DescriptorType::Wsh | DescriptorType::WshSortedMulti => "P2WSH",
+        DescriptorType::ShWsh | DescriptorType::ShWshSortedMulti => "P2SH-P2WSH",
+        DescriptorType::Sh | DescriptorType::ShSortedMulti => "P2SH",
+    }
+}
+
+/// Master fingerprint and account index of a single key descriptor on a BIP44 style path
+pub fn account_of(desc: &Descriptor<DescriptorPublicKey>) -> Option<(Fingerprint, u32)> {
+    let mut keys = Vec::new();
+    desc.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+    let [DescriptorPublicKey::XPub(key)] = &keys[..] else {
+        return None;
+    };
+    let (fingerprint, path) = key.origin.as_ref()?;
+    match path.as_ref() {
+        [ChildNumber::Hardened {
+            index: 44 | 49 | 84 | 86,
+        }, ChildNumber::Hardened { .. }, ChildNumber::Hardened { index }] => {
+            Some((*fingerprint, *index))
+        }
+        _ => None,
+    }
+}
+
+/// Total balance of a wallet as of its last sync, read from its database
+pub fn balance(entry: &WalletEntry) -> Result<Amount, String> {
+    let mut db = Connection::open(&entry.db_file).map_err(|e| e.to_string())?;
+    let wallet = Wallet::load()
+        .load_wallet(&mut db)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("{} is not a wallet database", entry.db_file.display()))?;
+    Ok(wallet.balance().total())
+}
+
+/// All registered wallets. A folder that does not exist yet has no wallets, and a folder
+/// from before the registry existed is scanned once for wallet databases.
+pub fn load(dir: &str) -> Result<Vec<WalletEntry>, String> {
+    let path = Path::new(dir).join(REGISTRY);
+    match std::fs::read_to_string(&path) {
+        Ok(data) => {
+            let mut wallets: Vec<WalletEntry> =
+                serde_json::from_str(&data).map_err(|e| format!("{}: {e}", path.display()))?;
+            // entries written before accounts were tracked
+            for entry in wallets.iter_mut().filter(|w| w.seed.is_none()) {
+                let account = read_wallet_table(&entry.db_file).and_then(|(d, _)| account_of(&d));
+                entry.seed = account.map(|(fp, _)| fp);
+                entry.account = account.map(|(_, index)| index);
+            }
+            Ok(wallets)
+        }
+        Err(_) if !Path::new(dir).is_dir() => Ok(Vec::new()),
+        Err(_) => {
+            let found = migrate(dir);
+            save(dir, &found)?;
+            Ok(found)
+        }
+    }
+}
+
+pub fn save(dir: &str, wallets: &[WalletEntry]) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let data = serde_json::to_string_pretty(wallets).expect("entries always serialize");
+    std::fs::write(Path::new(dir).join(REGISTRY), data).map_err(|e| e.to_string())
+}
+
+/// Add `entry`, replacing any wallet registered under the same name
+pub fn register(dir: &str, entry: WalletEntry) -> Result<(), String> {
+    let mut wallets = load(dir)?;
+    wallets.retain(|w| w.name != entry.name);
+    wallets.push(entry);
+    save(dir, &wallets)
+}
+
+/// Reasons `name` can't be used for a new wallet file
+pub fn check_name(dir: &str, wallets: &[WalletEntry], name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Name can't be empty".into());
+    }
+    if name.contains(['/', '\\'])
+        || name.ends_with("_keys")
+        || [REGISTRY, BACKUPS, ".", ".."].contains(&name)
+    {
+        return Err(format!("\"{name}\" can't be used as a wallet name"));
+    }
+    if wallets.iter().any(|w| w.name == name) || Path::new(dir).join(name).exists() {
+        return Err(format!("A wallet named \"{name}\" already exists"));
+    }
+    Ok(())
+}
+
+fn position(wallets: &[WalletEntry], name: &str) -> Result<usize, String> {
+    wallets
+        .iter()
+        .position(|w| w.name == name)
+        .ok_or_else(|| format!("No wallet named \"{name}\""))
+}
+
+/// Record that the backup of seed `seed` was checked, for every wallet made from it
+pub fn set_backup_verified(dir: &str, seed: Fingerprint) -> Result<(), String> {
+    let mut wallets = load(dir)?;
+    for entry in wallets
+        .iter_mut()
+        .filter(|w| w.seed == Some(seed) && w.backup_verified.is_some())
+    {
+        entry.backup_verified = Some(true);
+    }
+    save(dir, &wallets)
+}
+
+/// Remember `backend` as the chain source wallet `name` is loaded with
+pub fn set_backend(dir: &str, name: &str, backend: Backend) -> Result<(), String> {
+    let mut wallets = load(dir)?;
+    let i = position(&wallets, name)?;
+    wallets[i].backend = backend;
+    save(dir, &wallets)
+}
+
+/// Rename a wallet's database and key files
+pub fn rename(dir: &str, name: &str, new_name: &str) -> Result<(), String> {
+    let mut wallets = load(dir)?;
+    check_name(dir, &wallets, new_name)?;
+    let i = position(&wallets, name)?;
+    let (db_file, keys_file) = files(dir, new_name);
+    let old = wallets[i].clone();
+    std::fs::rename(&old.db_file, &db_file).map_err(|e| e.to_string())?;
+    if let Some(old_keys) = &old.keys_file {
+        if let Err(e) = std::fs::rename(old_keys, &keys_file) {
+            // put the database back so the entry still points at both files
+            let _ = std::fs::rename(&db_file, &old.db_file);
+            return Err(e.to_string());
+        }
+    }
+    let entry = &mut wallets[i];
+    entry.name = new_name.into();
+    entry.db_file = db_file.clone();
+    entry.keys_file = old.keys_file.as_ref().map(|_| keys_file.clone());
+    save(dir, &wallets).inspect_err(|_| {
+        let _ = std::fs::rename(&db_file, &old.db_file);
+        if let Some(old_keys) = &old.keys_file {
+            let _ = std::fs::rename(&keys_file, old_keys);
+        }
+    })
+}
+
+/// Copy a wallet's database under `new_name` without its keys
+pub fn duplicate_watch_only(dir: &str, name: &str, new_name: &str) -> Result<(), String> {
+    let mut wallets = load(dir)?;
+    check_name(dir, &wallets, new_name)?;
+    let mut entry = wallets[position(&wallets, name)?].clone();
+    let (db_file, _) = files(dir, new_name);
+    std::fs::copy(&entry.db_file, &db_file).map_err(|e| e.to_string())?;
+    entry.name = new_name.into();
+    entry.db_file = db_file;
+    entry.keys_file = None;
+    entry.watch_only = true;
+    entry.backup_verified = None;
+    entry.created_at = now();
+    wallets.push(entry);
+    save(dir, &wallets)
+}
+
+/// Move a wallet's files into the backups folder and drop it from the registry.
+/// Returns the folder the files were moved to.
+pub fn delete(dir: &str, name: &str) -> Result<PathBuf, String> {
+    let mut wallets = load(dir)?;
+    let entry = wallets.remove(position(&wallets, name)?);
+    let backup = Path::new(dir)
+        .join(BACKUPS)
+        .join(format!("{name}-{}", now()));
+    std::fs::create_dir_all(&backup).map_err(|e| e.to_string())?;
+    for file in std::iter::once(&entry.db_file).chain(&entry.keys_file) {
+        let Some(file_name) = file.file_name() else {
+            continue;
+        };
+        if file.exists() {
+            // copy before removing so a failure never loses the only copy
+            std::fs::copy(file, backup.join(file_name)).map_err(|e| e.to_string())?;
+            std::fs::remove_file(file).map_err(|e| e.to_string())?;
+        }
+    }
+    save(dir, &wallets)?;
+    Ok(backup)
+}
+
+/// Build entries for the wallet databases in `dir`, skipping anything that isn't one
+fn migrate(dir: &str) -> Vec<WalletEntry> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<WalletEntry> = entries
+        .filter_map(|f| f.ok())
+        .filter_map(|f| {
+            let name = f.file_name().into_string().ok()?;
+            let (desc, network) = read_wallet_table(&f.path())?;
+            let (db_file, keys_file) = files(dir, &name);
+            let created_at = f
+                .metadata()
+                .and_then(|m| m.created().or_else(|_| m.modified()))
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let watch_only = !keys_file.is_file();
+            let account = account_of(&desc);
+            Some(WalletEntry {
+                name,
+                network,
+                script_type: script_type(desc.desc_type()).into(),
+                created_at,
+                watch_only,
+                backend: Backend::default(),
+                db_file,
+                keys_file: (!watch_only).then_some(keys_file),
+                seed: account.map(|(fp, _)| fp),
+                account: account.map(|(_, index)| index),
+                backup_verified: None,
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| a.name.cmp(&b.name));
+    found
+}
+
+/// External descriptor and network of a bdk wallet database, `None` for any other file
+pub fn read_wallet_table(path: &Path) -> Option<(Descriptor<DescriptorPublicKey>, Network)> {
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+    let (desc, network): (String, String) = db
+        .query_row(
+            "SELECT descriptor, network FROM bdk_wallet WHERE id = 0",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()?;
+    Some((
+        Descriptor::from_str(&desc).ok()?,
+        Network::from_str(&network).ok()?,
+    ))
+}
+
+/// `YYYY-MM-DD` for a unix timestamp, in UTC
+pub fn format_date(secs: u64) -> String {
+    // days to civil date, from Howard Hinnant's date algorithms
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_700_000_000), "2023-11-14");
+    }
+
+    #[test]
+    fn missing_folder_is_empty() {
+        let dir = crate::test_dir();
+        let missing = dir.path().join("wallets");
+        assert_eq!(load(missing.to_str().unwrap()), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn migration_skips_stray_files() {
+        let tmp = crate::test_dir();
+        let dir = tmp.path();
+        std::fs::write(dir.join("notes.txt"), "not a wallet").unwrap();
+        std::fs::copy("tests/tw", dir.join("tw")).unwrap();
+        std::fs::copy("tests/tw_keys", dir.join("tw_keys")).unwrap();
+
+        let wallets = load(dir.to_str().unwrap()).unwrap();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].name, "tw");
+        assert_eq!(wallets[0].network, Network::Testnet);
+        assert_eq!(wallets[0].script_type, "P2WPKH");
+        assert!(!wallets[0].watch_only);
+        // the registry is written on first load
+        assert!(dir.join(REGISTRY).is_file());
+    }
+
+    #[test]
+    fn manage_wallets() {
+        let tmp = crate::test_dir();
+        let dir = tmp.path();
+        std::fs::copy("tests/tw", dir.join("tw")).unwrap();
+        std::fs::copy("tests/tw_keys", dir.join("tw_keys")).unwrap();
+        let d = dir.to_str().unwrap();
+
+        assert!(rename(d, "tw", "").is_err());
+        assert!(rename(d, "tw", "tw").is_err());
+        // a keys file that can't be moved leaves both files where they were
+        std::fs::rename(dir.join("tw_keys"), dir.join("gone")).unwrap();
+        assert!(rename(d, "tw", "main").is_err());
+        assert!(dir.join("tw").is_file() && !dir.join("main").exists());
+        std::fs::rename(dir.join("gone"), dir.join("tw_keys")).unwrap();
+        rename(d, "tw", "main").unwrap();
+        assert!(dir.join("main").is_file() && dir.join("main_keys").is_file());
+        assert!(!dir.join("tw").exists() && !dir.join("tw_keys").exists());
+
+        duplicate_watch_only(d, "main", "watch").unwrap();
+        assert!(dir.join("watch").is_file() && !dir.join("watch_keys").exists());
+        let wallets = load(d).unwrap();
+        assert_eq!(wallets.len(), 2);
+        assert!(wallets[1].watch_only && wallets[1].keys_file.is_none());
+
+        set_backend(d, "watch", Backend::Cbf).unwrap();
+        let watch = load(d)
+            .unwrap()
+            .into_iter()
+            .find(|w| w.name == "watch")
+            .unwrap();
+        assert_eq!(watch.backend, Backend::Cbf);
+        assert!(crate::bdk_utils::load_wallet(&watch.db_file, watch.keys_file.as_deref()).is_ok());
+
+        let backup = delete(d, "main").unwrap();
+        assert!(backup.join("main").is_file() && backup.join("main_keys").is_file());
+        assert!(!dir.join("main").exists());
+        assert_eq!(load(d).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn backup_verification() {
+        let tmp = crate::test_dir();
+        let dir = tmp.path();
+        std::fs::copy("tests/tw", dir.join("tw")).unwrap();
+        std::fs::copy("tests/tw_keys", dir.join("tw_keys")).unwrap();
+        let d = dir.to_str().unwrap();
+
+        let mut wallets = load(d).unwrap();
+        let seed = wallets[0].seed.unwrap();
+        assert_eq!(wallets[0].account, Some(0));
+        wallets[0].backup_verified = Some(false);
+        save(d, &wallets).unwrap();
+        duplicate_watch_only(d, "tw", "watch").unwrap();
+
+        set_backup_verified(d, seed).unwrap();
+        let wallets = load(d).unwrap();
+        assert_eq!(wallets[0].backup_verified, Some(true));
+        // the watch-only copy holds no seed to check
+        assert_eq!(wallets[1].backup_verified, None);
+    }
+
+    #[test]
+    fn skipped_backup_check() {
+        use crate::bdk_utils::{from_words, seed_fingerprint, AccountScript};
+        let tmp = crate::test_dir();
+        let d = tmp.path().to_str().unwrap();
+        let words = "section attitude true fabric foam ribbon chaos cradle ordinary venture fat ensure winter skate error glove pulse dolphin they cable verify wolf rain ribbon";
+        let mne = bdk_wallet::keys::bip39::Mnemonic::parse(words).unwrap();
+        let seed = seed_fingerprint(&mne);
+        let wallet = from_words(
+            d,
+            "new",
+            mne,
+            AccountScript::Bip84,
+            0,
+            true,
+            Network::Testnet,
+        )
+        .unwrap();
+
+        // a signing wallet whose check was skipped is flagged, a watch-only one has no seed
+        let entry = WalletEntry::new(d, "new", &wallet, false, Backend::default());
+        let watch = WalletEntry::new(d, "watch", &wallet, true, Backend::default());
+        assert_eq!(watch.with_backup(seed, false).backup_verified, None);
+        register(d, entry.with_backup(seed, false)).unwrap();
+        assert_eq!(load(d).unwrap()[0].backup_verified, Some(false));
+
+        set_backup_verified(d, seed).unwrap();
+        assert_eq!(load(d).unwrap()[0].backup_verified, Some(true));
+    }
+}
@@ -1,71 +1,257 @@
-use std::{thread, time::Duration};
+use std::{
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use bdk_sqlite::rusqlite::Connection;
 use flume::{Receiver, Sender};
 
 use bdk_wallet::{
-    bitcoin::{Amount, FeeRate, Psbt},
-    AddressInfo, Balance, LocalOutput, PersistedWallet, SignOptions,
+    bitcoin::{Amount, OutPoint, Psbt, Transaction},
+    chain::ChainPosition,
+    AddressInfo, KeychainKind, LocalOutput, PersistedWallet, SignOptions,
 };
 
 use crate::{
-    app::settings::Settings,
-    bdk_utils,
-    messages::{self, TxParts, WalletRequest, WalletResponse},
+    app::settings::{Settings, WalletSettings},
+    bdk_utils, bip329, bundle,
+    chain::{self, ChainError, ChainSource},
+    export::WalletExport,
+    messages::{
+        self, ConnectionStatus, PaymentRequest, TxDetails, TxParts, WalletRequest, WalletResponse,
+    },
+    policy::{self, PolicyNode},
+    registry,
 };
 
+mod addresses;
+mod drafts;
+mod labels;
 mod receive;
+mod requests;
 
 pub struct WalletBackground {
     wallet: PersistedWallet<Connection>,
     name: String,
     wallet_req: Receiver<messages::WalletRequest>,
     wallet_updates: Sender<messages::WalletResponse>,
-    electrum_url: String,
-    db: String,
+    settings: Settings,
+    /// Connection to the wallet database, open for as long as the wallet is loaded
+    db: Connection,
+    /// Chain source reused between requests, dropped after an error or a settings change
+    chain: Option<Box<dyn ChainSource>>,
+    wallet_settings: WalletSettings,
 }
 
 impl WalletBackground {
     pub fn new(
         wallet: PersistedWallet<Connection>,
         name: String,
+        db_file: PathBuf,
         req: Receiver<messages::WalletRequest>,
         resp: Sender<messages::WalletResponse>,
         settings: Settings,
     ) -> Self {
+        let db = bdk_utils::open_db(&db_file);
+        let wallet_settings = bdk_utils::load_wallet_settings(&db);
         WalletBackground {
             wallet,
             name,
             wallet_req: req,
             wallet_updates: resp,
-            electrum_url: settings.electrum_url,
-            db: settings.wallet_db,
+            db,
+            chain: None,
+            settings,
+            wallet_settings,
         }
     }
 
     fn persist(&mut self) {
-        bdk_utils::persist(&self.db, &self.name, &mut self.wallet);
+        bdk_utils::persist(&mut self.db, &mut self.wallet);
     }
 
-    fn mark_used(&mut self, addr: AddressInfo) {
-        self.wallet
-            .mark_used(bdk_wallet::KeychainKind::External, addr.index);
-        self.persist();
+    fn db(&self) -> &Connection {
+        &self.db
     }
 
-    fn get_utxos(&self) -> Vec<LocalOutput> {
-        self.wallet.list_unspent().collect()
+    fn mark_used(&mut self, addr: AddressInfo, reserved_for: Option<String>) {
+        if let Err(e) = receive::mark_used(self, addr.index, reserved_for) {
+            self.handle_debug(format!("marking address failed: {e}"));
+        }
+        self.send_addresses();
     }
 
-    pub fn monitor_wallet(&mut self) {
-        self.get_balance();
+    fn unmark_used(&mut self, addr: AddressInfo) {
+        if let Err(e) = receive::unmark_used(self, addr.index) {
+            self.handle_debug(format!("unmarking address failed: {e}"));
+        }
+        self.send_addresses();
+    }
+
+    /// Push the next unused and the hand marked addresses to the UI
+    fn send_addresses(&mut self) {
         let addr = receive::get_unused_addrs(self);
         self.wallet_updates
             .send(WalletResponse::RecvAddresses(addr))
             .unwrap();
+        match receive::list_marked(self) {
+            Ok(marked) => self
+                .wallet_updates
+                .send(WalletResponse::MarkedAddresses(marked))
+                .unwrap(),
+            Err(e) => self.handle_debug(format!("loading marked addresses failed: {e}")),
+        }
+    }
+
+    fn get_utxos(&self) -> Vec<LocalOutput> {
+        self.wallet.list_unspent().collect()
+    }
+
+    fn get_history(&self) -> Vec<TxDetails> {
+        let mut history: Vec<TxDetails> = self
+            .wallet
+            .transactions()
+            .map(|ctx| {
+                let tx = &ctx.tx_node.tx;
+                let (sent, received) = self.wallet.sent_and_received(tx);
+                let height = match ctx.chain_position {
+                    ChainPosition::Confirmed(anchor) => Some(anchor.block_id.height),
+                    ChainPosition::Unconfirmed(_) => None,
+                };
+                TxDetails {
+                    txid: ctx.tx_node.txid,
+                    sent,
+                    received,
+                    fee: self.wallet.calculate_fee(tx).ok(),
+                    height,
+                }
+            })
+            .collect();
+        // pending first, then newest confirmed
+        history.sort_by_key(|tx| std::cmp::Reverse(tx.height.unwrap_or(u32::MAX)));
+        history
+    }
+
+    fn send_payment_requests(&self) {
+        match requests::list_requests(self) {
+            Ok(reqs) => self
+                .wallet_updates
+                .send(WalletResponse::PaymentRequests(reqs))
+                .unwrap(),
+            Err(e) => self.handle_debug(format!("loading payment requests failed: {e}")),
+        }
+    }
+
+    fn send_drafts(&self) {
+        match drafts::list_drafts(self) {
+            Ok(drafts) => self
+                .wallet_updates
+                .send(WalletResponse::Drafts(drafts))
+                .unwrap(),
+            Err(e) => self.handle_debug(format!("loading drafts failed: {e}")),
+        }
+    }
+
+    fn save_payment_request(&mut self, req: PaymentRequest) {
+        if let Err(e) = requests::save_request(self, &req) {
+            self.handle_debug(format!("saving payment request failed: {e}"));
+            return;
+        }
+        if !req.label.is_empty() {
+            self.set_labels(&[bip329::Label::new(
+                bip329::LabelType::Addr,
+                req.address.to_string(),
+                req.label.clone(),
+            )]);
+        }
+        self.send_addresses();
+        self.send_payment_requests();
+    }
+
+    fn send_labels(&self) {
+        match labels::list_labels(self) {
+            Ok(labels) => self
+                .wallet_updates
+                .send(WalletResponse::Labels(labels))
+                .unwrap(),
+            Err(e) => self.handle_debug(format!("loading labels failed: {e}")),
+        }
+    }
+
+    fn set_labels(&mut self, new: &[bip329::Label]) {
+        if let Err(e) = labels::set_labels(self, new) {
+            self.handle_debug(format!("saving labels failed: {e}"));
+        }
+        self.send_labels();
+    }
+
+    fn import_labels(&mut self, path: PathBuf) {
+        let imported = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| bip329::import(&data).map_err(|e| e.to_string()));
+        match imported {
+            Ok(new) => {
+                self.set_labels(&new);
+                self.handle_debug(format!("Imported {} labels", new.len()));
+            }
+            Err(e) => self.handle_debug(format!("label import failed: {e}")),
+        }
+    }
+
+    fn export_labels(&self, path: PathBuf) {
+        let res = labels::list_labels(self)
+            .map_err(|e| e.to_string())
+            .and_then(|labels| {
+                std::fs::write(&path, bip329::export(&labels))
+                    .map(|_| labels.len())
+                    .map_err(|e| e.to_string())
+            });
+        match res {
+            Ok(n) => self.handle_debug(format!("Exported {n} labels to {}", path.display())),
+            Err(e) => self.handle_debug(format!("label export failed: {e}")),
+        }
+    }
+
+    fn backup(&self, path: PathBuf, passphrase: String) {
+        let res = registry::load(&self.settings.wallet_db).and_then(|wallets| {
+            let entry = wallets
+                .iter()
+                .find(|w| w.name == self.name)
+                .ok_or("wallet is not registered")?;
+            bundle::create(self.db(), entry, &self.settings, &passphrase, &path)
+        });
+        match res {
+            Ok(()) => self.handle_debug(format!("Backup written to {}", path.display())),
+            Err(e) => self.handle_debug(format!("backup failed: {e}")),
+        }
+    }
+
+    /// Push balance, utxos, history and payment request status to the UI after the wallet changes
+    fn send_wallet_state(&self) {
+        self.get_balance();
         self.wallet_updates
             .send(WalletResponse::UtxoList(self.get_utxos()))
             .unwrap();
+        self.wallet_updates
+            .send(WalletResponse::TxHistory(self.get_history()))
+            .unwrap();
+        self.send_payment_requests();
+    }
+
+    pub fn monitor_wallet(&mut self) {
+        if let Err(e) = receive::restore_marks(self) {
+            self.handle_debug(format!("restoring address marks failed: {e}"));
+        }
+        self.send_addresses();
+        self.send_labels();
+        self.send_wallet_state();
+
+        self.wallet_updates
+            .send(WalletResponse::WalletConfig(self.wallet_settings.clone()))
+            .unwrap();
+        self.send_policy();
+        self.send_drafts();
 
         // tell ui to go to loaded wallet display
         self.wallet_updates
@@ -79,33 +265,110 @@ impl WalletBackground {
                     WalletRequest::Debug(s) => self.handle_debug(s),
                     WalletRequest::Sync => self.handle_sync(),
                     WalletRequest::AppConfig(c) => self.handle_config(c),
-                    WalletRequest::SendTransaction(tx) => self.send_tx(tx),
+                    WalletRequest::WalletConfig(c) => self.handle_wallet_config(c),
+                    WalletRequest::Rescan(stop_gap) => self.handle_rescan(stop_gap),
+                    WalletRequest::EstimateFee(target) => self.estimate_fee(target),
+                    WalletRequest::SendTransaction(tx, label) => self.send_tx(tx, label),
                     WalletRequest::CreateTransaction(tx) => self.create_tx(tx),
-                    WalletRequest::MarkUsed(addr) => self.mark_used(addr),
+                    WalletRequest::DiscardDraft(txid) => {
+                        if let Err(e) = drafts::remove_draft(self, txid) {
+                            self.handle_debug(format!("discarding draft failed: {e}"));
+                        }
+                        self.send_drafts();
+                    }
+                    WalletRequest::MarkUsed(addr) => self.mark_used(addr, None),
+                    WalletRequest::UnmarkUsed(addr) => self.unmark_used(addr),
+                    WalletRequest::Reserve(addr, name) => self.mark_used(addr, Some(name)),
+                    WalletRequest::SavePaymentRequest(req) => self.save_payment_request(req),
+                    WalletRequest::SetLabel(label) => self.set_labels(&[label]),
+                    WalletRequest::ImportLabels(path) => self.import_labels(path),
+                    WalletRequest::ExportLabels(path) => self.export_labels(path),
+                    WalletRequest::Backup(path, passphrase) => self.backup(path, passphrase),
+                    WalletRequest::Export => self
+                        .wallet_updates
+                        .send(WalletResponse::Export(WalletExport::new(
+                            &self.wallet,
+                            &self.name,
+                        )))
+                        .unwrap(),
+                    WalletRequest::AddressPage(kc, page) => {
+                        let (rows, more) = addresses::list_page(self, kc, page);
+                        self.wallet_updates
+                            .send(WalletResponse::AddressPage(rows, more))
+                            .unwrap()
+                    }
+                    WalletRequest::SearchAddress(query) => self
+                        .wallet_updates
+                        .send(WalletResponse::AddressSearch(addresses::search(
+                            self, &query,
+                        )))
+                        .unwrap(),
                     WalletRequest::Close => break,
                 };
             };
         }
-        println!("Closing wallet");
+        println!("Closing wallet {}", self.name);
     }
 
-    fn send_tx(&mut self, mut psbt: Psbt) {
+    /// Insert a broadcast transaction as pending so the spent coins are not selected again
+    /// before the next sync
+    fn apply_pending(&mut self, tx: &Transaction) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.wallet.apply_unconfirmed_txs([(tx, now)]);
+        self.persist();
+    }
+
+    fn send_tx(&mut self, mut psbt: Psbt, label: String) {
         // fn send_tx(&mut self, tx: Transaction) {
+        let draft = psbt.unsigned_tx.compute_txid();
         let sigops = SignOptions::default();
         let msg = if let Err(e) = self.wallet.finalize_psbt(&mut psbt, sigops) {
             e.to_string()
         } else {
             let tx = psbt.extract_tx().unwrap();
-            let res = bdk_utils::broadcast_tx(&tx, &self.electrum_url);
+            let res = self.with_chain(|_, source| bdk_utils::broadcast_tx(&tx, source));
             match res {
-                Ok(txid) => format!("txid: {txid:?}"),
-                Err(e) => e,
+                Ok(txid) => {
+                    self.apply_pending(&tx);
+                    if let Err(e) = drafts::remove_draft(self, draft) {
+                        self.handle_debug(format!("removing sent draft failed: {e}"));
+                    }
+                    self.send_drafts();
+                    if !label.is_empty() {
+                        self.set_labels(&[bip329::Label::new(
+                            bip329::LabelType::Tx,
+                            txid.to_string(),
+                            label,
+                        )]);
+                    }
+                    self.send_wallet_state();
+                    format!("txid: {txid:?}")
+                }
+                Err(e) => e.to_string(),
             }
         };
         self.wallet_updates
             .send(WalletResponse::Debug(msg))
             .unwrap();
     }
+
+    /// Push the spending policy of each keychain to the UI
+    fn send_policy(&self) {
+        let mut trees = Vec::new();
+        for kc in [KeychainKind::External, KeychainKind::Internal] {
+            match self.wallet.policies(kc) {
+                Ok(policy) => trees.extend(policy.map(|p| (kc, PolicyNode::new(&p)))),
+                Err(e) => self.handle_debug(format!("reading spending policy failed: {e}")),
+            }
+        }
+        self.wallet_updates
+            .send(WalletResponse::Policies(trees))
+            .unwrap();
+    }
+
     fn create_tx(&mut self, tx: TxParts) {
         self.wallet_updates
             .send(WalletResponse::Debug("Starting tx creation".into()))
@@ -113,9 +376,32 @@ impl WalletBackground {
 
         // let wpkh: WPubkeyHash = WPubkeyHash::from_str(&tx.addr).unwrap();
         // let script_pubkey = ScriptBuf::new_p2wpkh(&wpkh);
+        let paths: Vec<_> = if tx.policy_path.is_empty() {
+            Vec::new()
+        } else {
+            [KeychainKind::External, KeychainKind::Internal]
+                .into_iter()
+                .filter_map(|kc| match self.wallet.policies(kc) {
+                    Ok(Some(policy)) => Some((policy::policy_path(&policy, &tx.policy_path), kc)),
+                    _ => None,
+                })
+                .collect()
+        };
+        // outputs labeled BIP329 `spendable: false` stay out of coin selection
+        let unspendable: Vec<OutPoint> = match labels::list_labels(self) {
+            Ok(labels) => labels
+                .iter()
+                .filter(|l| l.kind == bip329::LabelType::Output && l.spendable == Some(false))
+                .filter_map(|l| l.reference.parse().ok())
+                .collect(),
+            Err(e) => {
+                self.handle_debug(format!("loading labels failed: {e}"));
+                Vec::new()
+            }
+        };
         let mut builder = self.wallet.build_tx();
         builder
-            .fee_rate(FeeRate::from_sat_per_vb(5_u64).unwrap())
+            .fee_rate(tx.fee_rate)
             .add_recipient(tx.addr.script_pubkey(), Amount::from_sat(tx.sats_amount));
 
         if let Some(selected) = tx.utxos {
@@ -123,28 +409,68 @@ impl WalletBackground {
                 builder.add_utxo(utxo.outpoint).unwrap();
             })
         }
+        for (path, kc) in paths {
+            builder.policy_path(path, kc);
+        }
+        builder.unspendable(unspendable);
 
         let built = builder.finish();
 
         // self.persist();
 
         if let Ok(res) = built {
+            // keep the change address the draft pays to from being handed out again
+            self.persist();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            if let Err(e) = drafts::save_draft(self, &res, now) {
+                self.handle_debug(format!("saving draft failed: {e}"));
+            }
+            self.send_drafts();
             self.wallet_updates
                 .send(WalletResponse::NewPsbt(res))
                 .unwrap();
             self.wallet_updates
                 .send(WalletResponse::Debug("TX Created".into()))
                 .unwrap();
-        } else {
+        } else if let Err(e) = built {
             self.wallet_updates
-                .send(WalletResponse::Debug("tx creation failed".into()))
+                .send(WalletResponse::Debug(format!("tx creation failed: {e}")))
                 .unwrap();
         }
     }
 
+    /// The loaded wallet keeps its database, new chain settings apply from the next request.
+    /// The backend is remembered for this wallet.
     fn handle_config(&mut self, c: Settings) {
-        self.db = c.wallet_db;
-        self.electrum_url = c.electrum_url;
+        if c.backend != self.settings.backend {
+            if let Err(e) = registry::set_backend(&c.wallet_db, &self.name, c.backend) {
+                self.handle_debug(format!("saving the wallet's backend failed: {e}"));
+            }
+        }
+        self.settings = c;
+        self.chain = None;
+    }
+
+    fn estimate_fee(&mut self, target: usize) {
+        let res = self.with_chain(|_, source| source.estimate_fee(target));
+        match res {
+            Ok(rate) => self
+                .wallet_updates
+                .send(WalletResponse::FeeEstimate(rate))
+                .unwrap(),
+            Err(e) => self.handle_debug(format!("fee estimation failed: {e}")),
+        }
+    }
+
+    fn handle_wallet_config(&mut self, c: WalletSettings) {
+        match bdk_utils::save_wallet_settings(&self.db, &c) {
+            Ok(_) => self.handle_debug("Scan settings saved".into()),
+            Err(e) => self.handle_debug(format!("saving scan settings failed: {e}")),
+        }
+        self.wallet_settings = c;
     }
 
     fn get_balance(&self) {
@@ -162,18 +488,22 @@ impl WalletBackground {
 
         // request new state
         let cps: Vec<_> = self.wallet.checkpoints().collect();
-        let bal: Balance = if cps.len() > 1 {
-            // short synce
-            bdk_utils::cp_sync(&self.db, &self.name, &mut self.wallet, &self.electrum_url)
-        } else {
-            // full synce
-            bdk_utils::full_scan(&self.db, &self.name, &mut self.wallet, &self.electrum_url)
-        };
+        let res = self.with_chain(|bg, source| {
+            if cps.len() > 1 {
+                // short synce
+                bdk_utils::cp_sync(&mut bg.db, &mut bg.wallet, source, &bg.wallet_settings)
+            } else {
+                // full synce
+                bdk_utils::full_scan(&mut bg.db, &mut bg.wallet, source, &bg.wallet_settings)
+            }
+        });
+        if let Err(e) = res {
+            self.handle_debug(format!("Sync failed: {e}"));
+            return;
+        }
 
-        // send balance to UI thread
-        self.wallet_updates
-            .send(WalletResponse::Sync(bal))
-            .expect("main thread died");
+        // send balance and anything else the sync changed to UI thread
+        self.send_wallet_state();
 
         // log complete
         self.wallet_updates
@@ -181,9 +511,183 @@ impl WalletBackground {
             .unwrap();
     }
 
+    fn handle_rescan(&mut self, stop_gap: usize) {
+        self.handle_debug(format!("Starting full rescan with stop gap {stop_gap}"));
+        let res = self.with_chain(|bg, source| {
+            let settings = WalletSettings {
+                stop_gap,
+                ..bg.wallet_settings.clone()
+            };
+            bdk_utils::full_scan(&mut bg.db, &mut bg.wallet, source, &settings)
+        });
+        if let Err(e) = res {
+            self.handle_debug(format!("Rescan failed: {e}"));
+            return;
+        }
+        self.send_addresses();
+        self.send_wallet_state();
+        self.handle_debug("Rescan complete".into());
+    }
+
+    /// Run `op` against the chain source, connecting first if there is none yet,
+    /// and report the connection state to the UI
+    fn with_chain<T>(
+        &mut self,
+        mut op: impl FnMut(&mut Self, &mut dyn ChainSource) -> Result<T, ChainError>,
+    ) -> Result<T, ChainError> {
+        let reused = self.chain.is_some();
+        let (mut res, mut status) = self.run_chain(&mut op);
+        if reused && res.as_ref().is_err_and(ChainError::is_transport) {
+            // the kept connection may have gone stale, try once more on a fresh one
+            (res, status) = self.run_chain(&mut op);
+        }
+        for change in chain::take_cert_changes() {
+            self.wallet_updates
+                .send(WalletResponse::CertificateChanged(change))
+                .unwrap();
+        }
+        self.wallet_updates
+            .send(WalletResponse::Connection(status))
+            .unwrap();
+        res
+    }
+
+    fn run_chain<T>(
+        &mut self,
+        op: &mut impl FnMut(&mut Self, &mut dyn ChainSource) -> Result<T, ChainError>,
+    ) -> (Result<T, ChainError>, ConnectionStatus) {
+        let source = match self.chain.take() {
+            Some(source) => Ok(source),
+            None => chain::connect(&self.settings, self.wallet.network()),
+        };
+        match source {
+            Ok(mut source) => {
+                let res = op(self, source.as_mut());
+                // a rejected request still came from a server that is up
+                let status = match (&res, source.server()) {
+                    (Err(e), _) if e.is_transport() => ConnectionStatus::Offline(e.to_string()),
+                    (_, Some(server)) => ConnectionStatus::Connected(server),
+                    (_, None) => ConnectionStatus::Unknown,
+                };
+                // reconnect on the next request rather than reuse a connection that broke,
+                // a rejected request leaves it usable
+                if !res.as_ref().is_err_and(ChainError::is_transport) {
+                    self.chain = Some(source);
+                }
+                (res, status)
+            }
+            Err(e) => (
+                Err(ChainError::Transport(e.clone())),
+                ConnectionStatus::Offline(e),
+            ),
+        }
+    }
+
     fn handle_debug(&self, s: String) {
         self.wallet_updates
             .send(WalletResponse::Debug(s))
             .expect("Main thread stopped")
     }
 }
+
+/// The `tests/tw` fixture in `dir`, copied there the first time, opened the way the app
+/// loads a wallet. Opening it again after dropping the first shows what was stored.
+#[cfg(test)]
+fn open_fixture(dir: &std::path::Path) -> (WalletBackground, Receiver<WalletResponse>) {
+    if !dir.join("tw").exists() {
+        std::fs::copy("tests/tw", dir.join("tw")).unwrap();
+        std::fs::copy("tests/tw_keys", dir.join("tw_keys")).unwrap();
+    }
+    let d = dir.to_str().unwrap();
+    let wallet = crate::load_test_wallet(d, "tw").unwrap();
+    let settings = serde_json::from_value(serde_json::json!({
+        "electrum_servers": [],
+        "wallet_db": d,
+    }))
+    .unwrap();
+    let (_, req) = flume::unbounded();
+    let (resp, updates) = flume::unbounded();
+    let bg = WalletBackground::new(wallet, "tw".into(), dir.join("tw"), req, resp, settings);
+    (bg, updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::bitcoin::{absolute, hashes::Hash, transaction, Amount, TxIn, TxOut, Txid};
+
+    use super::*;
+
+    #[test]
+    fn sent_tx_stays_pending_after_reload() {
+        let tmp = crate::test_dir();
+        let (mut bg, _updates) = open_fixture(tmp.path());
+        let ours = bg
+            .wallet
+            .peek_address(KeychainKind::External, 0)
+            .script_pubkey();
+        let funding = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            // any outpoint but the null one, which would make it a coinbase
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: ours.clone(),
+            }],
+        };
+        bg.apply_pending(&funding);
+
+        let mut builder = bg.wallet.build_tx();
+        builder.add_recipient(ours, Amount::from_sat(10_000));
+        let mut psbt = builder.finish().unwrap();
+        assert!(bg.wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        let tx = psbt.extract_tx().unwrap();
+        let fee = bg.wallet.calculate_fee(&tx).unwrap();
+        bg.apply_pending(&tx);
+        drop(bg);
+
+        let (bg, _updates) = open_fixture(tmp.path());
+        let sent = bg
+            .get_history()
+            .into_iter()
+            .find(|t| t.txid == tx.compute_txid())
+            .unwrap();
+        assert_eq!(sent.height, None);
+        // the funding coin is spent, so it can't be selected again
+        let spent = OutPoint::new(funding.compute_txid(), 0);
+        assert!(bg.get_utxos().iter().all(|u| u.outpoint != spent));
+        assert_eq!(bg.wallet.balance().total(), Amount::from_sat(100_000) - fee);
+    }
+
+    #[test]
+    fn one_connection_persists_across_writes() {
+        let tmp = crate::test_dir();
+        let (mut bg, _updates) = open_fixture(tmp.path());
+        let start = bg
+            .wallet
+            .derivation_index(KeychainKind::External)
+            .map_or(0, |i| i + 1);
+        // several writes through the same connection
+        for _ in 0..3 {
+            bg.wallet.reveal_next_address(KeychainKind::External);
+            bg.persist();
+        }
+        let config = WalletSettings {
+            stop_gap: 50,
+            batch_size: 7,
+            lookahead: 40,
+        };
+        bg.handle_wallet_config(config.clone());
+        drop(bg);
+
+        let (bg, _updates) = open_fixture(tmp.path());
+        assert_eq!(
+            bg.wallet.derivation_index(KeychainKind::External),
+            Some(start + 2)
+        );
+        assert_eq!(bg.wallet_settings, config);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::HashMap, str::FromStr};
+
+use bdk_wallet::{
+    bitcoin::{Address, Amount},
+    miniscript::ForEachKey,
+    KeychainKind,
+};
+
+use crate::messages::AddressDetails;
+
+use super::WalletBackground;
+
+pub const PAGE_SIZE: u32 = 20;
+/// How far past the revealed addresses a search derives, and the list pages, before giving up
+const SEARCH_LIMIT: u32 = 1000;
+
+/// First index past the revealed addresses of `keychain` that is never derived
+fn index_limit(wallet: &WalletBackground, keychain: KeychainKind) -> u32 {
+    wallet
+        .wallet
+        .derivation_index(keychain)
+        .map_or(0, |last| last + 1)
+        .saturating_add(SEARCH_LIMIT)
+        .min(0x8000_0000)
+}
+
+/// Full derivation path(s) of the keys at `index`, prefixed with their master fingerprint
+fn derivation_path(wallet: &WalletBackground, keychain: KeychainKind, index: u32) -> String {
+    let desc = wallet.wallet.public_descriptor(keychain);
+    let Ok(desc) = desc.at_derivation_index(index) else {
+        return String::new();
+    };
+    let mut paths = Vec::new();
+    desc.for_each_key(|key| {
+        if let Some(path) = key.full_derivation_path() {
+            paths.push(format!("[{}]m/{}", key.master_fingerprint(), path));
+        }
+        true
+    });
+    paths.join(", ")
+}
+
+fn details(
+    wallet: &WalletBackground,
+    keychain: KeychainKind,
+    index: u32,
+    outputs: &HashMap<(KeychainKind, u32), (usize, Amount)>,
+) -> AddressDetails {
+    let (times_received, balance) = outputs
+        .get(&(keychain, index))
+        .copied()
+        .unwrap_or((0, Amount::ZERO));
+    AddressDetails {
+        keychain,
+        index,
+        path: derivation_path(wallet, keychain, index),
+        address: wallet.wallet.peek_address(keychain, index).address,
+        used: wallet.wallet.spk_index().is_used(keychain, index),
+        revealed: wallet
+            .wallet
+            .derivation_index(keychain)
+            .is_some_and(|last| index <= last),
+        times_received,
+        balance,
+    }
+}
+
+/// Number of outputs received and unspent balance per derivation index
+fn output_summary(wallet: &WalletBackground) -> HashMap<(KeychainKind, u32), (usize, Amount)> {
+    let mut outputs = HashMap::new();
+    wallet.wallet.list_output().for_each(|o| {
+        let entry = outputs
+            .entry((o.keychain, o.derivation_index))
+            .or_insert((0, Amount::ZERO));
+        entry.0 += 1;
+        if !o.is_spent {
+            entry.1 += o.txout.value;
+        }
+    });
+    outputs
+}
+
+/// One page of addresses and whether there is a page after it
+pub fn list_page(
+    wallet: &WalletBackground,
+    keychain: KeychainKind,
+    page: u32,
+) -> (Vec<AddressDetails>, bool) {
+    let outputs = output_summary(wallet);
+    let limit = index_limit(wallet, keychain);
+    let start = page.saturating_mul(PAGE_SIZE).min(limit);
+    let end = start.saturating_add(PAGE_SIZE).min(limit);
+    let rows = (start..end)
+        .map(|index| details(wallet, keychain, index, &outputs))
+        .collect();
+    (rows, end < limit)
+}
+
+/// Look up an arbitrary address, returning where it sits in the wallet if it is ours
+pub fn search(wallet: &WalletBackground, query: &str) -> Result<Option<AddressDetails>, String> {
+    let address = Address::from_str(query.trim())
+        .map_err(|e| e.to_string())?
+        .require_network(wallet.wallet.network())
+        .map_err(|_| "Address is for a different network".to_string())?;
+    let spk = address.script_pubkey();
+
+    let found = wallet.wallet.derivation_of_spk(spk.clone()).or_else(|| {
+        // not in the revealed or lookahead range, derive further to be sure
+        [KeychainKind::External, KeychainKind::Internal]
+            .into_iter()
+            .find_map(|kc| {
+                let start = wallet
+                    .wallet
+                    .derivation_index(kc)
+                    .map_or(0, |last| last + 1);
+                (start..index_limit(wallet, kc))
+                    .find(|i| wallet.wallet.peek_address(kc, *i).script_pubkey() == spk)
+                    .map(|i| (kc, i))
+            })
+    });
+
+    Ok(found.map(|(kc, index)| details(wallet, kc, index, &output_summary(wallet))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_and_search_after_reload() {
+        let tmp = crate::test_dir();
+        let (mut bg, _updates) = super::super::open_fixture(tmp.path());
+        let revealed = bg.wallet.derivation_index(KeychainKind::External);
+        let last = revealed.map_or(0, |last| last + 1) + 4;
+        let _ = bg
+            .wallet
+            .reveal_addresses_to(KeychainKind::External, last)
+            .count();
+        bg.persist();
+        drop(bg);
+
+        let (bg, _updates) = super::super::open_fixture(tmp.path());
+        assert_eq!(
+            bg.wallet.derivation_index(KeychainKind::External),
+            Some(last)
+        );
+        let (rows, more) = list_page(&bg, KeychainKind::External, 0);
+        assert_eq!(rows.len(), PAGE_SIZE as usize);
+        assert!(more);
+        assert!(rows.iter().all(|r| r.revealed == (r.index <= last)));
+
+        // the list stops where searching does
+        let limit = last + 1 + SEARCH_LIMIT;
+        let (rows, more) = list_page(&bg, KeychainKind::External, limit / PAGE_SIZE);
+        assert_eq!(rows.last().map(|r| r.index), Some(limit - 1));
+        assert!(!more);
+
+        // beyond the revealed and lookahead range is still found
+        let far = bg.wallet.peek_address(KeychainKind::Internal, 500).address;
+        let found = search(&bg, &far.to_string()).unwrap().unwrap();
+        assert_eq!((found.keychain, found.index), (KeychainKind::Internal, 500));
+        assert!(!found.revealed);
+
+        let mainnet = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        assert!(search(&bg, mainnet).is_err());
+        assert!(search(&bg, "not an address").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use bdk_wallet::{
+    bitcoin::{Psbt, Txid},
+    rusqlite::{params, Connection},
+};
+
+use crate::messages::Draft;
+
+use super::WalletBackground;
+
+const TABLE: &str = "seashell_drafts";
+
+fn init_table(db: &Connection) -> bdk_wallet::rusqlite::Result<()> {
+    db.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE} (
+                txid TEXT PRIMARY KEY,
+                psbt BLOB NOT NULL,
+                created_at INTEGER NOT NULL
+            )"
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Keep an unsent transaction, keyed by its unsigned txid so it is found again after signing
+pub fn save_draft(
+    wallet: &WalletBackground,
+    psbt: &Psbt,
+    created_at: u64,
+) -> bdk_wallet::rusqlite::Result<()> {
+    let db = wallet.db();
+    init_table(db)?;
+    db.execute(
+        &format!("INSERT OR REPLACE INTO {TABLE} (txid, psbt, created_at) VALUES (?1, ?2, ?3)"),
+        params![
+            psbt.unsigned_tx.compute_txid().to_string(),
+            psbt.serialize(),
+            created_at
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn remove_draft(wallet: &WalletBackground, txid: Txid) -> bdk_wallet::rusqlite::Result<()> {
+    let db = wallet.db();
+    init_table(db)?;
+    db.execute(
+        &format!("DELETE FROM {TABLE} WHERE txid = ?1"),
+        params![txid.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Stored drafts, newest first. Rows that no longer parse are skipped.
+pub fn list_drafts(wallet: &WalletBackground) -> bdk_wallet::rusqlite::Result<Vec<Draft>> {
+    let db = wallet.db();
+    init_table(db)?;
+    let mut stmt = db.prepare(&format!(
+        "SELECT psbt, created_at FROM {TABLE} ORDER BY created_at DESC"
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        let psbt: Vec<u8> = row.get(0)?;
+        Ok((psbt, row.get(1)?))
+    })?;
+    let mut drafts = Vec::new();
+    for row in rows {
+        let (psbt, created_at) = row?;
+        if let Ok(psbt) = Psbt::deserialize(&psbt) {
+            drafts.push(Draft { psbt, created_at });
+        }
+    }
+    Ok(drafts)
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk_wallet::bitcoin::{
+        absolute, hashes::Hash, transaction, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut,
+    };
+
+    use super::*;
+
+    fn draft(n: u8) -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn drafts_round_trip() {
+        let tmp = crate::test_dir();
+        let (bg, _updates) = super::super::open_fixture(tmp.path());
+        let (a, b, c) = (draft(1), draft(2), draft(3));
+        save_draft(&bg, &a, 100).unwrap();
+        save_draft(&bg, &b, 300).unwrap();
+        save_draft(&bg, &c, 200).unwrap();
+        remove_draft(&bg, c.unsigned_tx.compute_txid()).unwrap();
+        // saving again replaces rather than duplicates
+        save_draft(&bg, &a, 400).unwrap();
+        drop(bg);
+
+        let (bg, _updates) = super::super::open_fixture(tmp.path());
+        let drafts = list_drafts(&bg).unwrap();
+        let stored: Vec<_> = drafts.iter().map(|d| (&d.psbt, d.created_at)).collect();
+        assert_eq!(stored, vec![(&a, 400), (&b, 300)]);
+    }
+}
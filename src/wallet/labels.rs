@@ -0,0 +1,121 @@
+use bdk_wallet::rusqlite::{params, types::Type, Connection, Error::FromSqlConversionFailure};
+
+use crate::bip329::Label;
+
+use super::WalletBackground;
+
+const TABLE: &str = "seashell_labels";
+
+fn init_table(db: &Connection) -> bdk_wallet::rusqlite::Result<()> {
+    db.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE} (
+                type TEXT NOT NULL,
+                ref TEXT NOT NULL,
+                label TEXT NOT NULL,
+                origin TEXT,
+                spendable INTEGER,
+                PRIMARY KEY (type, ref)
+            )"
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Insert or replace labels. A label with no text and no spendable flag is removed.
+pub fn set_labels(
+    wallet: &mut WalletBackground,
+    labels: &[Label],
+) -> bdk_wallet::rusqlite::Result<()> {
+    init_table(&wallet.db)?;
+    let tx = wallet.db.transaction()?;
+    for l in labels {
+        if l.label.is_empty() && l.spendable.is_none() {
+            tx.execute(
+                &format!("DELETE FROM {TABLE} WHERE type = ?1 AND ref = ?2"),
+                params![l.kind.to_string(), l.reference],
+            )?;
+        } else {
+            tx.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {TABLE} (type, ref, label, origin, spendable)
+                    VALUES (?1, ?2, ?3, ?4, ?5)"
+                ),
+                params![
+                    l.kind.to_string(),
+                    l.reference,
+                    l.label,
+                    l.origin,
+                    l.spendable
+                ],
+            )?;
+        }
+    }
+    tx.commit()
+}
+
+pub fn list_labels(wallet: &WalletBackground) -> bdk_wallet::rusqlite::Result<Vec<Label>> {
+    let db = wallet.db();
+    init_table(db)?;
+    let mut stmt = db.prepare(&format!(
+        "SELECT type, ref, label, origin, spendable FROM {TABLE} ORDER BY type, ref"
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        let kind: String = row.get(0)?;
+        Ok(Label {
+            kind: kind
+                .parse()
+                .map_err(|e: String| FromSqlConversionFailure(0, Type::Text, e.into()))?,
+            reference: row.get(1)?,
+            label: row.get(2)?,
+            origin: row.get(3)?,
+            spendable: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bip329::LabelType;
+
+    #[test]
+    fn labels_round_trip() {
+        let tmp = crate::test_dir();
+        let (mut bg, _updates) = super::super::open_fixture(tmp.path());
+        let txid = "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd";
+        let outpoint = format!("{txid}:0");
+        let mut frozen = Label::new(LabelType::Output, outpoint.clone(), "cold".into());
+        frozen.spendable = Some(false);
+        set_labels(
+            &mut bg,
+            &[
+                Label::new(LabelType::Tx, txid.into(), "rent".into()),
+                Label::new(LabelType::Addr, "tb1qexample".into(), "shop".into()),
+                frozen.clone(),
+            ],
+        )
+        .unwrap();
+        // an empty label removes it, a new text replaces it
+        set_labels(
+            &mut bg,
+            &[
+                Label::new(LabelType::Addr, "tb1qexample".into(), String::new()),
+                Label::new(LabelType::Tx, txid.into(), "rent, march".into()),
+            ],
+        )
+        .unwrap();
+        drop(bg);
+
+        let (bg, _updates) = super::super::open_fixture(tmp.path());
+        assert_eq!(
+            list_labels(&bg).unwrap(),
+            vec![
+                frozen,
+                Label::new(LabelType::Tx, txid.into(), "rent, march".into()),
+            ]
+        );
+    }
+}
@@ -1,6 +1,26 @@
-use bdk_wallet::KeychainKind;
+use bdk_wallet::{
+    rusqlite::{params, Connection},
+    KeychainKind,
+};
 
-use super::WalletBackground;
+use crate::messages::MarkedAddress;
+
+use super::{requests, WalletBackground};
+
+const TABLE: &str = "seashell_address_marks";
+
+fn init_table(db: &Connection) -> bdk_wallet::rusqlite::Result<()> {
+    db.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE} (
+                keychain_index INTEGER PRIMARY KEY,
+                reserved_for TEXT
+            )"
+        ),
+        [],
+    )?;
+    Ok(())
+}
 
 pub fn get_unused_addrs(wallet: &mut WalletBackground) -> Vec<bdk_wallet::AddressInfo> {
     let mut addrs: Vec<bdk_wallet::AddressInfo> = Vec::new();
@@ -28,11 +48,95 @@ pub fn get_unused_addrs(wallet: &mut WalletBackground) -> Vec<bdk_wallet::Addres
     addrs
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     #[test]
-//     fn get_addrs() {
-//         todo!()
-//     }
-// }
+/// Mark an external address as used, optionally reserving it for a named counterparty.
+/// bdk only keeps these marks in memory so they are also stored in the wallet database.
+pub fn mark_used(
+    wallet: &mut WalletBackground,
+    index: u32,
+    reserved_for: Option<String>,
+) -> bdk_wallet::rusqlite::Result<()> {
+    let db = wallet.db();
+    init_table(db)?;
+    db.execute(
+        &format!("INSERT OR REPLACE INTO {TABLE} (keychain_index, reserved_for) VALUES (?1, ?2)"),
+        params![index, reserved_for],
+    )?;
+    wallet.wallet.mark_used(KeychainKind::External, index);
+    Ok(())
+}
+
+pub fn unmark_used(wallet: &mut WalletBackground, index: u32) -> bdk_wallet::rusqlite::Result<()> {
+    let db = wallet.db();
+    init_table(db)?;
+    db.execute(
+        &format!("DELETE FROM {TABLE} WHERE keychain_index = ?1"),
+        params![index],
+    )?;
+    wallet.wallet.unmark_used(KeychainKind::External, index);
+    Ok(())
+}
+
+pub fn list_marked(wallet: &WalletBackground) -> bdk_wallet::rusqlite::Result<Vec<MarkedAddress>> {
+    let db = wallet.db();
+    init_table(db)?;
+    let mut stmt = db.prepare(&format!(
+        "SELECT keychain_index, reserved_for FROM {TABLE} ORDER BY keychain_index"
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        let index: u32 = row.get(0)?;
+        Ok(MarkedAddress {
+            address: wallet.wallet.peek_address(KeychainKind::External, index),
+            reserved_for: row.get(1)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Re-apply stored marks and requested addresses after loading the wallet
+pub fn restore_marks(wallet: &mut WalletBackground) -> bdk_wallet::rusqlite::Result<()> {
+    let mut indexes: Vec<u32> = list_marked(wallet)?
+        .into_iter()
+        .map(|m| m.address.index)
+        .collect();
+    indexes.extend(
+        requests::list_requests(wallet)?
+            .iter()
+            .map(|(r, _)| r.index),
+    );
+    indexes.into_iter().for_each(|index| {
+        wallet.wallet.mark_used(KeychainKind::External, index);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_round_trip() {
+        let tmp = crate::test_dir();
+        let (mut bg, _updates) = super::super::open_fixture(tmp.path());
+        let unused = get_unused_addrs(&mut bg);
+        let (first, second) = (unused[0].index, unused[1].index);
+        mark_used(&mut bg, first, None).unwrap();
+        mark_used(&mut bg, second, Some("alice".into())).unwrap();
+        unmark_used(&mut bg, first).unwrap();
+        drop(bg);
+
+        let (mut bg, _updates) = super::super::open_fixture(tmp.path());
+        let marked = list_marked(&bg).unwrap();
+        assert_eq!(marked.len(), 1);
+        assert_eq!(marked[0].address.index, second);
+        assert_eq!(marked[0].reserved_for.as_deref(), Some("alice"));
+        // bdk forgets the marks on reload until they are restored
+        let used = |bg: &WalletBackground, index| {
+            bg.wallet.spk_index().is_used(KeychainKind::External, index)
+        };
+        assert!(!used(&bg, second));
+        restore_marks(&mut bg).unwrap();
+        assert!(used(&bg, second));
+        assert!(!used(&bg, first));
+        assert!(get_unused_addrs(&mut bg).iter().all(|a| a.index != second));
+    }
+}
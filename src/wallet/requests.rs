@@ -0,0 +1,149 @@
+use bdk_wallet::{
+    bitcoin::Amount,
+    rusqlite::{params, Connection},
+    KeychainKind,
+};
+
+use crate::messages::{PaymentRequest, PaymentStatus};
+
+use super::WalletBackground;
+
+const TABLE: &str = "seashell_payment_requests";
+
+fn init_table(db: &Connection) -> bdk_wallet::rusqlite::Result<()> {
+    db.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {TABLE} (
+                keychain_index INTEGER PRIMARY KEY,
+                amount_sat INTEGER,
+                label TEXT NOT NULL,
+                message TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )"
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Store a request, updating any earlier request for the same address but keeping the
+/// time it was first made
+pub fn save_request(
+    wallet: &mut WalletBackground,
+    req: &PaymentRequest,
+) -> bdk_wallet::rusqlite::Result<()> {
+    let db = wallet.db();
+    init_table(db)?;
+    db.execute(
+        &format!(
+            "INSERT INTO {TABLE} (keychain_index, amount_sat, label, message, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(keychain_index) DO UPDATE SET
+                amount_sat = excluded.amount_sat,
+                label = excluded.label,
+                message = excluded.message"
+        ),
+        params![
+            req.index,
+            req.amount.map(|a| a.to_sat()),
+            req.label,
+            req.message,
+            req.created_at
+        ],
+    )?;
+    // a requested address should not be handed out again
+    wallet.wallet.mark_used(KeychainKind::External, req.index);
+    wallet.persist();
+    Ok(())
+}
+
+/// Load all stored requests along with how much has been received to each address
+pub fn list_requests(
+    wallet: &WalletBackground,
+) -> bdk_wallet::rusqlite::Result<Vec<(PaymentRequest, PaymentStatus)>> {
+    let db = wallet.db();
+    init_table(db)?;
+    let mut stmt = db.prepare(&format!(
+        "SELECT keychain_index, amount_sat, label, message, created_at FROM {TABLE}
+        ORDER BY created_at DESC"
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        let index: u32 = row.get(0)?;
+        let amount: Option<u64> = row.get(1)?;
+        Ok(PaymentRequest {
+            index,
+            address: wallet
+                .wallet
+                .peek_address(KeychainKind::External, index)
+                .address,
+            amount: amount.map(Amount::from_sat),
+            label: row.get(2)?,
+            message: row.get(3)?,
+            created_at: row.get(4)?,
+        })
+    })?;
+
+    rows.map(|req| {
+        let req = req?;
+        let status = request_status(wallet, &req);
+        Ok((req, status))
+    })
+    .collect()
+}
+
+fn request_status(wallet: &WalletBackground, req: &PaymentRequest) -> PaymentStatus {
+    let received: Amount = wallet
+        .wallet
+        .list_output()
+        .filter(|o| o.keychain == KeychainKind::External && o.derivation_index == req.index)
+        .map(|o| o.txout.value)
+        .sum();
+
+    if received == Amount::ZERO {
+        PaymentStatus::Unpaid
+    } else if req.amount.is_some_and(|amount| received < amount) {
+        PaymentStatus::PartiallyPaid(received)
+    } else {
+        PaymentStatus::Paid(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_round_trip() {
+        let tmp = crate::test_dir();
+        let (mut bg, _updates) = super::super::open_fixture(tmp.path());
+        let index = 3;
+        let mut req = PaymentRequest {
+            index,
+            address: bg
+                .wallet
+                .peek_address(KeychainKind::External, index)
+                .address,
+            amount: Some(Amount::from_sat(5000)),
+            label: "coffee".into(),
+            message: "thanks".into(),
+            created_at: 100,
+        };
+        save_request(&mut bg, &req).unwrap();
+        // editing keeps the time it was first made
+        req.label = "lunch".into();
+        req.amount = None;
+        req.created_at = 200;
+        save_request(&mut bg, &req).unwrap();
+        drop(bg);
+
+        let (bg, _updates) = super::super::open_fixture(tmp.path());
+        let reqs = list_requests(&bg).unwrap();
+        assert_eq!(reqs.len(), 1);
+        let (stored, status) = &reqs[0];
+        assert_eq!(stored.label, "lunch");
+        assert_eq!(stored.amount, None);
+        assert_eq!(stored.created_at, 100);
+        assert_eq!(stored.address, req.address);
+        assert_eq!(*status, PaymentStatus::Unpaid);
+    }
+}